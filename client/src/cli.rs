@@ -0,0 +1,305 @@
+use crate::config::Host;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand};
+use session_manager::config::config::DEFAULT_ESCAPE_CHAR;
+use session_manager::session_manager_plugin::escape_sequence::parse_escape_char;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
+
+/// Terminal client for AWS Systems Manager Session Manager.
+///
+/// Without a command, picks an instance to connect to.
+#[derive(Parser, Debug)]
+#[command(version, about)]
+pub struct Cli {
+    #[command(flatten)]
+    pub options: GlobalOptions,
+
+    #[command(subcommand)]
+    pub command: Option<Command>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    /// Starts a shell session on a target, or a session of the given document.
+    Connect {
+        /// Host alias of the configuration, or instance id, Name=Value, tag:Key=Value, IP address
+        /// or computer name of the target, comma separated to combine. Picked from the managed instances when not set.
+        target: Option<String>,
+    },
+
+    /// Forwards a local port to a port on the target, or to a host reachable from the target.
+    Forward {
+        /// Host alias of the configuration, or instance id, Name=Value, tag:Key=Value, IP address
+        /// or computer name of the target, comma separated to combine. Picked from the managed instances when not set.
+        target: Option<String>,
+
+        /// Port to connect to on the target, or on the remote host. The forwards of the host are
+        /// used when not set.
+        #[arg(long, short = 'p')]
+        port: Option<u16>,
+
+        /// Local port to listen on, a random port is used when not set.
+        #[arg(long, short = 'l', requires = "port")]
+        local_port: Option<u16>,
+
+        /// Host the target forwards to, such as an RDS endpoint.
+        #[arg(long, requires = "port")]
+        host: Option<String>,
+    },
+
+    /// Runs a command on a target, exiting with the exit code of the command.
+    Exec {
+        /// Host alias of the configuration, or instance id, Name=Value, tag:Key=Value, IP address
+        /// or computer name of the target, comma separated to combine.
+        target: String,
+
+        /// Runs the command in a terminal, for commands such as `sudo -iu app bash`.
+        #[arg(long, short = 't')]
+        interactive: bool,
+
+        /// Command to run.
+        #[arg(required = true, trailing_var_arg = true, allow_hyphen_values = true)]
+        command: Vec<String>,
+    },
+
+    /// Lists the managed instances.
+    List,
+
+    /// Runs as the AWS session-manager-plugin, taking the arguments the AWS CLI passes to it.
+    /// Also the mode when the executable is named session-manager-plugin.
+    Plugin {
+        #[arg(trailing_var_arg = true, allow_hyphen_values = true)]
+        args: Vec<String>,
+    },
+
+    /// Lists the sessions of the account.
+    Sessions {
+        /// Lists ended sessions instead of the active ones.
+        #[arg(long)]
+        history: bool,
+    },
+}
+
+/// Options shared by every command.
+#[derive(Args, Debug, Clone)]
+pub struct GlobalOptions {
+    /// Named profile of the AWS configuration, instances are listed across every profile given.
+    /// AWS_PROFILE is used when not set.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub profile: Vec<String>,
+
+    /// AWS region of the targets, instances are listed across every region given. AWS_REGION
+    /// is used when not set.
+    #[arg(long, global = true, value_delimiter = ',')]
+    pub region: Vec<String>,
+
+    /// Endpoint of the SSM service, such as a VPC endpoint.
+    #[arg(long, global = true)]
+    pub endpoint_url: Option<String>,
+
+    /// Session document to start instead of the default document of the command.
+    #[arg(long, global = true)]
+    pub document: Option<String>,
+
+    /// Document parameters as key=value pairs or a JSON object, may be repeated.
+    #[arg(long, global = true, value_name = "PARAMETERS")]
+    pub parameters: Vec<String>,
+
+    /// Lists the instances again instead of using the cached ones.
+    #[arg(long, global = true)]
+    pub refresh: bool,
+
+    /// Character starting escape sequences in shell sessions, such as ~. to disconnect, ^X for a
+    /// control character or none to disable them. Defaults to ~.
+    #[arg(long, short = 'e', global = true, value_name = "CHAR")]
+    pub escape_char: Option<EscapeChar>,
+
+    /// Reason for the session, shown in the session history.
+    #[arg(long, global = true)]
+    pub reason: Option<String>,
+
+    /// Level of the logs, RUST_LOG is used when not set.
+    #[arg(long, global = true, value_name = "LEVEL")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Configuration file, ~/.config/ssm-term/config.toml when not set.
+    #[arg(long, global = true, env = "SSM_TERM_CONFIG", value_name = "PATH")]
+    pub config: Option<PathBuf>,
+
+    /// Writes the logs to stderr instead of the log file in the state directory.
+    #[arg(long, global = true)]
+    pub log_stderr: bool,
+
+    /// Increases the log level, may be repeated.
+    #[arg(long, short = 'v', global = true, action = clap::ArgAction::Count)]
+    pub verbose: u8,
+}
+
+impl Command {
+    /// Returns the target given on the command line, if the command takes one.
+    pub fn target_mut(&mut self) -> Option<&mut String> {
+        match self {
+            Command::Connect { target } | Command::Forward { target, .. } => target.as_mut(),
+            Command::Exec { target, .. } => Some(target),
+            Command::List | Command::Plugin { .. } | Command::Sessions { .. } => None,
+        }
+    }
+}
+
+/// Character starting escape sequences in shell sessions, as given with `--escape-char`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum EscapeChar {
+    Char(u8),
+
+    /// Escape sequences are disabled, given as `none`.
+    Disabled,
+}
+
+impl EscapeChar {
+    /// Returns the character, None when escape sequences are disabled.
+    pub fn byte(self) -> Option<u8> {
+        match self {
+            EscapeChar::Char(c) => Some(c),
+            EscapeChar::Disabled => None,
+        }
+    }
+}
+
+impl FromStr for EscapeChar {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        Ok(match parse_escape_char(value)? {
+            Some(c) => EscapeChar::Char(c),
+            None => EscapeChar::Disabled,
+        })
+    }
+}
+
+impl GlobalOptions {
+    /// Takes the options not given on the command line from the host.
+    pub fn apply_host(&mut self, host: &Host) {
+        if self.profile.is_empty() {
+            self.profile.extend(host.profile.clone());
+        }
+        if self.region.is_empty() {
+            self.region.extend(host.region.clone());
+        }
+        if self.document.is_none() {
+            self.document.clone_from(&host.document);
+        }
+    }
+
+    /// Returns the escape character given, or the default one. None when escapes are disabled.
+    pub fn escape_char(&self) -> Option<u8> {
+        self.escape_char
+            .map_or(Some(DEFAULT_ESCAPE_CHAR), EscapeChar::byte)
+    }
+
+    /// Returns the log level requested on the command line, if any.
+    pub fn log_level(&self) -> Option<LevelFilter> {
+        if self.log_level.is_some() {
+            return self.log_level;
+        }
+
+        match self.verbose {
+            0 => None,
+            1 => Some(LevelFilter::INFO),
+            2 => Some(LevelFilter::DEBUG),
+            _ => Some(LevelFilter::TRACE),
+        }
+    }
+
+    /// Parses the document parameters, later values replace earlier ones of the same key.
+    pub fn parameters(&self) -> Result<HashMap<String, Vec<String>>> {
+        let mut parameters = HashMap::new();
+        for value in &self.parameters {
+            parameters.extend(parse_parameters(value)?);
+        }
+
+        Ok(parameters)
+    }
+}
+
+/// Parses `key=value,key=value` or a JSON object such as `{"portNumber":["80"]}`, like the AWS
+/// CLI does.
+fn parse_parameters(value: &str) -> Result<HashMap<String, Vec<String>>> {
+    if value.trim_start().starts_with('{') {
+        let parameters: HashMap<String, serde_json::Value> =
+            serde_json::from_str(value).context("Invalid JSON parameters")?;
+
+        return parameters
+            .into_iter()
+            .map(|(key, value)| Ok((key, json_parameter_values(value)?)))
+            .collect();
+    }
+
+    let mut parameters = HashMap::new();
+    for pair in value.split(',').filter(|pair| !pair.is_empty()) {
+        let Some((key, value)) = pair.split_once('=') else {
+            bail!("Invalid parameter {:?}, expected key=value", pair);
+        };
+        parameters.insert(key.trim().to_string(), vec![value.to_string()]);
+    }
+
+    Ok(parameters)
+}
+
+fn json_parameter_values(value: serde_json::Value) -> Result<Vec<String>> {
+    match value {
+        serde_json::Value::Array(values) => values
+            .into_iter()
+            .map(json_parameter_value)
+            .collect::<Result<_>>(),
+        value => Ok(vec![json_parameter_value(value)?]),
+    }
+}
+
+fn json_parameter_value(value: serde_json::Value) -> Result<String> {
+    match value {
+        serde_json::Value::String(value) => Ok(value),
+        serde_json::Value::Number(value) => Ok(value.to_string()),
+        serde_json::Value::Bool(value) => Ok(value.to_string()),
+        value => bail!("Invalid parameter value {}", value),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> GlobalOptions {
+        Cli::try_parse_from([&["ssm-term"], args].concat())
+            .unwrap()
+            .options
+    }
+
+    #[test]
+    fn parses_the_escape_char() {
+        assert_eq!(options(&[]).escape_char(), Some(b'~'));
+        assert_eq!(options(&["-e", "%"]).escape_char(), Some(b'%'));
+        assert_eq!(options(&["--escape-char", "^]"]).escape_char(), Some(0x1d));
+    }
+
+    #[test]
+    fn disables_escapes_with_none() {
+        let options = options(&["-e", "none", "connect", "web"]);
+
+        assert_eq!(options.escape_char, Some(EscapeChar::Disabled));
+        assert_eq!(options.escape_char(), None);
+    }
+
+    #[test]
+    fn rejects_invalid_escape_chars() {
+        for invalid in ["ab", "^1", ""] {
+            assert!(
+                Cli::try_parse_from(["ssm-term", "-e", invalid]).is_err(),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+}
@@ -0,0 +1,12 @@
+use crate::commands::{Context, CANCELLED_EXIT_CODE};
+use anyhow::Result;
+use std::collections::HashMap;
+
+/// Starts a shell session, or a session of the document given as option.
+pub async fn execute(context: &Context, target: Option<String>) -> Result<i32> {
+    let Some(target) = context.target(target).await? else {
+        return Ok(CANCELLED_EXIT_CODE);
+    };
+
+    context.run_session(&target, None, HashMap::new()).await
+}
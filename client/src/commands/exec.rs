@@ -0,0 +1,29 @@
+use crate::commands::{Context, CANCELLED_EXIT_CODE};
+use anyhow::Result;
+use session_manager::config::config::{
+    INTERACTIVE_COMMAND_DOCUMENT_NAME, NON_INTERACTIVE_COMMAND_DOCUMENT_NAME,
+};
+use std::collections::HashMap;
+
+/// Runs a command like `ssh host command` does, in a terminal when interactive.
+pub async fn execute(
+    context: &Context,
+    target: String,
+    interactive: bool,
+    command: &[String],
+) -> Result<i32> {
+    let Some(target) = context.target(Some(target)).await? else {
+        return Ok(CANCELLED_EXIT_CODE);
+    };
+
+    let document_name = if interactive {
+        INTERACTIVE_COMMAND_DOCUMENT_NAME
+    } else {
+        NON_INTERACTIVE_COMMAND_DOCUMENT_NAME
+    };
+    let parameters = HashMap::from([("command".to_string(), vec![command.join(" ")])]);
+
+    context
+        .run_session(&target, Some(document_name), parameters)
+        .await
+}
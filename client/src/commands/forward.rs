@@ -0,0 +1,82 @@
+use crate::commands::{Context, CANCELLED_EXIT_CODE};
+use crate::config::Forward;
+use anyhow::{bail, Result};
+use futures_util::future;
+use session_manager::config::config::{
+    PORT_FORWARDING_DOCUMENT_NAME, PORT_FORWARDING_TO_REMOTE_HOST_DOCUMENT_NAME,
+};
+use std::collections::HashMap;
+
+/// Forwards a local port to the target, or through the target to a remote host. Without a
+/// port, forwards the ports of the host alias, each in its own session.
+pub async fn execute(
+    context: &Context,
+    target: Option<String>,
+    forward: Option<Forward>,
+) -> Result<i32> {
+    let forwards = match forward {
+        Some(forward) => vec![forward],
+        None => context
+            .host
+            .as_ref()
+            .map(|host| host.forwards.clone())
+            .unwrap_or_default(),
+    };
+    if forwards.is_empty() {
+        bail!("A port is required, either with --port or in the forwards of the host");
+    }
+
+    let Some(target) = context.target(target).await? else {
+        return Ok(CANCELLED_EXIT_CODE);
+    };
+
+    let sessions = forwards.iter().map(|forward| {
+        let (document_name, parameters) = session_parameters(forward);
+        context.run_session(&target, Some(document_name), parameters)
+    });
+    // Every session runs until it closes on its own, so a failing forward does not drop the
+    // others without terminating them.
+    let mut exit_code = 0;
+    let mut errors = Vec::new();
+    for result in future::join_all(sessions).await {
+        match result {
+            Ok(code) if exit_code == 0 => exit_code = code,
+            Ok(_) => {}
+            Err(e) => errors.push(e),
+        }
+    }
+
+    match errors.len() {
+        0 => Ok(exit_code),
+        1 => Err(errors.remove(0)),
+        _ => bail!(
+            "{} of {} forwards failed: {}",
+            errors.len(),
+            forwards.len(),
+            errors
+                .iter()
+                .map(|e| format!("{:#}", e))
+                .collect::<Vec<_>>()
+                .join("; ")
+        ),
+    }
+}
+
+/// Returns the document and parameters of the session forwarding the port.
+fn session_parameters(forward: &Forward) -> (&'static str, HashMap<String, Vec<String>>) {
+    let mut parameters =
+        HashMap::from([("portNumber".to_string(), vec![forward.port.to_string()])]);
+    if let Some(local_port) = forward.local_port {
+        parameters.insert("localPortNumber".to_string(), vec![local_port.to_string()]);
+    }
+
+    let document_name = match &forward.host {
+        Some(host) => {
+            parameters.insert("host".to_string(), vec![host.clone()]);
+            PORT_FORWARDING_TO_REMOTE_HOST_DOCUMENT_NAME
+        }
+        None => PORT_FORWARDING_DOCUMENT_NAME,
+    };
+
+    (document_name, parameters)
+}
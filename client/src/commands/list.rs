@@ -0,0 +1,24 @@
+use crate::commands::{print_table, Context};
+use crate::inventory::instance;
+use anyhow::Result;
+
+/// Prints the managed instances of every profile and region.
+pub async fn execute(context: &Context) -> Result<i32> {
+    let instances = context.instances().await?;
+    let labelled = instance::labelled(&instances);
+
+    let rows = instances
+        .iter()
+        .map(|instance| {
+            instance
+                .columns(labelled)
+                .into_iter()
+                .map(str::to_string)
+                .collect()
+        })
+        .collect::<Vec<_>>();
+
+    print_table(&instance::headers(labelled), &rows);
+
+    Ok(0)
+}
@@ -0,0 +1,488 @@
+use crate::cli::GlobalOptions;
+use crate::config::Host;
+use crate::inventory::cache::{InventoryCache, INVENTORY_CACHE_TTL};
+use crate::inventory::instance::Instance;
+use crate::inventory::scope::Scope;
+use crate::inventory::selector::Selector;
+use crate::inventory::source::{AwsInstanceSource, InstanceSource};
+use crate::picker;
+use anyhow::{bail, Context as _, Result};
+use aws_sdk_ssm::operation::start_session::StartSessionOutput;
+use futures_util::future;
+use session_manager::config::config::SessionConfig;
+use session_manager::session_manager_plugin::registry::SessionPluginRegistry;
+use session_manager::session_manager_plugin::session::Session;
+use session_manager::session_manager_plugin::session_handle::SessionNotice;
+use session_manager::session_manager_plugin::session_type::SessionType;
+use std::collections::HashMap;
+use std::io::{self, IsTerminal};
+use std::sync::mpsc;
+use tokio::task;
+use tracing::debug;
+
+pub mod connect;
+pub mod exec;
+pub mod forward;
+pub mod list;
+pub mod plugin;
+pub mod sessions;
+
+/// Exit code when the instance picker is cancelled, as for an interrupt.
+pub const CANCELLED_EXIT_CODE: i32 = 130;
+
+/// AWS configuration and options shared by the commands.
+pub struct Context {
+    /// Every combination of the profiles and regions given, the defaults when none are.
+    pub scopes: Vec<Scope>,
+
+    /// Instances listed before, None when the user has no cache directory.
+    pub cache: Option<InventoryCache>,
+    pub options: GlobalOptions,
+
+    /// Host the target alias resolved to, its options are applied already.
+    pub host: Option<Host>,
+    pub session_config: SessionConfig,
+
+    /// Lists the instances targets are resolved and picked from.
+    source: Box<dyn InstanceSource>,
+}
+
+/// Instance to start a session on, in the scope it was found in.
+pub struct Target<'a> {
+    pub instance_id: String,
+    pub scope: &'a Scope,
+}
+
+impl Context {
+    /// Loads the AWS configuration of every profile and region.
+    pub async fn new(
+        options: GlobalOptions,
+        session_config: SessionConfig,
+        host: Option<Host>,
+    ) -> Result<Self> {
+        let profiles = defaulted(&options.profile);
+        let regions = defaulted(&options.region);
+
+        let endpoint_url = options.endpoint_url.as_deref();
+        let scopes = future::join_all(profiles.iter().flat_map(|profile| {
+            regions
+                .iter()
+                .map(|region| Scope::load(profile.clone(), region.clone(), endpoint_url))
+        }))
+        .await;
+
+        let cache = InventoryCache::open(INVENTORY_CACHE_TTL);
+
+        Ok(Self {
+            source: Box::new(AwsInstanceSource::new(scopes.clone(), cache.clone())),
+            scopes,
+            cache,
+            options,
+            host,
+            session_config,
+        })
+    }
+
+    pub fn instance_source(&self) -> &dyn InstanceSource {
+        self.source.as_ref()
+    }
+
+    /// Lists the instances of every scope, the cached ones while none is stale unless refreshing.
+    pub async fn instances(&self) -> Result<Vec<Instance>> {
+        let cached = self
+            .cache
+            .as_ref()
+            .filter(|_| !self.options.refresh)
+            .and_then(|cache| cache.load_fresh(&self.scopes));
+
+        match cached {
+            Some(instances) => Ok(instances),
+            None => self.instance_source().instances(Vec::new()).await,
+        }
+    }
+
+    /// Opens the picker on the cached instances right away, stale ones included, and replaces
+    /// them once listed again.
+    async fn pick_instance(&self) -> Result<Option<Instance>> {
+        let cached = match &self.cache {
+            Some(cache) if !self.options.refresh => self
+                .scopes
+                .iter()
+                .filter_map(|scope| cache.load(scope))
+                .flatten()
+                .collect(),
+            _ => Vec::new(),
+        };
+
+        let (sender, receiver) = mpsc::channel();
+        let picker = task::spawn_blocking(move || picker::view::pick(cached, Some(receiver)));
+        let refresh = async {
+            let _ = sender.send(self.instance_source().instances(Vec::new()).await);
+        };
+
+        // Picking does not wait for the refresh, which is dropped once an instance is picked.
+        tokio::pin!(picker);
+        tokio::select! {
+            instance = &mut picker => instance?,
+            () = refresh => picker.await?,
+        }
+    }
+
+    /// Returns the target in the scope the instance was listed from.
+    fn target_of(&self, instance: Instance) -> Target<'_> {
+        let scope = self
+            .scopes
+            .iter()
+            .find(|scope| scope.contains(&instance))
+            .unwrap_or(&self.scopes[0]);
+
+        Target {
+            instance_id: instance.instance_id,
+            scope,
+        }
+    }
+
+    /// Resolves the target to an instance, picking from the instances it matches when there are
+    /// several, or from all the instances when no target is given. Returns None when the picker
+    /// is cancelled.
+    pub async fn target(&self, target: Option<String>) -> Result<Option<Target<'_>>> {
+        let interactive = io::stdin().is_terminal() && io::stderr().is_terminal();
+
+        let Some(target) = target else {
+            if !interactive {
+                bail!("A target is required when not running in a terminal");
+            }
+
+            let instance = self.pick_instance().await?;
+
+            return Ok(instance.map(|instance| self.target_of(instance)));
+        };
+
+        let selectors = Selector::parse_all(&target)?;
+
+        // An id alone needs no lookup, which also requires fewer permissions. Unless there are
+        // several scopes, as the scope of the instance is not known.
+        if let ([Selector::InstanceId(id)], [scope]) =
+            (selectors.as_slice(), self.scopes.as_slice())
+        {
+            return Ok(Some(Target {
+                instance_id: id.clone(),
+                scope,
+            }));
+        }
+
+        let mut instances = matching_instances(self.instance_source(), &selectors).await?;
+
+        let instance = match instances.len() {
+            0 => bail!("No managed instance matches {}", target),
+            1 => instances.pop(),
+            count if interactive => {
+                debug!("{} matches {} instances", target, count);
+                pick(instances).await?
+            }
+            count => bail!(
+                "{} matches {} instances, select one of them: {}",
+                target,
+                count,
+                instances
+                    .iter()
+                    .map(describe_instance)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            ),
+        };
+
+        Ok(instance.map(|instance| self.target_of(instance)))
+    }
+
+    /// Starts a session with the document and parameters of the command, those of the host and
+    /// those given as options take precedence in turn. Runs the session until it closes and
+    /// returns the exit code for the process.
+    pub async fn run_session(
+        &self,
+        target: &Target<'_>,
+        document_name: Option<&str>,
+        mut parameters: HashMap<String, Vec<String>>,
+    ) -> Result<i32> {
+        if let Some(host) = &self.host {
+            parameters.extend(host.parameters.clone());
+        }
+        parameters.extend(self.options.parameters()?);
+
+        let ssm = &target.scope.ssm;
+        let request = ssm
+            .start_session()
+            .target(&target.instance_id)
+            .set_document_name(
+                self.options
+                    .document
+                    .clone()
+                    .or(document_name.map(str::to_string)),
+            )
+            .set_parameters((!parameters.is_empty()).then_some(parameters))
+            .set_reason(self.options.reason.clone());
+
+        let output = Session::start_session(request).await?;
+
+        self.run_started_session(target, output).await
+    }
+
+    /// Runs a session started already, such as by the AWS CLI, until it closes and returns the
+    /// exit code for the process.
+    pub async fn run_started_session(
+        &self,
+        target: &Target<'_>,
+        output: StartSessionOutput,
+    ) -> Result<i32> {
+        let ssm = &target.scope.ssm;
+        let session = Session::new(
+            &target.scope.config,
+            output
+                .session_id
+                .context("StartSession returned no session id")?,
+            output
+                .stream_url
+                .context("StartSession returned no stream url")?,
+            output
+                .token_value
+                .context("StartSession returned no token")?,
+            target.instance_id.clone(),
+        )
+        .with_ssm_client(ssm.clone())
+        .with_config(self.session_config.clone());
+
+        // Notices may arrive while the terminal is in raw mode, which needs the carriage return.
+        let (notices, mut notice_receiver) =
+            tokio::sync::mpsc::unbounded_channel::<SessionNotice>();
+        let printer = task::spawn(async move {
+            while let Some(notice) = notice_receiver.recv().await {
+                eprint!("{}\r\n", notice.to_string().replace('\n', "\r\n"));
+            }
+        });
+
+        let registry = SessionPluginRegistry::with_escape_char(self.options.escape_char());
+        let exit = session.with_notices(notices).run(&registry).await;
+        printer.await?;
+        let exit = exit?;
+
+        // The terminal is restored at this point, so the reason is not mangled by raw mode. Only
+        // terminal sessions and failures report it, the output of commands is left untouched.
+        if exit.error
+            || exit
+                .session_type
+                .as_ref()
+                .is_some_and(SessionType::is_terminal)
+        {
+            eprintln!("\n{}\n", exit.reason);
+        }
+
+        Ok(exit.process_exit_code())
+    }
+}
+
+/// Opens the picker on the instances, returns the instance picked.
+async fn pick(instances: Vec<Instance>) -> Result<Option<Instance>> {
+    task::spawn_blocking(move || picker::view::pick(instances, None)).await?
+}
+
+/// Lists the instances matching every selector, filtered by SSM where it can and once listed
+/// otherwise.
+async fn matching_instances(
+    source: &dyn InstanceSource,
+    selectors: &[Selector],
+) -> Result<Vec<Instance>> {
+    let filters = selectors
+        .iter()
+        .filter_map(|selector| selector.filter().transpose())
+        .collect::<Result<Vec<_>>>()?;
+
+    Ok(source
+        .instances(filters)
+        .await?
+        .into_iter()
+        .filter(|instance| selectors.iter().all(|selector| selector.matches(instance)))
+        .collect())
+}
+
+/// Returns the values given, or the default of the environment as None when there are none.
+fn defaulted(values: &[String]) -> Vec<Option<String>> {
+    match values {
+        [] => vec![None],
+        values => values.iter().cloned().map(Some).collect(),
+    }
+}
+
+fn describe_instance(instance: &Instance) -> String {
+    match instance.display_name() {
+        "" => instance.instance_id.clone(),
+        name => format!("{} ({})", instance.instance_id, name),
+    }
+}
+
+/// Prints rows aligned in columns below the headers.
+pub fn print_table(headers: &[&str], rows: &[Vec<String>]) {
+    let mut widths = headers
+        .iter()
+        .map(|header| header.len())
+        .collect::<Vec<_>>();
+    for row in rows {
+        for (width, value) in widths.iter_mut().zip(row) {
+            *width = (*width).max(value.chars().count());
+        }
+    }
+
+    let print_row = |values: &[&str]| {
+        let line = values
+            .iter()
+            .zip(&widths)
+            .map(|(value, width)| format!("{:width$}", value, width = width))
+            .collect::<Vec<_>>()
+            .join("  ");
+        println!("{}", line.trim_end());
+    };
+
+    print_row(headers);
+    for row in rows {
+        print_row(&row.iter().map(String::as_str).collect::<Vec<_>>());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_sdk_ssm::types::InstanceInformationStringFilter;
+    use futures_util::future::BoxFuture;
+    use std::sync::Mutex;
+
+    /// Returns the same instances for any filters, recording the filters of every request.
+    #[derive(Default)]
+    struct StubSource {
+        instances: Vec<Instance>,
+        requests: Mutex<Vec<Vec<InstanceInformationStringFilter>>>,
+    }
+
+    impl InstanceSource for StubSource {
+        fn instances(
+            &self,
+            filters: Vec<InstanceInformationStringFilter>,
+        ) -> BoxFuture<'_, Result<Vec<Instance>>> {
+            self.requests.lock().unwrap().push(filters);
+            Box::pin(async { Ok(self.instances.clone()) })
+        }
+    }
+
+    struct FailingSource;
+
+    impl InstanceSource for FailingSource {
+        fn instances(
+            &self,
+            _filters: Vec<InstanceInformationStringFilter>,
+        ) -> BoxFuture<'_, Result<Vec<Instance>>> {
+            Box::pin(async { bail!("AccessDenied") })
+        }
+    }
+
+    fn instance(id: &str, name: Option<&str>, computer_name: &str, ip: &str) -> Instance {
+        Instance {
+            instance_id: id.to_string(),
+            name: name.map(str::to_string),
+            computer_name: Some(computer_name.to_string()),
+            ip_address: Some(ip.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn stub() -> StubSource {
+        StubSource {
+            instances: vec![
+                instance(
+                    "i-0123456789abcdef0",
+                    Some("web-1"),
+                    "ip-10-0-0-1.ec2.internal",
+                    "10.0.0.1",
+                ),
+                instance(
+                    "i-0fedcba9876543210",
+                    Some("web-2"),
+                    "ip-10-0-0-2.ec2.internal",
+                    "10.0.0.2",
+                ),
+                instance("mi-0123456789abcdef0", None, "db-box", "192.168.1.5"),
+            ],
+            ..Default::default()
+        }
+    }
+
+    async fn matching_ids(source: &StubSource, target: &str) -> Vec<String> {
+        matching_instances(source, &Selector::parse_all(target).unwrap())
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|instance| instance.instance_id)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn matches_listed_instances_on_every_selector() {
+        let source = stub();
+
+        // Instances without a Name tag in EC2 are left to the SSM filter.
+        assert_eq!(
+            matching_ids(&source, "Name=web-2").await,
+            ["i-0fedcba9876543210", "mi-0123456789abcdef0"]
+        );
+        assert_eq!(
+            matching_ids(&source, "10.0.0.1").await,
+            ["i-0123456789abcdef0"]
+        );
+        assert_eq!(
+            matching_ids(&source, "DB-BOX").await,
+            ["mi-0123456789abcdef0"]
+        );
+        assert_eq!(
+            matching_ids(&source, "ip-10-0-0-2").await,
+            ["i-0fedcba9876543210"]
+        );
+        assert!(matching_ids(&source, "Name=web-1,10.0.0.2")
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn keeps_instances_matched_by_tag_filters() {
+        let source = stub();
+
+        // Tags other than Name are only known to SSM, so every instance listed is kept.
+        assert_eq!(matching_ids(&source, "tag:env=prod").await.len(), 3);
+    }
+
+    #[tokio::test]
+    async fn passes_id_and_tag_selectors_as_filters() {
+        let source = stub();
+        matching_ids(&source, "tag:env=prod,Name=web-1,10.0.0.1").await;
+        matching_ids(&source, "db-box").await;
+
+        let requests = source.requests.lock().unwrap();
+        let filters = requests[0]
+            .iter()
+            .map(|filter| (filter.key(), filter.values()))
+            .collect::<Vec<_>>();
+        assert_eq!(
+            filters,
+            [
+                ("tag:env", ["prod".to_string()].as_slice()),
+                ("tag:Name", ["web-1".to_string()].as_slice()),
+            ]
+        );
+        assert!(requests[1].is_empty());
+    }
+
+    #[tokio::test]
+    async fn fails_when_the_instances_cannot_be_listed() {
+        let selectors = Selector::parse_all("web-1").unwrap();
+
+        assert!(matching_instances(&FailingSource, &selectors)
+            .await
+            .is_err());
+    }
+}
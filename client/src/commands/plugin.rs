@@ -0,0 +1,112 @@
+use crate::cli::GlobalOptions;
+use crate::commands::{Context, Target};
+use anyhow::{bail, Context as _, Result};
+use aws_sdk_ssm::operation::start_session::StartSessionOutput;
+use serde::Deserialize;
+use session_manager::config::config::{SessionConfig, CLIENT_VERSION};
+use std::env;
+
+/// Name of the AWS session manager plugin, the AWS CLI runs the executable of this name.
+pub const PLUGIN_NAME: &str = "session-manager-plugin";
+
+const START_SESSION_OPERATION: &str = "StartSession";
+
+/// Newer AWS CLI versions pass the name of an environment variable holding the StartSession
+/// response instead of the response, which keeps the token out of the process list.
+const START_SESSION_RESPONSE_ENV_PREFIX: &str = "AWS_SSM_START_SESSION_RESPONSE";
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "PascalCase")]
+struct StartSessionResponse {
+    session_id: String,
+    stream_url: String,
+    token_value: String,
+}
+
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, rename_all = "PascalCase")]
+struct StartSessionRequest {
+    target: String,
+}
+
+/// Runs like the AWS session manager plugin, with the arguments the AWS CLI passes to it: the
+/// StartSession response, the region and the operation, followed by the profile, the
+/// StartSession request and the SSM endpoint from CLI versions supporting them.
+pub async fn execute(
+    mut options: GlobalOptions,
+    session_config: SessionConfig,
+    args: &[String],
+) -> Result<i32> {
+    let (response, region, operation, rest) = match args {
+        [] => {
+            println!(
+                "\nThe Session Manager plugin was installed successfully. Use the AWS CLI to start \
+                 a session.\n"
+            );
+            return Ok(0);
+        }
+        [flag] if flag == "--version" => {
+            println!("{}", CLIENT_VERSION);
+            return Ok(0);
+        }
+        [response, region, operation, rest @ ..] => (response, region, operation, rest),
+        [operation, ..] => bail!(
+            "Unknown operation {}, use {} --version to check the version",
+            operation,
+            PLUGIN_NAME
+        ),
+    };
+
+    if operation != START_SESSION_OPERATION {
+        bail!("Invalid operation {}", operation);
+    }
+
+    let response = start_session_response(response)?;
+    let request = match rest.get(1) {
+        Some(request) => serde_json::from_str::<StartSessionRequest>(request)
+            .context("Invalid StartSession request")?,
+        None => StartSessionRequest::default(),
+    };
+
+    // The AWS CLI passes empty values for the profile and endpoint it does not know.
+    let non_empty = |value: &&String| !value.is_empty();
+    options.profile = rest
+        .first()
+        .filter(non_empty)
+        .cloned()
+        .into_iter()
+        .collect();
+    options.region = Some(region)
+        .filter(non_empty)
+        .cloned()
+        .into_iter()
+        .collect();
+    if let Some(endpoint) = rest.get(2).filter(non_empty) {
+        options.endpoint_url = Some(endpoint.clone());
+    }
+
+    let context = Context::new(options, session_config, None).await?;
+    let target = Target {
+        instance_id: request.target,
+        scope: &context.scopes[0],
+    };
+    let output = StartSessionOutput::builder()
+        .session_id(response.session_id)
+        .stream_url(response.stream_url)
+        .token_value(response.token_value)
+        .build();
+
+    context.run_started_session(&target, output).await
+}
+
+/// Parses the StartSession response, read from the environment variable the argument names
+/// when it is not the response itself.
+fn start_session_response(argument: &str) -> Result<StartSessionResponse> {
+    let response = if argument.starts_with(START_SESSION_RESPONSE_ENV_PREFIX) {
+        env::var(argument).with_context(|| format!("{} is not set", argument))?
+    } else {
+        argument.to_string()
+    };
+
+    serde_json::from_str(&response).context("Invalid StartSession response")
+}
@@ -0,0 +1,67 @@
+use crate::commands::{print_table, Context};
+use anyhow::Result;
+use aws_sdk_ssm::primitives::{DateTime, DateTimeFormat};
+use aws_sdk_ssm::types::SessionState;
+
+/// Prints the active sessions, or the ended ones for the history, of every profile and region.
+pub async fn execute(context: &Context, history: bool) -> Result<i32> {
+    let state = if history {
+        SessionState::History
+    } else {
+        SessionState::Active
+    };
+
+    let labelled = context.scopes.len() > 1;
+
+    let mut rows = Vec::new();
+    for scope in &context.scopes {
+        let mut sessions = scope
+            .ssm
+            .describe_sessions()
+            .state(state.clone())
+            .into_paginator()
+            .items()
+            .send();
+
+        while let Some(session) = sessions.next().await {
+            let session = session?;
+            let mut row = vec![
+                session.session_id.unwrap_or_default(),
+                session.target.unwrap_or_default(),
+                session
+                    .status
+                    .map(|status| status.as_str().to_string())
+                    .unwrap_or_default(),
+                format_date(session.start_date),
+                session.document_name.unwrap_or_default(),
+                session.owner.unwrap_or_default(),
+                session.reason.unwrap_or_default(),
+            ];
+            if labelled {
+                row.insert(2, scope.to_string());
+            }
+            rows.push(row);
+        }
+    }
+
+    let mut headers = vec![
+        "SESSION ID",
+        "TARGET",
+        "STATUS",
+        "START DATE",
+        "DOCUMENT",
+        "OWNER",
+        "REASON",
+    ];
+    if labelled {
+        headers.insert(2, "SCOPE");
+    }
+    print_table(&headers, &rows);
+
+    Ok(0)
+}
+
+fn format_date(date: Option<DateTime>) -> String {
+    date.and_then(|date| date.fmt(DateTimeFormat::DateTime).ok())
+        .unwrap_or_default()
+}
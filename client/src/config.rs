@@ -0,0 +1,247 @@
+use crate::cli::EscapeChar;
+use anyhow::{Context, Result};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use session_manager::config::config::SessionConfig;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use tracing::level_filters::LevelFilter;
+
+/// Settings of `~/.config/ssm-term/config.toml`, the command line takes precedence.
+#[derive(Deserialize, Debug, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Log level when neither given on the command line nor in RUST_LOG.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub log_level: Option<LevelFilter>,
+
+    /// Character starting escape sequences, as for `--escape-char`.
+    #[serde(deserialize_with = "deserialize_from_str")]
+    pub escape_char: Option<EscapeChar>,
+
+    pub session: SessionConfig,
+
+    /// Targets by alias, like the hosts of `~/.ssh/config`.
+    pub hosts: HashMap<String, Host>,
+}
+
+/// Target named by an alias, together with the options to connect to it.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct Host {
+    /// Selector of the target, as given on the command line. The alias itself when not set.
+    pub target: Option<String>,
+    pub profile: Option<String>,
+    pub region: Option<String>,
+    pub document: Option<String>,
+
+    /// Document parameters, a string or a list of strings each.
+    #[serde(deserialize_with = "deserialize_parameters")]
+    pub parameters: HashMap<String, Vec<String>>,
+
+    /// Ports forwarded by `forward` when no port is given.
+    pub forwards: Vec<Forward>,
+}
+
+/// Port forwarded to the target, or through the target to a remote host.
+#[derive(Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Forward {
+    pub port: u16,
+    pub local_port: Option<u16>,
+    pub host: Option<String>,
+}
+
+impl Config {
+    /// Loads the configuration file given, or the default one when it exists.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path.to_path_buf(), true),
+            None => match default_path() {
+                Some(path) => (path, false),
+                None => return Ok(Self::default()),
+            },
+        };
+
+        let contents = match fs::read_to_string(&path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound && !required => {
+                return Ok(Self::default())
+            }
+            Err(e) => return Err(e).context(format!("Failed to read {}", path.display())),
+        };
+
+        let config: Self =
+            toml::from_str(&contents).with_context(|| format!("Invalid {}", path.display()))?;
+        config
+            .session
+            .validate()
+            .with_context(|| format!("Invalid session settings in {}", path.display()))?;
+
+        Ok(config)
+    }
+
+    /// Resolves a host alias, replacing it with the target of the host. Returns None when the
+    /// target is not an alias.
+    pub fn resolve_host(&self, target: &mut String) -> Option<Host> {
+        let host = self.hosts.get(target.as_str())?.clone();
+        if let Some(host_target) = &host.target {
+            *target = host_target.clone();
+        }
+
+        Some(host)
+    }
+}
+
+/// Returns `ssm-term/config.toml` in `XDG_CONFIG_HOME`, or in `~/.config` on every platform.
+fn default_path() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+
+    Some(dir.join("ssm-term").join("config.toml"))
+}
+
+fn deserialize_from_str<'de, D, T>(deserializer: D) -> Result<Option<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: FromStr,
+    T::Err: std::fmt::Display,
+{
+    let value = String::deserialize(deserializer)?;
+
+    value.parse().map(Some).map_err(D::Error::custom)
+}
+
+fn deserialize_parameters<'de, D>(deserializer: D) -> Result<HashMap<String, Vec<String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Values {
+        One(String),
+        Many(Vec<String>),
+    }
+
+    let parameters = HashMap::<String, Values>::deserialize(deserializer)?;
+
+    Ok(parameters
+        .into_iter()
+        .map(|(key, values)| match values {
+            Values::One(value) => (key, vec![value]),
+            Values::Many(values) => (key, values),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    const CONFIG: &str = r#"
+        log_level = "debug"
+        escape_char = "none"
+
+        [session]
+        ping_interval_secs = 30
+
+        [hosts.web]
+        target = "Name=web-*"
+        profile = "prod"
+        region = "eu-west-1"
+        parameters = { command = "uptime", executionTimeout = ["3600"] }
+        forwards = [{ port = 5432, local_port = 15432, host = "db.internal" }, { port = 80 }]
+
+        [hosts.i-0123456789abcdef0]
+        profile = "dev"
+    "#;
+
+    /// Writes the contents to a file of the temporary directory, named after the test.
+    fn write_config(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("ssm-term-config-{}-{}.toml", process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path
+    }
+
+    #[test]
+    fn parses_the_settings_and_hosts() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+
+        assert_eq!(config.log_level, Some(LevelFilter::DEBUG));
+        assert_eq!(config.escape_char, Some(EscapeChar::Disabled));
+        assert_eq!(config.session.ping_interval_secs, 30);
+        assert_eq!(
+            config.session.resend_max_attempts,
+            SessionConfig::default().resend_max_attempts
+        );
+
+        let web = &config.hosts["web"];
+        assert_eq!(web.profile.as_deref(), Some("prod"));
+        assert_eq!(web.parameters["command"], ["uptime"]);
+        assert_eq!(web.parameters["executionTimeout"], ["3600"]);
+        assert_eq!(web.forwards.len(), 2);
+        assert_eq!(web.forwards[0].local_port, Some(15432));
+        assert_eq!(web.forwards[0].host.as_deref(), Some("db.internal"));
+        assert_eq!(web.forwards[1].port, 80);
+        assert_eq!(web.forwards[1].local_port, None);
+    }
+
+    #[test]
+    fn resolves_host_aliases() {
+        let config: Config = toml::from_str(CONFIG).unwrap();
+
+        let mut target = "web".to_string();
+        let host = config.resolve_host(&mut target).unwrap();
+        assert_eq!(target, "Name=web-*");
+        assert_eq!(host.region.as_deref(), Some("eu-west-1"));
+
+        // Without a target of its own, the alias is the target.
+        let mut target = "i-0123456789abcdef0".to_string();
+        let host = config.resolve_host(&mut target).unwrap();
+        assert_eq!(target, "i-0123456789abcdef0");
+        assert_eq!(host.profile.as_deref(), Some("dev"));
+
+        let mut target = "Name=db".to_string();
+        assert!(config.resolve_host(&mut target).is_none());
+        assert_eq!(target, "Name=db");
+    }
+
+    #[test]
+    fn rejects_unknown_and_invalid_settings() {
+        assert!(toml::from_str::<Config>("colour = true").is_err());
+        assert!(toml::from_str::<Config>("[hosts.web]\nuser = \"root\"").is_err());
+        assert!(toml::from_str::<Config>("log_level = \"loud\"").is_err());
+        assert!(toml::from_str::<Config>("escape_char = \"ab\"").is_err());
+        assert!(toml::from_str::<Config>("[hosts.web]\nforwards = [{ local_port = 1 }]").is_err());
+    }
+
+    #[test]
+    fn loads_and_validates_the_file_given() {
+        let path = write_config("valid", CONFIG);
+        let config = Config::load(Some(&path)).unwrap();
+        assert!(config.hosts.contains_key("web"));
+        fs::remove_file(&path).unwrap();
+
+        let path = write_config(
+            "invalid_session",
+            "[session]\nreconnect_initial_delay_millis = 2000\nreconnect_max_delay_millis = 1000",
+        );
+        let error = Config::load(Some(&path)).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            format!("Invalid session settings in {}", path.display())
+        );
+        fs::remove_file(&path).unwrap();
+
+        // Unlike the default file, a file given must exist.
+        let path = std::env::temp_dir().join(format!("ssm-term-config-{}-missing", process::id()));
+        assert!(Config::load(Some(&path)).is_err());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::inventory::instance::Instance;
+use crate::inventory::scope::Scope;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::PathBuf;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::debug;
+
+/// How long listed instances are used without listing them again.
+pub const INVENTORY_CACHE_TTL: Duration = Duration::from_secs(5 * 60);
+
+/// Instances of a scope as stored on disk.
+#[derive(Serialize, Deserialize)]
+struct CacheFile {
+    /// Seconds since the Unix epoch the instances were listed at.
+    updated_at: u64,
+    instances: Vec<Instance>,
+}
+
+/// Instances listed before, stored per profile and region under the cache directory of the
+/// user, e.g. `~/.cache/ssm-term/inventory/default/eu-west-1.json`. Instances listed from a
+/// custom SSM endpoint are kept apart, in a directory named after the endpoint.
+#[derive(Clone, Debug)]
+pub struct InventoryCache {
+    dir: PathBuf,
+    ttl: Duration,
+}
+
+impl InventoryCache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Self {
+        Self { dir, ttl }
+    }
+
+    /// Opens the cache in the cache directory of the user, None when there is none.
+    pub fn open(ttl: Duration) -> Option<Self> {
+        dirs::cache_dir().map(|dir| Self::new(dir.join("ssm-term").join("inventory"), ttl))
+    }
+
+    /// Returns the instances of the scope, marked stale once older than the TTL. None when the
+    /// scope was never listed or its cache cannot be read.
+    pub fn load(&self, scope: &Scope) -> Option<Vec<Instance>> {
+        let path = self.path(scope);
+        let file = match fs::read(&path)
+            .map_err(anyhow::Error::from)
+            .and_then(|data| Ok(serde_json::from_slice::<CacheFile>(&data)?))
+        {
+            Ok(file) => file,
+            Err(e) => {
+                debug!("No cached instances in {}: {:#}", path.display(), e);
+                return None;
+            }
+        };
+
+        let age = unix_time().saturating_sub(file.updated_at);
+        let stale = age > self.ttl.as_secs();
+        debug!(
+            "Cached instances of {} are {}s old{}",
+            scope,
+            age,
+            if stale { ", stale" } else { "" }
+        );
+
+        let mut instances = file.instances;
+        for instance in &mut instances {
+            instance.stale = stale;
+        }
+
+        Some(instances)
+    }
+
+    /// Returns the instances of every scope when all of them are cached and none is stale.
+    pub fn load_fresh(&self, scopes: &[Scope]) -> Option<Vec<Instance>> {
+        let mut instances = Vec::new();
+        for scope in scopes {
+            let cached = self.load(scope)?;
+            if cached.iter().any(|instance| instance.stale) {
+                return None;
+            }
+            instances.extend(cached);
+        }
+
+        Some(instances)
+    }
+
+    /// Replaces the instances of the scope.
+    pub fn store(&self, scope: &Scope, instances: &[Instance]) -> Result<()> {
+        let path = self.path(scope);
+        let dir = path.parent().unwrap_or(&self.dir);
+        fs::create_dir_all(dir).with_context(|| format!("Failed to create {}", dir.display()))?;
+
+        let file = CacheFile {
+            updated_at: unix_time(),
+            instances: instances.to_vec(),
+        };
+
+        // Written aside and renamed, so a concurrent launch never reads half a file.
+        let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+        fs::write(&temporary, serde_json::to_vec(&file)?)
+            .with_context(|| format!("Failed to write {}", temporary.display()))?;
+        fs::rename(&temporary, &path)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+
+        Ok(())
+    }
+
+    fn path(&self, scope: &Scope) -> PathBuf {
+        self.path_of(
+            &scope.profile_name(),
+            scope.region(),
+            scope.endpoint_url.as_deref(),
+        )
+    }
+
+    fn path_of(&self, profile: &str, region: Option<&str>, endpoint_url: Option<&str>) -> PathBuf {
+        let mut dir = self.dir.join(file_name(profile));
+        if let Some(endpoint_url) = endpoint_url {
+            dir.push(file_name(endpoint_url));
+        }
+
+        dir.join(format!("{}.json", file_name(region.unwrap_or("default"))))
+    }
+}
+
+/// Replaces the characters that cannot be used in a file name, and leading dots so names such
+/// as `..` stay within the cache directory.
+fn file_name(value: &str) -> String {
+    let name = value
+        .chars()
+        .map(|c| match c {
+            'a'..='z' | 'A'..='Z' | '0'..='9' | '-' | '_' | '.' => c,
+            _ => '_',
+        })
+        .collect::<String>();
+    let dots = name.len() - name.trim_start_matches('.').len();
+
+    if name.is_empty() {
+        return "_".to_string();
+    }
+
+    "_".repeat(dots) + &name[dots..]
+}
+
+fn unix_time() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Component;
+
+    fn cache() -> InventoryCache {
+        InventoryCache::new(PathBuf::from("/cache"), INVENTORY_CACHE_TTL)
+    }
+
+    #[test]
+    fn replaces_leading_dots_and_separators() {
+        assert_eq!(file_name("default"), "default");
+        assert_eq!(file_name("eu-west-1"), "eu-west-1");
+        assert_eq!(file_name("my.profile"), "my.profile");
+        assert_eq!(file_name("."), "_");
+        assert_eq!(file_name(".."), "__");
+        assert_eq!(file_name(".hidden"), "_hidden");
+        assert_eq!(file_name("../../etc"), "___.._etc");
+        assert_eq!(file_name(""), "_");
+    }
+
+    #[test]
+    fn keeps_every_scope_within_the_cache_directory() {
+        for name in ["..", ".", "../..", "/etc", ""] {
+            let path = cache().path_of(name, Some(name), Some(name));
+
+            assert!(path.starts_with("/cache"), "{}", path.display());
+            assert_eq!(path.components().count(), 5, "{}", path.display());
+            assert!(
+                path.components()
+                    .skip(1)
+                    .all(|c| matches!(c, Component::Normal(_))),
+                "{}",
+                path.display()
+            );
+        }
+    }
+
+    #[test]
+    fn keys_instances_of_custom_endpoints_apart() {
+        let cache = cache();
+        let default = cache.path_of("default", Some("eu-west-1"), None);
+        let local = cache.path_of("default", Some("eu-west-1"), Some("http://localhost:4566"));
+        let other = cache.path_of("default", Some("eu-west-1"), Some("http://localhost:4567"));
+
+        assert_eq!(default, PathBuf::from("/cache/default/eu-west-1.json"));
+        assert_eq!(
+            local,
+            PathBuf::from("/cache/default/http___localhost_4566/eu-west-1.json")
+        );
+        assert_ne!(local, other);
+    }
+}
@@ -0,0 +1,109 @@
+use aws_sdk_ssm::types::InstanceInformation;
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+const HEADERS: [&str; 8] = [
+    "NAME",
+    "INSTANCE ID",
+    "ACCOUNT",
+    "REGION",
+    "PLATFORM",
+    "AGENT VERSION",
+    "PING STATUS",
+    "IP ADDRESS",
+];
+
+/// Positions of the account and region in the columns.
+const LABEL_COLUMNS: [usize; 2] = [2, 3];
+
+/// Managed instance as listed to pick a target.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Instance {
+    pub instance_id: String,
+
+    /// Value of the Name tag, only known for EC2 instances.
+    pub name: Option<String>,
+    pub computer_name: Option<String>,
+    pub platform: Option<String>,
+    pub agent_version: Option<String>,
+    pub ping_status: Option<String>,
+    pub ip_address: Option<String>,
+
+    /// Profile and region the instance was listed with.
+    pub profile: Option<String>,
+    pub region: Option<String>,
+
+    /// Account of the instance, only looked up when listing several profiles or regions.
+    pub account: Option<String>,
+
+    /// Set when loaded from a cache older than its TTL, until the instances are listed again.
+    #[serde(skip)]
+    pub stale: bool,
+}
+
+impl Instance {
+    /// Returns the Name tag, falling back to the computer name for instances without one.
+    pub fn display_name(&self) -> &str {
+        self.name
+            .as_deref()
+            .or(self.computer_name.as_deref())
+            .unwrap_or_default()
+    }
+
+    /// Returns the values shown for the instance, in the order of [headers].
+    pub fn columns(&self, labelled: bool) -> Vec<&str> {
+        let values = [
+            self.display_name(),
+            &self.instance_id,
+            self.account.as_deref().unwrap_or_default(),
+            self.region.as_deref().unwrap_or_default(),
+            self.platform.as_deref().unwrap_or_default(),
+            self.agent_version.as_deref().unwrap_or_default(),
+            self.ping_status.as_deref().unwrap_or_default(),
+            self.ip_address.as_deref().unwrap_or_default(),
+        ];
+
+        visible(values, labelled)
+    }
+}
+
+/// Returns the headers of the columns, the account and region only when labelled.
+pub fn headers(labelled: bool) -> Vec<&'static str> {
+    visible(HEADERS, labelled)
+}
+
+/// Returns whether the instances come from several profiles or regions, which then label them.
+pub fn labelled(instances: &[Instance]) -> bool {
+    instances
+        .iter()
+        .map(|instance| (&instance.profile, &instance.region))
+        .collect::<HashSet<_>>()
+        .len()
+        > 1
+}
+
+fn visible<T>(values: [T; 8], labelled: bool) -> Vec<T> {
+    values
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| labelled || !LABEL_COLUMNS.contains(index))
+        .map(|(_, value)| value)
+        .collect()
+}
+
+impl From<InstanceInformation> for Instance {
+    fn from(information: InstanceInformation) -> Self {
+        Self {
+            instance_id: information.instance_id.unwrap_or_default(),
+            computer_name: information.computer_name,
+            platform: information.platform_name,
+            agent_version: information.agent_version,
+            ping_status: information
+                .ping_status
+                .map(|status| status.as_str().to_string()),
+            ip_address: information.ip_address,
+            ..Self::default()
+        }
+    }
+}
@@ -0,0 +1,5 @@
+pub mod cache;
+pub mod instance;
+pub mod scope;
+pub mod selector;
+pub mod source;
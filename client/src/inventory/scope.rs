@@ -0,0 +1,97 @@
+use crate::inventory::instance::Instance;
+use anyhow::{Context, Result};
+use aws_config::{BehaviorVersion, Region};
+use aws_types::SdkConfig;
+use std::env;
+use std::fmt;
+
+/// Profile and region instances are listed from, sessions on them are started with the same.
+#[derive(Clone, Debug)]
+pub struct Scope {
+    pub profile: Option<String>,
+
+    /// Endpoint of SSM when not the one of the region.
+    pub endpoint_url: Option<String>,
+    pub config: SdkConfig,
+    pub ssm: aws_sdk_ssm::Client,
+    pub ec2: aws_sdk_ec2::Client,
+    pub sts: aws_sdk_sts::Client,
+}
+
+impl Scope {
+    /// Loads the AWS configuration of the profile and region, the defaults of the environment
+    /// when not set.
+    pub async fn load(
+        profile: Option<String>,
+        region: Option<String>,
+        endpoint_url: Option<&str>,
+    ) -> Self {
+        let mut loader = aws_config::defaults(BehaviorVersion::latest());
+        if let Some(profile) = &profile {
+            loader = loader.profile_name(profile);
+        }
+        if let Some(region) = region {
+            loader = loader.region(Region::new(region));
+        }
+        Self::new(profile, loader.load().await, endpoint_url)
+    }
+
+    /// Creates the clients of a loaded configuration. The endpoint url only applies to SSM.
+    pub fn new(profile: Option<String>, config: SdkConfig, endpoint_url: Option<&str>) -> Self {
+        let mut ssm_config = aws_sdk_ssm::config::Builder::from(&config);
+        if let Some(endpoint_url) = endpoint_url {
+            ssm_config = ssm_config.endpoint_url(endpoint_url);
+        }
+
+        Self {
+            profile,
+            endpoint_url: endpoint_url.map(str::to_string),
+            ssm: aws_sdk_ssm::Client::from_conf(ssm_config.build()),
+            ec2: aws_sdk_ec2::Client::new(&config),
+            sts: aws_sdk_sts::Client::new(&config),
+            config,
+        }
+    }
+
+    /// Returns the name of the profile the configuration was loaded from, the one of
+    /// `AWS_PROFILE` when not set, like the SDK.
+    pub fn profile_name(&self) -> String {
+        self.profile
+            .clone()
+            .or_else(|| {
+                env::var("AWS_PROFILE")
+                    .ok()
+                    .filter(|profile| !profile.is_empty())
+            })
+            .unwrap_or_else(|| "default".to_string())
+    }
+
+    pub fn region(&self) -> Option<&str> {
+        self.config.region().map(Region::as_ref)
+    }
+
+    /// Returns whether the instance was listed from this scope.
+    pub fn contains(&self, instance: &Instance) -> bool {
+        instance.profile == self.profile && instance.region.as_deref() == self.region()
+    }
+
+    /// Returns the id of the account the credentials belong to.
+    pub async fn account(&self) -> Result<String> {
+        self.sts
+            .get_caller_identity()
+            .send()
+            .await?
+            .account
+            .context("GetCallerIdentity returned no account")
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let region = self.region().unwrap_or("default region");
+        match &self.profile {
+            Some(profile) => write!(f, "{} in {}", profile, region),
+            None => write!(f, "{}", region),
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use crate::inventory::instance::Instance;
+use anyhow::{bail, Error, Result};
+use aws_sdk_ssm::types::InstanceInformationStringFilter;
+use std::fmt;
+use std::net::IpAddr;
+use std::str::FromStr;
+
+/// Selects the instances a target refers to, several selectors separated by commas must all
+/// match, e.g. `tag:env=prod,Name=web-1`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Selector {
+    /// `i-0123456789abcdef0` for EC2 instances or `mi-0123456789abcdef0` for hybrid ones.
+    InstanceId(String),
+
+    /// `Name=web-1` or `tag:Key=Value`.
+    Tag { key: String, value: String },
+
+    /// Address reported by the agent, usually the private IP.
+    IpAddress(IpAddr),
+
+    /// Host name reported by the agent, the domain can be left out.
+    ComputerName(String),
+}
+
+impl Selector {
+    /// Parses the selectors of a target.
+    pub fn parse_all(target: &str) -> Result<Vec<Self>> {
+        let selectors = target
+            .split(',')
+            .map(str::trim)
+            .filter(|selector| !selector.is_empty())
+            .map(Self::from_str)
+            .collect::<Result<Vec<_>>>()?;
+
+        if selectors.is_empty() {
+            bail!("Empty target {:?}", target);
+        }
+
+        Ok(selectors)
+    }
+
+    /// Returns the filter SSM applies for the selector, the others are matched once listed.
+    pub fn filter(&self) -> Result<Option<InstanceInformationStringFilter>> {
+        let (key, value) = match self {
+            Self::InstanceId(id) => ("InstanceIds".to_string(), id),
+            Self::Tag { key, value } => (format!("tag:{}", key), value),
+            Self::IpAddress(_) | Self::ComputerName(_) => return Ok(None),
+        };
+
+        Ok(Some(
+            InstanceInformationStringFilter::builder()
+                .key(key)
+                .values(value)
+                .build()?,
+        ))
+    }
+
+    /// Returns whether the instance matches, tags other than Name are left to the SSM filter.
+    pub fn matches(&self, instance: &Instance) -> bool {
+        match self {
+            Self::InstanceId(id) => instance.instance_id == *id,
+            Self::Tag { key, value } if key == "Name" && instance.name.is_some() => {
+                instance.name.as_deref() == Some(value)
+            }
+            Self::Tag { .. } => true,
+            Self::IpAddress(ip) => {
+                instance
+                    .ip_address
+                    .as_deref()
+                    .and_then(|address| address.parse::<IpAddr>().ok())
+                    == Some(*ip)
+            }
+            Self::ComputerName(name) => instance.computer_name.as_deref().is_some_and(|computer| {
+                let host = computer.split('.').next().unwrap_or_default();
+                computer.eq_ignore_ascii_case(name) || host.eq_ignore_ascii_case(name)
+            }),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(selector: &str) -> Result<Self> {
+        if let Some(tag) = selector.strip_prefix("tag:") {
+            let Some((key, value)) = tag.split_once('=') else {
+                bail!(
+                    "Invalid tag selector {:?}, expected tag:Key=Value",
+                    selector
+                );
+            };
+            if key.is_empty() {
+                bail!("Invalid tag selector {:?}, the key is empty", selector);
+            }
+
+            return Ok(Self::Tag {
+                key: key.to_string(),
+                value: value.to_string(),
+            });
+        }
+
+        if let Some(name) = selector.strip_prefix("Name=") {
+            return Ok(Self::Tag {
+                key: "Name".to_string(),
+                value: name.to_string(),
+            });
+        }
+
+        if is_instance_id(selector) {
+            return Ok(Self::InstanceId(selector.to_string()));
+        }
+
+        if let Ok(ip) = selector.parse() {
+            return Ok(Self::IpAddress(ip));
+        }
+
+        if selector.contains('=') {
+            bail!(
+                "Invalid selector {:?}, tags are selected with tag:Key=Value",
+                selector
+            );
+        }
+
+        Ok(Self::ComputerName(selector.to_string()))
+    }
+}
+
+impl fmt::Display for Selector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::InstanceId(id) => write!(f, "{}", id),
+            Self::Tag { key, value } if key == "Name" => write!(f, "Name={}", value),
+            Self::Tag { key, value } => write!(f, "tag:{}={}", key, value),
+            Self::IpAddress(ip) => write!(f, "{}", ip),
+            Self::ComputerName(name) => write!(f, "{}", name),
+        }
+    }
+}
+
+/// Returns whether the value is an EC2 or hybrid instance id, e.g. `i-0123456789abcdef0`.
+fn is_instance_id(value: &str) -> bool {
+    let id = value
+        .strip_prefix("i-")
+        .or_else(|| value.strip_prefix("mi-"));
+
+    id.is_some_and(|id| id.len() >= 8 && id.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(selector: &str) -> Selector {
+        selector.parse().unwrap()
+    }
+
+    fn tag(key: &str, value: &str) -> Selector {
+        Selector::Tag {
+            key: key.to_string(),
+            value: value.to_string(),
+        }
+    }
+
+    #[test]
+    fn parses_selectors() {
+        assert_eq!(
+            parse("i-0123456789abcdef0"),
+            Selector::InstanceId("i-0123456789abcdef0".to_string())
+        );
+        assert_eq!(
+            parse("mi-0123456789abcdef0"),
+            Selector::InstanceId("mi-0123456789abcdef0".to_string())
+        );
+        assert_eq!(parse("Name=web-1"), tag("Name", "web-1"));
+        assert_eq!(parse("Name="), tag("Name", ""));
+        assert_eq!(parse("tag:env=prod"), tag("env", "prod"));
+        assert_eq!(parse("tag:a=b=c"), tag("a", "b=c"));
+        assert_eq!(parse("10.0.0.1"), Selector::IpAddress([10, 0, 0, 1].into()));
+        assert_eq!(
+            parse("fd00::1"),
+            Selector::IpAddress("fd00::1".parse().unwrap())
+        );
+        assert_eq!(parse("web-1"), Selector::ComputerName("web-1".to_string()));
+    }
+
+    #[test]
+    fn parses_short_or_non_hex_ids_as_computer_names() {
+        assert_eq!(
+            parse("i-0123"),
+            Selector::ComputerName("i-0123".to_string())
+        );
+        assert_eq!(
+            parse("mi-internal-host"),
+            Selector::ComputerName("mi-internal-host".to_string())
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_selectors() {
+        for invalid in ["tag:env", "tag:=prod", "env=prod"] {
+            assert!(invalid.parse::<Selector>().is_err(), "{:?}", invalid);
+        }
+        assert!(Selector::parse_all(" , ").is_err());
+    }
+
+    #[test]
+    fn parses_every_selector_of_a_target() {
+        assert_eq!(
+            Selector::parse_all("tag:env=prod, Name=web-1,").unwrap(),
+            [tag("env", "prod"), tag("Name", "web-1")]
+        );
+    }
+
+    #[test]
+    fn displays_as_parsed() {
+        for selector in [
+            "i-0123456789abcdef0",
+            "Name=web-1",
+            "tag:env=prod",
+            "10.0.0.1",
+            "web-1",
+        ] {
+            assert_eq!(parse(selector).to_string(), selector);
+        }
+    }
+
+    #[test]
+    fn filters_ids_and_tags_in_ssm() {
+        let filter = |selector: &str| {
+            parse(selector)
+                .filter()
+                .unwrap()
+                .map(|filter| (filter.key().to_string(), filter.values().to_vec()))
+        };
+
+        assert_eq!(
+            filter("i-0123456789abcdef0"),
+            Some((
+                "InstanceIds".to_string(),
+                vec!["i-0123456789abcdef0".to_string()]
+            ))
+        );
+        assert_eq!(
+            filter("tag:env=prod"),
+            Some(("tag:env".to_string(), vec!["prod".to_string()]))
+        );
+        assert_eq!(filter("10.0.0.1"), None);
+        assert_eq!(filter("web-1"), None);
+    }
+
+    #[test]
+    fn matches_instances() {
+        let instance = Instance {
+            instance_id: "i-0123456789abcdef0".to_string(),
+            name: Some("web-1".to_string()),
+            computer_name: Some("ip-10-0-0-1.ec2.internal".to_string()),
+            ip_address: Some("10.0.0.1".to_string()),
+            ..Default::default()
+        };
+
+        assert!(parse("i-0123456789abcdef0").matches(&instance));
+        assert!(!parse("i-0fedcba9876543210").matches(&instance));
+        assert!(parse("Name=web-1").matches(&instance));
+        assert!(!parse("Name=web-2").matches(&instance));
+        assert!(parse("tag:env=prod").matches(&instance));
+        assert!(parse("10.0.0.1").matches(&instance));
+        assert!(!parse("10.0.0.2").matches(&instance));
+        assert!(parse("ip-10-0-0-1").matches(&instance));
+        assert!(parse("IP-10-0-0-1.EC2.INTERNAL").matches(&instance));
+        assert!(!parse("ip-10-0-0").matches(&instance));
+    }
+
+    #[test]
+    fn leaves_name_tags_to_ssm_without_a_known_name() {
+        let hybrid = Instance {
+            instance_id: "mi-0123456789abcdef0".to_string(),
+            ..Default::default()
+        };
+
+        assert!(parse("Name=db-box").matches(&hybrid));
+        assert!(!parse("db-box").matches(&hybrid));
+    }
+}
@@ -0,0 +1,438 @@
+use crate::inventory::cache::InventoryCache;
+use crate::inventory::instance::Instance;
+use crate::inventory::scope::Scope;
+use anyhow::Result;
+use aws_sdk_ec2::types::Filter;
+use aws_sdk_ssm::types::InstanceInformationStringFilter;
+use futures_util::future::{self, BoxFuture};
+use std::collections::HashMap;
+use tracing::{debug, warn};
+
+/// Instance ids per DescribeInstances request, the limit of values in a filter.
+const NAME_TAG_BATCH_SIZE: usize = 200;
+
+/// Lists the managed instances that can be targeted, implemented by a stub to resolve targets
+/// without AWS in the tests.
+pub trait InstanceSource: Send + Sync {
+    /// Lists the instances matching all the filters, every instance without filters.
+    fn instances(
+        &self,
+        filters: Vec<InstanceInformationStringFilter>,
+    ) -> BoxFuture<'_, Result<Vec<Instance>>>;
+}
+
+/// Lists the instances registered with SSM in every scope concurrently, with their Name tags
+/// from EC2. Instances are labelled with the account they belong to when listing several
+/// scopes. Every instance of a scope listed is stored in the cache.
+pub struct AwsInstanceSource {
+    scopes: Vec<Scope>,
+    cache: Option<InventoryCache>,
+}
+
+impl AwsInstanceSource {
+    pub fn new(scopes: Vec<Scope>, cache: Option<InventoryCache>) -> Self {
+        Self { scopes, cache }
+    }
+}
+
+impl InstanceSource for AwsInstanceSource {
+    fn instances(
+        &self,
+        filters: Vec<InstanceInformationStringFilter>,
+    ) -> BoxFuture<'_, Result<Vec<Instance>>> {
+        Box::pin(async move {
+            let label_accounts = self.scopes.len() > 1;
+            let cache = self.cache.as_ref().filter(|_| filters.is_empty());
+            let results = future::join_all(
+                self.scopes
+                    .iter()
+                    .map(|scope| scope_instances(scope, filters.clone(), label_accounts)),
+            )
+            .await;
+
+            // A region that is not enabled must not hide the instances of the others.
+            let mut instances = Vec::new();
+            let mut errors = Vec::new();
+            for (scope, result) in self.scopes.iter().zip(results) {
+                match result {
+                    Ok(scope_instances) => {
+                        if let Some(cache) = cache {
+                            if let Err(e) = cache.store(scope, &scope_instances) {
+                                warn!("Failed to cache the instances of {}: {:#}", scope, e);
+                            }
+                        }
+                        instances.extend(scope_instances);
+                    }
+                    Err(e) => {
+                        errors.push(e.context(format!("Failed to list the instances of {}", scope)))
+                    }
+                }
+            }
+
+            if !errors.is_empty() && errors.len() == self.scopes.len() {
+                return Err(errors.remove(0));
+            }
+            for e in errors {
+                warn!("{:#}", e);
+            }
+
+            Ok(instances)
+        })
+    }
+}
+
+/// Lists the instances of a scope, paginating through all of them.
+async fn scope_instances(
+    scope: &Scope,
+    filters: Vec<InstanceInformationStringFilter>,
+    label_account: bool,
+) -> Result<Vec<Instance>> {
+    let mut pages = scope
+        .ssm
+        .describe_instance_information()
+        .set_filters((!filters.is_empty()).then_some(filters))
+        .into_paginator()
+        .items()
+        .send();
+
+    let mut instances = Vec::new();
+    while let Some(information) = pages.next().await {
+        let mut instance = Instance::from(information?);
+        instance.profile = scope.profile.clone();
+        instance.region = scope.region().map(str::to_string);
+        instances.push(instance);
+    }
+    debug!("Found {} managed instances in {}", instances.len(), scope);
+
+    let account = async {
+        match label_account {
+            true => Some(scope.account().await),
+            false => None,
+        }
+    };
+    let (names, account) = tokio::join!(name_tags(scope, &instances), account);
+
+    // Names and accounts only help picking, so instances are still listed without them.
+    match names {
+        Ok(mut names) => {
+            for instance in &mut instances {
+                instance.name = names.remove(&instance.instance_id);
+            }
+        }
+        Err(e) => warn!(
+            "Failed to describe the Name tags of the instances in {}: {:#}",
+            scope, e
+        ),
+    }
+    match account {
+        Some(Ok(account)) => {
+            for instance in &mut instances {
+                instance.account = Some(account.clone());
+            }
+        }
+        Some(Err(e)) => warn!("Failed to get the account of {}: {:#}", scope, e),
+        None => {}
+    }
+
+    Ok(instances)
+}
+
+/// Returns the Name tags of the EC2 instances, hybrid instances have no tags in EC2.
+async fn name_tags(scope: &Scope, instances: &[Instance]) -> Result<HashMap<String, String>> {
+    let ids = instances
+        .iter()
+        .map(|instance| instance.instance_id.clone())
+        .filter(|id| id.starts_with("i-"))
+        .collect::<Vec<_>>();
+
+    let mut names = HashMap::new();
+    for batch in ids.chunks(NAME_TAG_BATCH_SIZE) {
+        // A filter rather than instance ids, as unknown ids fail the whole request.
+        let mut reservations = scope
+            .ec2
+            .describe_instances()
+            .filters(
+                Filter::builder()
+                    .name("instance-id")
+                    .set_values(Some(batch.to_vec()))
+                    .build(),
+            )
+            .into_paginator()
+            .items()
+            .send();
+
+        while let Some(reservation) = reservations.next().await {
+            for instance in reservation?.instances.unwrap_or_default() {
+                let name = instance
+                    .tags
+                    .unwrap_or_default()
+                    .into_iter()
+                    .find(|tag| tag.key.as_deref() == Some("Name"))
+                    .and_then(|tag| tag.value);
+
+                if let (Some(id), Some(name)) = (instance.instance_id, name) {
+                    names.insert(id, name);
+                }
+            }
+        }
+    }
+
+    Ok(names)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use aws_config::{BehaviorVersion, Region};
+    use aws_sdk_ssm::config::{Credentials, SharedCredentialsProvider};
+    use aws_types::SdkConfig;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+
+    /// AWS account serving one page of DescribeInstanceInformation per instance.
+    struct Account {
+        id: &'static str,
+        instances: &'static [&'static str],
+        pages_listed: AtomicUsize,
+    }
+
+    impl Account {
+        /// Answers a request to SSM, EC2 or STS with its content type and body.
+        fn respond(&self, headers: &str, body: &str) -> (&'static str, String) {
+            if headers.contains("AmazonSSM.DescribeInstanceInformation") {
+                self.pages_listed.fetch_add(1, Ordering::SeqCst);
+                let request: serde_json::Value = serde_json::from_str(body).unwrap();
+                let page = request["NextToken"]
+                    .as_str()
+                    .map_or(0, |token| token.parse().unwrap());
+
+                let mut response = serde_json::json!({
+                    "InstanceInformationList": [{
+                        "InstanceId": self.instances[page],
+                        "PingStatus": "Online",
+                    }],
+                });
+                if page + 1 < self.instances.len() {
+                    response["NextToken"] = (page + 1).to_string().into();
+                }
+                ("application/x-amz-json-1.1", response.to_string())
+            } else if body.contains("Action=DescribeInstances") {
+                // Only the instances of the filter, hybrid instances are unknown to EC2.
+                let items = self
+                    .instances
+                    .iter()
+                    .filter(|id| {
+                        body.split('&')
+                            .any(|pair| pair.ends_with(&format!("={}", id)))
+                    })
+                    .map(|id| {
+                        format!(
+                            "<item><instanceId>{0}</instanceId><tagSet><item><key>Name</key>\
+                             <value>name-{0}</value></item></tagSet></item>",
+                            id
+                        )
+                    })
+                    .collect::<String>();
+                let response = format!(
+                    "<DescribeInstancesResponse><reservationSet><item><instancesSet>{}\
+                     </instancesSet></item></reservationSet></DescribeInstancesResponse>",
+                    items
+                );
+                ("text/xml", response)
+            } else if body.contains("Action=GetCallerIdentity") {
+                let response = format!(
+                    "<GetCallerIdentityResponse><GetCallerIdentityResult><Account>{}</Account>\
+                     </GetCallerIdentityResult></GetCallerIdentityResponse>",
+                    self.id
+                );
+                ("text/xml", response)
+            } else {
+                panic!("unexpected request {}", headers);
+            }
+        }
+    }
+
+    /// Serves the account locally, returning its endpoint.
+    async fn serve(account: Arc<Account>) -> String {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint_url = format!("http://{}", listener.local_addr().unwrap());
+
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let (headers, body) = read_request(&mut stream).await;
+                let (content_type, response) = account.respond(&headers, &body);
+
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: {}\r\nContent-Length: {}\r\n\
+                     Connection: close\r\n\r\n",
+                    content_type,
+                    response.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        endpoint_url
+    }
+
+    /// Reads the headers and the body of a request.
+    async fn read_request(stream: &mut TcpStream) -> (String, String) {
+        let mut request = Vec::new();
+        let mut buffer = [0; 4096];
+        loop {
+            let read = stream.read(&mut buffer).await.unwrap();
+            assert!(read > 0, "request ended early");
+            request.extend_from_slice(&buffer[..read]);
+
+            let request = String::from_utf8_lossy(&request);
+            let Some((headers, body)) = request.split_once("\r\n\r\n") else {
+                continue;
+            };
+            let length = headers
+                .lines()
+                .find_map(|line| {
+                    let (name, value) = line.split_once(':')?;
+                    name.eq_ignore_ascii_case("content-length")
+                        .then(|| value.trim().parse::<usize>().unwrap())
+                })
+                .unwrap_or(0);
+            if body.len() >= length {
+                return (headers.to_string(), body.to_string());
+            }
+        }
+    }
+
+    fn account(id: &'static str, instances: &'static [&'static str]) -> Arc<Account> {
+        Arc::new(Account {
+            id,
+            instances,
+            pages_listed: AtomicUsize::new(0),
+        })
+    }
+
+    fn scope(profile: &str, region: &str, endpoint_url: String) -> Scope {
+        let config = SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new(region.to_string()))
+            .endpoint_url(endpoint_url)
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "access-key",
+                "secret-key",
+                None,
+                None,
+                "test",
+            )))
+            .build();
+
+        Scope::new(Some(profile.to_string()), config, None)
+    }
+
+    /// Returns the id, name, profile, region and account of every instance.
+    fn labels(instances: &[Instance]) -> Vec<[Option<&str>; 5]> {
+        instances
+            .iter()
+            .map(|instance| {
+                [
+                    Some(instance.instance_id.as_str()),
+                    instance.name.as_deref(),
+                    instance.profile.as_deref(),
+                    instance.region.as_deref(),
+                    instance.account.as_deref(),
+                ]
+            })
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn lists_every_page_of_every_scope() {
+        let dev = account("111111111111", &["i-0dev1", "i-0dev2", "mi-0dev3"]);
+        let prod = account("222222222222", &["i-0prod1"]);
+        let source = AwsInstanceSource::new(
+            vec![
+                scope("dev", "eu-west-1", serve(Arc::clone(&dev)).await),
+                scope("prod", "us-east-1", serve(Arc::clone(&prod)).await),
+            ],
+            None,
+        );
+
+        let instances = source.instances(Vec::new()).await.unwrap();
+
+        assert_eq!(dev.pages_listed.load(Ordering::SeqCst), 3);
+        assert_eq!(prod.pages_listed.load(Ordering::SeqCst), 1);
+        let dev = [Some("dev"), Some("eu-west-1"), Some("111111111111")];
+        let prod = [Some("prod"), Some("us-east-1"), Some("222222222222")];
+        assert_eq!(
+            labels(&instances),
+            [
+                [
+                    Some("i-0dev1"),
+                    Some("name-i-0dev1"),
+                    dev[0],
+                    dev[1],
+                    dev[2]
+                ],
+                [
+                    Some("i-0dev2"),
+                    Some("name-i-0dev2"),
+                    dev[0],
+                    dev[1],
+                    dev[2]
+                ],
+                [Some("mi-0dev3"), None, dev[0], dev[1], dev[2]],
+                [
+                    Some("i-0prod1"),
+                    Some("name-i-0prod1"),
+                    prod[0],
+                    prod[1],
+                    prod[2]
+                ],
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn labels_accounts_only_across_several_scopes() {
+        let dev = account("111111111111", &["i-0dev1"]);
+        let source =
+            AwsInstanceSource::new(vec![scope("dev", "eu-west-1", serve(dev).await)], None);
+
+        let instances = source.instances(Vec::new()).await.unwrap();
+
+        assert_eq!(
+            labels(&instances),
+            [[
+                Some("i-0dev1"),
+                Some("name-i-0dev1"),
+                Some("dev"),
+                Some("eu-west-1"),
+                None
+            ]]
+        );
+    }
+
+    #[tokio::test]
+    async fn lists_the_scopes_that_answer() {
+        let dev = account("111111111111", &["i-0dev1"]);
+        let unreachable = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let unreachable_url = format!("http://{}", unreachable.local_addr().unwrap());
+        drop(unreachable);
+
+        let source = AwsInstanceSource::new(
+            vec![
+                scope("dev", "eu-west-1", serve(dev).await),
+                scope("prod", "us-east-1", unreachable_url.clone()),
+            ],
+            None,
+        );
+        let instances = source.instances(Vec::new()).await.unwrap();
+        assert_eq!(instances.len(), 1);
+        assert_eq!(instances[0].instance_id, "i-0dev1");
+
+        let source =
+            AwsInstanceSource::new(vec![scope("prod", "us-east-1", unreachable_url)], None);
+        assert!(source.instances(Vec::new()).await.is_err());
+    }
+}
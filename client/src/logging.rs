@@ -0,0 +1,71 @@
+use crate::cli::GlobalOptions;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use tracing::level_filters::LevelFilter;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::EnvFilter;
+
+/// Number of daily log files kept.
+const LOG_FILES: usize = 7;
+
+/// Writes the logs to a daily log file, or to stderr when requested or when there is no state
+/// directory. Sessions own the terminal, logs written to it would mix with the remote output.
+/// The level of the configuration applies unless given on the command line or in RUST_LOG.
+pub fn init(options: &GlobalOptions, default_level: Option<LevelFilter>) {
+    let level = options
+        .log_level()
+        .or(default_level)
+        .unwrap_or(LevelFilter::WARN);
+    let filter = EnvFilter::builder().with_default_directive(level.into());
+    let filter = match options.log_level() {
+        Some(_) => filter.parse_lossy(""),
+        None => filter.from_env_lossy(),
+    };
+
+    let file = (!options.log_stderr).then(log_file).flatten();
+
+    match file {
+        Some(file) => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(file)
+            .init(),
+        None => tracing_subscriber::fmt()
+            .with_env_filter(filter)
+            .with_writer(io::stderr)
+            .with_target(false)
+            .without_time()
+            .compact()
+            .init(),
+    }
+}
+
+/// Returns the directory of the log files, `ssm-term` in the state directory of the user.
+fn log_dir() -> Option<PathBuf> {
+    Some(dirs::state_dir().or_else(dirs::cache_dir)?.join("ssm-term"))
+}
+
+fn log_file() -> Option<RollingFileAppender> {
+    let dir = log_dir()?;
+
+    // The appender does not create the directory itself.
+    fs::create_dir_all(&dir)
+        .map_err(|e| {
+            eprintln!(
+                "Failed to create the log directory {}: {}",
+                dir.display(),
+                e
+            )
+        })
+        .ok()?;
+
+    RollingFileAppender::builder()
+        .rotation(Rotation::DAILY)
+        .filename_prefix("ssm-term")
+        .filename_suffix("log")
+        // Older files are pruned whenever the appender opens a new file.
+        .max_log_files(LOG_FILES)
+        .build(&dir)
+        .map_err(|e| eprintln!("Failed to open the log file in {}: {}", dir.display(), e))
+        .ok()
+}
@@ -1,3 +0,0 @@
-pub mod channel_closed;
-pub mod output_stream_data;
-pub mod pause_publication;
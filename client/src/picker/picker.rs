@@ -0,0 +1,345 @@
+use crate::inventory::instance::Instance;
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use fuzzy_matcher::skim::SkimMatcherV2;
+use fuzzy_matcher::FuzzyMatcher;
+
+/// Outcome of a key press that closes the picker.
+#[derive(Debug, Clone, PartialEq)]
+pub enum PickerAction {
+    Connect(Box<Instance>),
+    Cancel,
+}
+
+/// State of the instance picker, the instances matching the query are ordered by their fuzzy
+/// score. Kept apart from the terminal so it can be driven by key events alone.
+pub struct Picker {
+    instances: Vec<Instance>,
+
+    /// Text each instance is matched against, in the order of the instances.
+    haystacks: Vec<String>,
+    matcher: SkimMatcherV2,
+    query: String,
+
+    /// Indexes of the instances matching the query, best match first.
+    matches: Vec<usize>,
+
+    /// Position of the highlighted instance in the matches.
+    selected: usize,
+
+    /// Position of the first match shown, follows the selection when scrolling.
+    offset: usize,
+}
+
+impl Picker {
+    pub fn new(instances: Vec<Instance>) -> Self {
+        let haystacks = instances.iter().map(haystack).collect();
+
+        let mut picker = Self {
+            instances,
+            haystacks,
+            matcher: SkimMatcherV2::default().smart_case(),
+            query: String::new(),
+            matches: Vec::new(),
+            selected: 0,
+            offset: 0,
+        };
+        picker.update_matches();
+
+        picker
+    }
+
+    /// Replaces the instances, e.g. once listed again, keeping the query and the selection on
+    /// the same instance while it is still listed.
+    pub fn set_instances(&mut self, instances: Vec<Instance>) {
+        let selected = self
+            .selected_instance()
+            .map(|instance| (instance.instance_id.clone(), instance.region.clone()));
+
+        self.haystacks = instances.iter().map(haystack).collect();
+        self.instances = instances;
+        self.update_matches();
+
+        if let Some((id, region)) = selected {
+            let position = self
+                .matches()
+                .position(|instance| instance.instance_id == id && instance.region == region);
+            self.selected = position.unwrap_or_default();
+        }
+    }
+
+    pub fn query(&self) -> &str {
+        &self.query
+    }
+
+    pub fn instances(&self) -> &[Instance] {
+        &self.instances
+    }
+
+    /// Returns the instances matching the query, best match first.
+    pub fn matches(&self) -> impl Iterator<Item = &Instance> {
+        self.matches.iter().map(|&index| &self.instances[index])
+    }
+
+    pub fn match_count(&self) -> usize {
+        self.matches.len()
+    }
+
+    /// Returns the position of the highlighted instance in the matches.
+    pub fn selected(&self) -> usize {
+        self.selected
+    }
+
+    pub fn selected_instance(&self) -> Option<&Instance> {
+        self.matches
+            .get(self.selected)
+            .map(|&index| &self.instances[index])
+    }
+
+    /// Returns the position of the first match to show in `rows` rows, scrolled so the
+    /// selection stays visible.
+    pub fn scroll(&mut self, rows: usize) -> usize {
+        let rows = rows.max(1);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+        self.offset = self.offset.min(self.matches.len().saturating_sub(rows));
+
+        self.offset
+    }
+
+    /// Applies a key press, `page_size` is the number of instances shown at once. Returns the
+    /// action once the picker should close.
+    pub fn handle_key(&mut self, key: KeyEvent, page_size: usize) -> Option<PickerAction> {
+        let control = key.modifiers.contains(KeyModifiers::CONTROL);
+
+        match key.code {
+            KeyCode::Enter => {
+                return self
+                    .selected_instance()
+                    .map(|instance| PickerAction::Connect(Box::new(instance.clone())));
+            }
+            KeyCode::Esc => return Some(PickerAction::Cancel),
+            KeyCode::Char('c' | 'd' | 'g') if control => return Some(PickerAction::Cancel),
+            KeyCode::Up => self.move_selection(-1),
+            KeyCode::Down => self.move_selection(1),
+            KeyCode::Char('p' | 'k') if control => self.move_selection(-1),
+            KeyCode::Char('n' | 'j') if control => self.move_selection(1),
+            KeyCode::PageUp => self.move_selection(-(page_size.max(1) as isize)),
+            KeyCode::PageDown => self.move_selection(page_size.max(1) as isize),
+            KeyCode::Home => self.selected = 0,
+            KeyCode::End => self.selected = self.matches.len().saturating_sub(1),
+            KeyCode::Char('u') if control => self.set_query(String::new()),
+            KeyCode::Char('w') if control => {
+                let query = self.query.trim_end();
+                let end = query.rfind(char::is_whitespace).map_or(0, |i| i + 1);
+                self.set_query(query[..end].to_string());
+            }
+            KeyCode::Backspace => {
+                let mut query = self.query.clone();
+                query.pop();
+                self.set_query(query);
+            }
+            KeyCode::Char(c) if !control => {
+                let mut query = self.query.clone();
+                query.push(c);
+                self.set_query(query);
+            }
+            _ => {}
+        }
+
+        None
+    }
+
+    fn set_query(&mut self, query: String) {
+        if query != self.query {
+            self.query = query;
+            self.update_matches();
+        }
+    }
+
+    fn move_selection(&mut self, delta: isize) {
+        let last = self.matches.len().saturating_sub(1);
+        self.selected = self.selected.saturating_add_signed(delta).min(last);
+    }
+
+    /// Filters the instances on every word of the query, the best summed score first and the
+    /// order of the instances for equal scores.
+    fn update_matches(&mut self) {
+        let words = self.query.split_whitespace().collect::<Vec<_>>();
+
+        let mut scored = self
+            .haystacks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, haystack)| {
+                words
+                    .iter()
+                    .map(|word| self.matcher.fuzzy_match(haystack, word))
+                    .sum::<Option<i64>>()
+                    .map(|score| (index, score))
+            })
+            .collect::<Vec<_>>();
+        scored.sort_by_key(|&(_, score)| std::cmp::Reverse(score));
+
+        self.matches = scored.into_iter().map(|(index, _)| index).collect();
+        self.selected = 0;
+        self.offset = 0;
+    }
+}
+
+/// Joins the fields shown for an instance, so any of them can be searched.
+fn haystack(instance: &Instance) -> String {
+    [
+        instance.name.as_deref(),
+        Some(instance.instance_id.as_str()),
+        instance.computer_name.as_deref(),
+        instance.platform.as_deref(),
+        instance.ip_address.as_deref(),
+        instance.ping_status.as_deref(),
+        instance.agent_version.as_deref(),
+        instance.account.as_deref(),
+        instance.region.as_deref(),
+    ]
+    .into_iter()
+    .flatten()
+    .collect::<Vec<_>>()
+    .join(" ")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(id: &str, name: &str) -> Instance {
+        Instance {
+            instance_id: id.to_string(),
+            name: Some(name.to_string()),
+            ..Default::default()
+        }
+    }
+
+    fn key(code: KeyCode) -> KeyEvent {
+        KeyEvent::new(code, KeyModifiers::NONE)
+    }
+
+    fn control(c: char) -> KeyEvent {
+        KeyEvent::new(KeyCode::Char(c), KeyModifiers::CONTROL)
+    }
+
+    fn type_query(picker: &mut Picker, query: &str) {
+        for c in query.chars() {
+            assert_eq!(picker.handle_key(key(KeyCode::Char(c)), 10), None);
+        }
+    }
+
+    fn matched_names(picker: &Picker) -> Vec<&str> {
+        picker.matches().map(Instance::display_name).collect()
+    }
+
+    fn selected_id(picker: &Picker) -> Option<&str> {
+        picker
+            .selected_instance()
+            .map(|instance| instance.instance_id.as_str())
+    }
+
+    #[test]
+    fn matches_every_word_of_the_query() {
+        let mut picker = Picker::new(vec![
+            instance("i-01", "web-prod"),
+            instance("i-02", "web-dev"),
+            instance("i-03", "db-prod"),
+        ]);
+        assert_eq!(picker.match_count(), 3);
+
+        type_query(&mut picker, "web prod");
+        assert_eq!(picker.query(), "web prod");
+        assert_eq!(matched_names(&picker), ["web-prod"]);
+
+        picker.handle_key(control('w'), 10);
+        assert_eq!(picker.query(), "web ");
+        assert_eq!(matched_names(&picker), ["web-prod", "web-dev"]);
+
+        picker.handle_key(control('u'), 10);
+        assert_eq!(picker.match_count(), 3);
+    }
+
+    #[test]
+    fn keeps_the_selection_across_a_refresh() {
+        let mut picker = Picker::new(vec![instance("i-01", "web-1"), instance("i-02", "web-2")]);
+        picker.handle_key(key(KeyCode::Down), 10);
+        assert_eq!(selected_id(&picker), Some("i-02"));
+
+        picker.set_instances(vec![
+            instance("i-00", "web-0"),
+            instance("i-01", "web-1"),
+            instance("i-02", "web-2"),
+        ]);
+        assert_eq!(selected_id(&picker), Some("i-02"));
+        assert_eq!(picker.selected(), 2);
+
+        picker.set_instances(vec![instance("i-00", "web-0"), instance("i-01", "web-1")]);
+        assert_eq!(selected_id(&picker), Some("i-00"));
+    }
+
+    #[test]
+    fn keeps_the_query_across_a_refresh() {
+        let mut picker = Picker::new(vec![instance("i-01", "web-1"), instance("i-02", "db-1")]);
+        type_query(&mut picker, "db");
+
+        picker.set_instances(vec![
+            instance("i-01", "web-1"),
+            instance("i-02", "db-1"),
+            instance("i-03", "db-2"),
+        ]);
+        assert_eq!(picker.query(), "db");
+        assert_eq!(matched_names(&picker), ["db-1", "db-2"]);
+    }
+
+    #[test]
+    fn pages_through_the_matches() {
+        let instances = (0..10)
+            .map(|i| instance(&format!("i-{:02}", i), &format!("host-{}", i)))
+            .collect();
+        let mut picker = Picker::new(instances);
+
+        picker.handle_key(key(KeyCode::PageDown), 3);
+        assert_eq!(picker.selected(), 3);
+        assert_eq!(picker.scroll(3), 1);
+
+        picker.handle_key(key(KeyCode::End), 3);
+        assert_eq!(picker.selected(), 9);
+        assert_eq!(picker.scroll(3), 7);
+
+        picker.handle_key(key(KeyCode::PageDown), 3);
+        assert_eq!(picker.selected(), 9);
+
+        picker.handle_key(key(KeyCode::PageUp), 3);
+        assert_eq!(picker.selected(), 6);
+        assert_eq!(picker.scroll(3), 6);
+
+        picker.handle_key(key(KeyCode::Home), 3);
+        picker.handle_key(key(KeyCode::PageUp), 3);
+        assert_eq!(picker.selected(), 0);
+        assert_eq!(picker.scroll(3), 0);
+    }
+
+    #[test]
+    fn closes_on_enter_and_escape() {
+        let mut picker = Picker::new(vec![instance("i-01", "web-1"), instance("i-02", "web-2")]);
+        picker.handle_key(control('n'), 10);
+
+        assert_eq!(
+            picker.handle_key(key(KeyCode::Enter), 10),
+            Some(PickerAction::Connect(Box::new(instance("i-02", "web-2"))))
+        );
+        assert_eq!(
+            picker.handle_key(key(KeyCode::Esc), 10),
+            Some(PickerAction::Cancel)
+        );
+
+        type_query(&mut picker, "nothing");
+        assert_eq!(picker.handle_key(key(KeyCode::Enter), 10), None);
+    }
+}
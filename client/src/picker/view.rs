@@ -0,0 +1,193 @@
+use crate::inventory::instance::{self, Instance};
+use crate::picker::picker::{Picker, PickerAction};
+use anyhow::Result;
+use crossterm::event::{self, Event, KeyEventKind};
+use crossterm::style::{Attribute, Print, SetAttribute};
+use crossterm::terminal::{self, Clear, ClearType};
+use crossterm::{cursor, queue};
+use session_manager::terminal::terminal_guard::TerminalGuard;
+use std::io::{self, Write};
+use std::sync::mpsc::{Receiver, TryRecvError};
+use std::time::Duration;
+use tracing::debug;
+
+const PROMPT: &str = "> ";
+
+/// Lines above the instances, the query, the match count and the header.
+const HEADER_ROWS: u16 = 3;
+
+/// How often the picker checks whether the instances were listed again while idle.
+const REFRESH_POLL_INTERVAL: Duration = Duration::from_millis(100);
+
+/// Opens the picker full screen and returns the instance picked, None when cancelled. Draws on
+/// stderr so stdout can still be redirected. The instances are replaced by those received from
+/// `refresh`, which are being listed again meanwhile.
+pub fn pick(
+    instances: Vec<Instance>,
+    mut refresh: Option<Receiver<Result<Vec<Instance>>>>,
+) -> Result<Option<Instance>> {
+    let mut columns = Columns::new(&instances);
+    let mut picker = Picker::new(instances);
+    let mut failure = None;
+
+    let mut stderr = io::stderr();
+    let _screen = TerminalGuard::alternate_screen()?;
+
+    loop {
+        if let Some(receiver) = &refresh {
+            match receiver.try_recv() {
+                Ok(Ok(instances)) => {
+                    columns = Columns::new(&instances);
+                    picker.set_instances(instances);
+                    refresh = None;
+                }
+                Ok(Err(e)) => {
+                    failure = Some(format!("listing failed: {:#}", e));
+                    refresh = None;
+                }
+                Err(TryRecvError::Empty) => {}
+                Err(TryRecvError::Disconnected) => refresh = None,
+            }
+        }
+
+        let status = match (&refresh, &failure) {
+            (Some(_), _) => "listing instances...",
+            (None, Some(failure)) => failure.as_str(),
+            (None, None) => "",
+        };
+
+        let (width, height) = terminal::size()?;
+        let rows = height.saturating_sub(HEADER_ROWS) as usize;
+        draw(&mut stderr, &mut picker, &columns, status, width, rows)?;
+
+        if refresh.is_some() && !event::poll(REFRESH_POLL_INTERVAL)? {
+            continue;
+        }
+
+        match event::read()? {
+            Event::Key(key) if key.kind != KeyEventKind::Release => {
+                match picker.handle_key(key, rows) {
+                    Some(PickerAction::Connect(instance)) => return Ok(Some(*instance)),
+                    Some(PickerAction::Cancel) => return Ok(None),
+                    None => {}
+                }
+            }
+            Event::Resize(width, height) => debug!("Terminal resized to {}x{}", width, height),
+            _ => {}
+        }
+    }
+}
+
+/// Columns shown for the instances, sized for all of them so they do not move while filtering.
+struct Columns {
+    labelled: bool,
+    widths: Vec<usize>,
+}
+
+impl Columns {
+    fn new(instances: &[Instance]) -> Self {
+        let labelled = instance::labelled(instances);
+        let mut widths = instance::headers(labelled)
+            .into_iter()
+            .map(str::len)
+            .collect::<Vec<_>>();
+        for instance in instances {
+            for (width, value) in widths.iter_mut().zip(instance.columns(labelled)) {
+                *width = (*width).max(value.chars().count());
+            }
+        }
+
+        Self { labelled, widths }
+    }
+}
+
+fn draw(
+    out: &mut impl Write,
+    picker: &mut Picker,
+    columns: &Columns,
+    status: &str,
+    width: u16,
+    rows: usize,
+) -> Result<()> {
+    let width = width as usize;
+    let offset = picker.scroll(rows);
+
+    queue!(out, cursor::MoveTo(0, 0))?;
+    line(out, &format!("{}{}", PROMPT, picker.query()), width)?;
+    queue!(out, SetAttribute(Attribute::Dim))?;
+    let stale = picker.instances().iter().filter(|i| i.stale).count();
+    let mut counts = format!("  {}/{}", picker.match_count(), picker.instances().len());
+    if stale > 0 {
+        counts.push_str(&format!(", {} stale", stale));
+    }
+    if !status.is_empty() {
+        counts.push_str(&format!(", {}", status));
+    }
+    line(out, &counts, width)?;
+    queue!(
+        out,
+        SetAttribute(Attribute::Reset),
+        SetAttribute(Attribute::Bold)
+    )?;
+    line(
+        out,
+        &format!(
+            "  {}",
+            format_row(&instance::headers(columns.labelled), &columns.widths)
+        ),
+        width,
+    )?;
+    queue!(out, SetAttribute(Attribute::Reset))?;
+
+    let selected = picker.selected();
+    for (position, instance) in picker.matches().enumerate().skip(offset).take(rows) {
+        let row = format_row(&instance.columns(columns.labelled), &columns.widths);
+
+        // Instances from a stale cache are dimmed until listed again.
+        if instance.stale {
+            queue!(out, SetAttribute(Attribute::Dim))?;
+        }
+        if position == selected {
+            queue!(out, SetAttribute(Attribute::Reverse))?;
+            line(out, &format!("{}{}", PROMPT, row), width)?;
+        } else {
+            line(out, &format!("  {}", row), width)?;
+        }
+        queue!(out, SetAttribute(Attribute::Reset))?;
+    }
+
+    let cursor = (PROMPT.len() + picker.query().chars().count()).min(width.saturating_sub(1));
+    queue!(
+        out,
+        Clear(ClearType::FromCursorDown),
+        cursor::MoveTo(cursor as u16, 0)
+    )?;
+    out.flush()?;
+
+    Ok(())
+}
+
+/// Writes a line cut to the width of the terminal, clearing what was drawn before. Moves to the
+/// next line without a newline, which would scroll on the last line.
+fn line(out: &mut impl Write, text: &str, width: usize) -> Result<()> {
+    let text = text.chars().take(width).collect::<String>();
+    queue!(
+        out,
+        Print(text),
+        Clear(ClearType::UntilNewLine),
+        cursor::MoveToNextLine(1)
+    )?;
+
+    Ok(())
+}
+
+fn format_row(values: &[&str], widths: &[usize]) -> String {
+    values
+        .iter()
+        .zip(widths)
+        .map(|(value, width)| format!("{:width$}", value, width = width))
+        .collect::<Vec<_>>()
+        .join("  ")
+        .trim_end()
+        .to_string()
+}
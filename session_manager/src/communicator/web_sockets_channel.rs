@@ -11,39 +11,88 @@
 // either express or implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
-use crate::config::config::{PING_TIME_INTERVAL, RETRY_ATTEMPT};
-use anyhow::{bail, Result};
+use crate::config::config::{CLOSE_CHANNEL_TIMEOUT, PING_TIME_INTERVAL, RETRY_ATTEMPT};
+use anyhow::{anyhow, bail, Result};
 use bytes::Bytes;
+use futures_util::stream::SplitSink;
 use futures_util::{SinkExt, StreamExt};
 use log::{debug, error};
-use std::error::Error;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::Duration;
 use tokio::net::TcpStream;
 use tokio::sync::Mutex;
-use tokio::time::Interval;
+use tokio::task::JoinHandle;
+use tokio::time;
 use tokio_websockets::{MaybeTlsStream, Message, WebSocketStream};
 
+type WebSocketSink = SplitSink<WebSocketStream<MaybeTlsStream<TcpStream>>, Message>;
+pub type OnMessageHandler = Box<dyn Fn(Vec<u8>) + Send + Sync>;
+pub type OnErrorHandler = Box<dyn Fn(anyhow::Error) + Send + Sync>;
+
+#[allow(async_fn_in_trait)]
 pub trait IWebSocketChannel {
     fn initialize(&mut self, channel_url: String, channel_token: String);
     async fn open(&mut self) -> Result<()>;
-    fn close(&mut self) -> Result<()>;
+    async fn close(&mut self) -> Result<()>;
     async fn send_message(&mut self, message: WebSocketMessage) -> Result<()>;
-    fn start_pings(&self, ping_interval: Interval);
+    fn start_pings(&mut self, ping_interval: Duration);
     fn get_channel_token(&self) -> &str;
     fn get_stream_url(&self) -> &str;
+    fn set_stream_url(&mut self, url: String);
     fn set_channel_token(&mut self, token: String);
-    fn set_on_error(&mut self, on_error_handler: Box<dyn Fn(Box<dyn Error>)>);
-    fn set_on_message(&mut self, on_message_handler: Box<dyn Fn(Vec<u8>)>);
+    fn set_on_error(&mut self, on_error_handler: OnErrorHandler);
+    fn set_on_message(&mut self, on_message_handler: OnMessageHandler);
 }
 
-struct WebSocketChannel {
+pub struct WebSocketChannel {
     url: String,
-    on_message: Arc<Mutex<Option<Box<dyn Fn(Vec<u8>)>>>>,
-    on_error: Arc<Mutex<Option<Box<dyn Fn(Box<dyn Error>)>>>>,
-    is_open: Arc<bool>,
-    write_lock: Mutex<()>,
-    connection: Option<Arc<Mutex<WebSocketStream<MaybeTlsStream<TcpStream>>>>>,
+    ping_interval: Duration,
+    on_message: Arc<std::sync::Mutex<Option<OnMessageHandler>>>,
+    on_error: Arc<std::sync::Mutex<Option<OnErrorHandler>>>,
+    is_open: Arc<AtomicBool>,
+    connection: Option<Arc<Mutex<WebSocketSink>>>,
     channel_token: String,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Default for WebSocketChannel {
+    fn default() -> Self {
+        Self {
+            url: String::new(),
+            ping_interval: PING_TIME_INTERVAL,
+            on_message: Arc::default(),
+            on_error: Arc::default(),
+            is_open: Arc::default(),
+            connection: None,
+            channel_token: String::new(),
+            tasks: Vec::new(),
+        }
+    }
+}
+
+impl WebSocketChannel {
+    pub fn new(channel_url: String, channel_token: String) -> Self {
+        let mut channel = Self::default();
+        channel.initialize(channel_url, channel_token);
+        channel
+    }
+
+    /// Sets the interval of the pings of the connections opened from now on.
+    pub fn set_ping_interval(&mut self, ping_interval: Duration) {
+        self.ping_interval = ping_interval;
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.is_open.load(Ordering::SeqCst)
+    }
+
+    /// Stops the listening and ping routines of the current connection.
+    fn abort_tasks(&mut self) {
+        for task in self.tasks.drain(..) {
+            task.abort();
+        }
+    }
 }
 
 impl IWebSocketChannel for WebSocketChannel {
@@ -53,28 +102,32 @@ impl IWebSocketChannel for WebSocketChannel {
     }
 
     async fn open(&mut self) -> Result<()> {
-        self.write_lock = Mutex::new(());
+        self.abort_tasks();
 
         let (ws, _response) = tokio_websockets::ClientBuilder::new()
-            .uri(&self.url)? // TODO: Error log
+            .uri(&self.url)
+            .map_err(|e| anyhow!("Invalid channel url {}: {}", &self.url, e))?
             .connect()
             .await?;
 
-        self.connection = Some(Arc::new(Mutex::new(ws)));
-        self.is_open = Arc::new(true);
-        self.start_pings(PING_TIME_INTERVAL);
+        let (sink, mut stream) = ws.split();
+
+        // Every connection gets its own open flag so routines of a previous connection
+        // can never observe the state of a reopened one.
+        let is_open = Arc::new(AtomicBool::new(true));
+        self.is_open = Arc::clone(&is_open);
+        self.connection = Some(Arc::new(Mutex::new(sink)));
+        self.start_pings(self.ping_interval);
 
-        let is_open = Arc::clone(&self.is_open);
         let url = self.url.clone();
-        let ws = Arc::clone(&self.connection.as_ref().unwrap());
         let on_message = Arc::clone(&self.on_message);
         let on_error = Arc::clone(&self.on_error);
 
-        tokio::spawn(async move {
+        self.tasks.push(tokio::spawn(async move {
             let mut retry_count = 0;
 
             loop {
-                if !*is_open {
+                if !is_open.load(Ordering::SeqCst) {
                     debug!(
                         "Ending the channel listening routine since the channel is closed: {}",
                         &url
@@ -82,21 +135,32 @@ impl IWebSocketChannel for WebSocketChannel {
                     break;
                 }
 
-                let mut ws = ws.lock().await;
-                match ws.next().await {
+                match stream.next().await {
                     Some(Ok(message)) => {
-                        if !message.is_binary() || !message.is_text() {
+                        if message.is_ping() || message.is_pong() {
+                            continue;
+                        }
+
+                        if message.is_close() {
+                            debug!("The channel was closed by the remote: {}", &url);
+                            if is_open.swap(false, Ordering::SeqCst) {
+                                if let Some(handler) = &*on_error.lock().unwrap() {
+                                    handler(anyhow!("Channel closed by the remote: {}", &url));
+                                }
+                            }
+                            break;
+                        }
+
+                        if !message.is_binary() && !message.is_text() {
                             error!("Invalid message type. We only accept UTF-8 or binary encoded text.");
                             continue;
                         }
 
                         retry_count = 0;
 
-                        if let Some(handler) = &*on_message.lock().await {
+                        if let Some(handler) = &*on_message.lock().unwrap() {
                             handler(message.as_payload().to_vec());
                         }
-
-                        continue;
                     }
                     Some(Err(e)) => {
                         retry_count += 1;
@@ -107,34 +171,53 @@ impl IWebSocketChannel for WebSocketChannel {
                                 RETRY_ATTEMPT
                             );
 
-                            if let Some(handler) = &*on_error.lock().await {
-                                handler(e.into());
+                            if is_open.swap(false, Ordering::SeqCst) {
+                                if let Some(handler) = &*on_error.lock().unwrap() {
+                                    handler(e.into());
+                                }
                             }
 
                             break;
                         }
 
                         debug!("An error happened when receiving the message. Retried times: {}, Error: {}", retry_count, e);
-                        continue;
                     }
                     None => {
                         debug!("The channel is closed: {}", &url);
+                        if is_open.swap(false, Ordering::SeqCst) {
+                            if let Some(handler) = &*on_error.lock().unwrap() {
+                                handler(anyhow!("Connection lost: {}", &url));
+                            }
+                        }
                         break;
                     }
                 };
             }
-        });
+        }));
 
         Ok(())
     }
 
-    fn close(&mut self) -> Result<()> {
+    async fn close(&mut self) -> Result<()> {
+        self.is_open.store(false, Ordering::SeqCst);
+        self.abort_tasks();
+
+        if let Some(connection) = self.connection.take() {
+            // Sends a close frame and waits for the one of the service.
+            let mut ws = connection.lock().await;
+            match time::timeout(CLOSE_CHANNEL_TIMEOUT, ws.close()).await {
+                Ok(Ok(())) => {}
+                Ok(Err(e)) => debug!("Failed to close the channel {}: {}", &self.url, e),
+                Err(_) => debug!("Timed out closing the channel {}", &self.url),
+            }
+        }
+
         Ok(())
     }
 
     async fn send_message(&mut self, message: WebSocketMessage) -> Result<()> {
-        if !*self.is_open {
-            return Ok(());
+        if !self.is_open() {
+            bail!("Can not send message when connection is closed.");
         }
 
         if let Some(connection) = &self.connection {
@@ -144,10 +227,7 @@ impl IWebSocketChannel for WebSocketChannel {
             };
 
             let mut ws = connection.lock().await;
-            let _ = self.write_lock.lock().await;
-            if ws.send(message).await.is_err() {
-                return Ok(());
-            }
+            ws.send(message).await?;
         } else {
             bail!("Connection is not open");
         }
@@ -155,17 +235,19 @@ impl IWebSocketChannel for WebSocketChannel {
         Ok(())
     }
 
-    fn start_pings(&self, ping_interval: Interval) {
+    fn start_pings(&mut self, ping_interval: Duration) {
         if let Some(connection) = &self.connection {
             let connection = Arc::clone(connection);
             let is_open = Arc::clone(&self.is_open);
-            let mut ping_interval = ping_interval;
 
-            tokio::spawn(async move {
+            self.tasks.push(tokio::spawn(async move {
+                let mut ping_interval = tokio::time::interval(ping_interval);
+                ping_interval.tick().await;
+
                 loop {
                     ping_interval.tick().await;
 
-                    if !*is_open {
+                    if !is_open.load(Ordering::SeqCst) {
                         break;
                     }
 
@@ -175,7 +257,7 @@ impl IWebSocketChannel for WebSocketChannel {
                         break;
                     }
                 }
-            });
+            }));
         }
     }
 
@@ -187,16 +269,26 @@ impl IWebSocketChannel for WebSocketChannel {
         &self.url
     }
 
+    fn set_stream_url(&mut self, url: String) {
+        self.url = url;
+    }
+
     fn set_channel_token(&mut self, token: String) {
         self.channel_token = token;
     }
 
-    fn set_on_error(&mut self, on_error_handler: Box<dyn Fn(Box<dyn Error>)>) {
-        self.on_error = Some(on_error_handler);
+    fn set_on_error(&mut self, on_error_handler: OnErrorHandler) {
+        *self.on_error.lock().unwrap() = Some(on_error_handler);
+    }
+
+    fn set_on_message(&mut self, on_message_handler: OnMessageHandler) {
+        *self.on_message.lock().unwrap() = Some(on_message_handler);
     }
+}
 
-    fn set_on_message(&mut self, on_message_handler: Box<dyn Fn(Vec<u8>)>) {
-        self.on_message = Some(on_message_handler);
+impl Drop for WebSocketChannel {
+    fn drop(&mut self) {
+        self.abort_tasks();
     }
 }
 
@@ -11,9 +11,15 @@
 // either express or implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
+use crate::retry::retryer::RepeatableExponentialRetryer;
+use crate::version::agent_version::AgentVersion;
+use anyhow::{bail, Result};
+use serde::Deserialize;
 use std::time::Duration;
-use tokio::time::Interval;
 
+/// Version of the session manager plugin protocol implemented by this client, the agent enables
+/// features such as the handshake based on it.
+pub const CLIENT_VERSION: &str = "1.2.553.0";
 pub const ROLE_PUBLISH_SUBSCRIBE: &str = "publish_subscribe";
 pub const MESSAGE_SCHEMA_VERSION: &str = "1.0";
 pub const DEFAULT_TRANSMISSION_TIMEOUT: Duration = Duration::from_millis(200);
@@ -33,7 +39,15 @@ pub const DATA_CHANNEL_NUM_MAX_RETRIES: u32 = 5;
 pub const DATA_CHANNEL_RETRY_INITIAL_DELAY_MILLIS: u64 = 100;
 pub const DATA_CHANNEL_RETRY_MAX_INTERVAL_MILLIS: u64 = 5000;
 pub const RETRY_ATTEMPT: u32 = 5;
-pub const PING_TIME_INTERVAL: Interval = tokio::time::interval(Duration::from_secs(60 * 5)); // 5 minutes
+pub const PING_TIME_INTERVAL: Duration = Duration::from_secs(60 * 5); // 5 minutes
+pub const RESIZE_SLEEP_INTERVAL: Duration = Duration::from_millis(500);
+pub const RESIZE_DEBOUNCE_INTERVAL: Duration = Duration::from_millis(100);
+/// How long terminating a session waits for the agent to close the channel.
+pub const TERMINATE_SESSION_TIMEOUT: Duration = Duration::from_secs(2);
+/// Character starting the escape sequences of shell sessions, such as `~.` to disconnect.
+pub const DEFAULT_ESCAPE_CHAR: u8 = b'~';
+/// How long closing the web socket waits for the close frame of the service.
+pub const CLOSE_CHANNEL_TIMEOUT: Duration = Duration::from_secs(1);
 
 // Plugin names
 pub const SHELL_PLUGIN_NAME: &str = "Standard_Stream";
@@ -41,8 +55,80 @@ pub const PORT_PLUGIN_NAME: &str = "Port";
 pub const INTERACTIVE_COMMANDS_PLUGIN_NAME: &str = "InteractiveCommands";
 pub const NON_INTERACTIVE_COMMANDS_PLUGIN_NAME: &str = "NonInteractiveCommands";
 
+// Session documents
+pub const INTERACTIVE_COMMAND_DOCUMENT_NAME: &str = "AWS-StartInteractiveCommand";
+pub const NON_INTERACTIVE_COMMAND_DOCUMENT_NAME: &str = "AWS-StartNonInteractiveCommand";
+pub const PORT_FORWARDING_DOCUMENT_NAME: &str = "AWS-StartPortForwardingSession";
+pub const PORT_FORWARDING_TO_REMOTE_HOST_DOCUMENT_NAME: &str =
+    "AWS-StartPortForwardingSessionToRemoteHost";
+
 // Agent Versions
-pub const TERMINATE_SESSION_FLAG_SUPPORTED_AFTER_THIS_AGENT_VERSION: &str = "2.3.722.0";
-pub const TCP_MULTIPLEXING_SUPPORTED_AFTER_THIS_AGENT_VERSION: &str = "3.0.196.0";
-pub const TCP_MULTIPLEXING_WITH_SMUX_KEEP_ALIVE_DISABLED_AFTER_THIS_AGENT_VERSION: &str =
-    "3.1.1511.0";
+pub const TERMINATE_SESSION_FLAG_SUPPORTED_AFTER_THIS_AGENT_VERSION: AgentVersion =
+    AgentVersion::new(2, 3, 722, 0);
+pub const TCP_MULTIPLEXING_SUPPORTED_AFTER_THIS_AGENT_VERSION: AgentVersion =
+    AgentVersion::new(3, 0, 196, 0);
+pub const TCP_MULTIPLEXING_WITH_SMUX_KEEP_ALIVE_DISABLED_AFTER_THIS_AGENT_VERSION: AgentVersion =
+    AgentVersion::new(3, 1, 1511, 0);
+
+/// Settings of a session users may tune, defaulting to the constants above.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SessionConfig {
+    /// Interval of the pings keeping the web socket open.
+    pub ping_interval_secs: u64,
+
+    /// Resends of an unacknowledged message before the session is considered lost.
+    pub resend_max_attempts: u32,
+
+    /// Retries of opening the data channel or resuming the session before giving up.
+    pub reconnect_max_retries: u32,
+    pub reconnect_initial_delay_millis: u64,
+    pub reconnect_max_delay_millis: u64,
+
+    /// How long terminating a session waits for the agent to close the channel.
+    pub terminate_timeout_millis: u64,
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        Self {
+            ping_interval_secs: PING_TIME_INTERVAL.as_secs(),
+            resend_max_attempts: RESEND_MAX_ATTEMPT,
+            reconnect_max_retries: DATA_CHANNEL_NUM_MAX_RETRIES,
+            reconnect_initial_delay_millis: DATA_CHANNEL_RETRY_INITIAL_DELAY_MILLIS,
+            reconnect_max_delay_millis: DATA_CHANNEL_RETRY_MAX_INTERVAL_MILLIS,
+            terminate_timeout_millis: TERMINATE_SESSION_TIMEOUT.as_millis() as u64,
+        }
+    }
+}
+
+impl SessionConfig {
+    /// Rejects values the session cannot run with.
+    pub fn validate(&self) -> Result<()> {
+        if self.ping_interval_secs == 0 {
+            bail!("ping_interval_secs must be greater than 0");
+        }
+        if self.reconnect_initial_delay_millis > self.reconnect_max_delay_millis {
+            bail!("reconnect_initial_delay_millis must not exceed reconnect_max_delay_millis");
+        }
+
+        Ok(())
+    }
+
+    pub fn ping_interval(&self) -> Duration {
+        Duration::from_secs(self.ping_interval_secs)
+    }
+
+    pub fn terminate_timeout(&self) -> Duration {
+        Duration::from_millis(self.terminate_timeout_millis)
+    }
+
+    /// Returns the retryer for opening the data channel and resuming the session.
+    pub fn reconnect_retryer(&self) -> RepeatableExponentialRetryer {
+        let mut retryer = RepeatableExponentialRetryer::default();
+        retryer.initial_delay = Duration::from_millis(self.reconnect_initial_delay_millis);
+        retryer.max_delay = Duration::from_millis(self.reconnect_max_delay_millis);
+        retryer.max_retries = self.reconnect_max_retries;
+        retryer
+    }
+}
@@ -1,2 +1,3 @@
 /// Config package implement configuration retrieval for session manager apis.
+#[allow(clippy::module_inception)]
 pub mod config;
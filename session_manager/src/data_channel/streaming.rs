@@ -1,19 +1,41 @@
-use crate::communicator::web_sockets_channel::IWebSocketChannel;
-use crate::encryption::encrypter::Encrypter;
-use crate::message::client_message::message::ClientMessage;
-use anyhow::Result;
-use std::any::Any;
+use crate::communicator::web_sockets_channel::{
+    IWebSocketChannel, WebSocketChannel, WebSocketMessage,
+};
+use crate::config::config::SessionConfig;
+use crate::config::config::{
+    CLIENT_VERSION, CLOCK_GRANULARITY, DEFAULT_ROUND_TRIP_TIME, DEFAULT_ROUND_TRIP_TIME_VARIATION,
+    DEFAULT_TRANSMISSION_TIMEOUT, INCOMING_MESSAGE_BUFFER_CAPACITY, MAX_TRANSMISSION_TIMEOUT,
+    OUTGOING_MESSAGE_BUFFER_CAPACITY, RESEND_MAX_ATTEMPT, RESEND_SLEEP_INTERVAL, RTTV_CONSTANT,
+    RTT_CONSTANT,
+};
+use crate::encryption::encrypter::{Encrypter, IEncrypter};
+use crate::message::client_message::message::{
+    AgentSessionState, ChannelClosed, ClientMessage, MessageType, PayloadType, PayloadTypeFlag,
+};
+use crate::message::handshake_message::message::{
+    ActionStatus, ActionType, EncryptionChallengeRequest, EncryptionChallengeResponse,
+    HandshakeCompletePayload, HandshakeRequestPayload, HandshakeResponsePayload,
+    KMSEncryptionRequest, KMSEncryptionResponse, ProcessedClientAction, SessionTypeRequest,
+};
+use crate::retry::retryer::RepeatableExponentialRetryer;
+use crate::service::service::OpenDataChannelInput;
+use crate::session_manager_plugin::session_type::SessionType;
+use crate::version::agent_version::{AgentCapabilities, AgentVersion};
+use anyhow::{anyhow, Result};
+use aws_sdk_kms::Client as KmsClient;
+use log::{debug, error, info, trace, warn};
+use sha2::{Digest, Sha256};
 use std::collections::{HashMap, LinkedList};
-use std::sync::{Arc, Mutex};
+use std::sync::{Arc, Weak};
 use std::time::{Duration, SystemTime};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
 
-struct DataChannel {
-    ws_channel: Arc<dyn IWebSocketChannel>,
-    role: String,
+pub struct DataChannel {
+    ws_channel: WebSocketChannel,
     client_id: String,
     session_id: String,
     target_id: String,
-    is_aws_cli_upgrade_needed: bool,
 
     /// records sequence number of last acknowledged message received over data channel
     expected_sequence_number: i64,
@@ -39,39 +61,774 @@ struct DataChannel {
     retransmission_timeout: Duration,
 
     /// Encrypter to encrypt/decrypt if agent requests encryption
-    encryption: Encrypter,
-    encryption_enabled: bool,
+    kms_client: KmsClient,
+    encryption: Option<Encrypter>,
 
-    /// SessionType
-    session_type: String,
-    is_session_type_set: Mutex<bool>,
-    session_properties: Box<dyn Any>,
+    /// SessionType, set by the handshake or by the first output of agents predating it
+    session_type: Option<SessionType>,
 
     /// Used to detect if resending a streaming message reaches timeout
-    is_stream_message_resend_timeout: Mutex<bool>,
+    is_stream_message_resend_timeout: bool,
+
+    /// Resends of an unacknowledged message before the session is considered lost.
+    resend_max_attempts: u32,
 
     /// Handles data on output stream. Output stream is data outputted by the SSM agent and received here.
     output_stream_handlers: Vec<OutputStreamDataMessageHandler>,
-    is_session_specific_handler_set: bool,
 
-    /// AgentVersion received during handshake
-    agent_version: String,
+    /// Notifies the session layer about connection and channel state changes.
+    events: UnboundedSender<DataChannelEvent>,
+
+    /// AgentVersion received during handshake, zero for agents predating the handshake.
+    agent_version: AgentVersion,
+}
+
+/// Notifications raised by the data channel that need to be handled by the session.
+#[derive(Debug)]
+pub enum DataChannelEvent {
+    /// The underlying web socket dropped and the session needs to be resumed.
+    ConnectionLost(anyhow::Error),
+
+    /// The agent closed the channel.
+    ChannelClosed(ChannelClosed),
+
+    /// The agent reported a change of the session state.
+    AgentSessionState(AgentSessionState),
+
+    /// Stream data accepted by every output stream handler, sent in order with the other events.
+    StreamData(ClientMessage),
+
+    /// The session type is known, either from the handshake or from the first output message.
+    SessionTypeSet(SessionType),
+
+    /// The agent completed the handshake and is about to start the session plugin.
+    HandshakeComplete {
+        agent_version: AgentVersion,
+        customer_message: String,
+    },
+
+    /// The agent asked the client to stop sending stream messages as the remote channel is inactive.
+    PausePublication,
+
+    /// The agent asked the client to start sending stream messages again.
+    StartPublication,
+
+    /// A stream message was resent more than the configured attempts without being acknowledged.
+    ResendTimeout,
 }
 
 struct ListMessageBuffer<T> {
-    messages: Mutex<LinkedList<T>>,
+    messages: LinkedList<T>,
     capacity: usize,
 }
 
 struct MapMessageBuffer {
-    messages: Mutex<HashMap<i64, StreamingMessage>>,
+    messages: HashMap<i64, StreamingMessage>,
+    capacity: usize,
 }
 
 struct StreamingMessage {
     content: Vec<u8>,
     sequence_number: i64,
     last_sent_time: SystemTime,
-    resend_attempt: Option<i32>,
+    resend_attempt: u32,
 }
 
-type OutputStreamDataMessageHandler = Box<dyn Fn(ClientMessage) -> Result<bool> + Send + Sync>;
+pub type OutputStreamDataMessageHandler = Box<dyn Fn(&ClientMessage) -> Result<bool> + Send + Sync>;
+
+impl DataChannel {
+    /// Creates the data channel for a session and starts its message processing and resend routines.
+    /// The routines end once the returned data channel is dropped.
+    pub fn new(
+        kms_client: KmsClient,
+        client_id: String,
+        session_id: String,
+        target_id: String,
+        stream_url: String,
+        token_value: String,
+    ) -> (Arc<Mutex<Self>>, UnboundedReceiver<DataChannelEvent>) {
+        let (events, events_rx) = mpsc::unbounded_channel();
+        let (incoming, incoming_rx) = mpsc::unbounded_channel();
+
+        let mut ws_channel = WebSocketChannel::new(stream_url, token_value);
+        ws_channel.set_on_message(Box::new(move |raw_message| {
+            let _ = incoming.send(raw_message);
+        }));
+
+        let on_error_events = events.clone();
+        ws_channel.set_on_error(Box::new(move |e| {
+            let _ = on_error_events.send(DataChannelEvent::ConnectionLost(e));
+        }));
+
+        let data_channel = Arc::new(Mutex::new(Self {
+            ws_channel,
+            client_id,
+            session_id,
+            target_id,
+            expected_sequence_number: 0,
+            stream_data_sequence_number: 0,
+            outgoing_message_buffer: ListMessageBuffer {
+                messages: LinkedList::new(),
+                capacity: OUTGOING_MESSAGE_BUFFER_CAPACITY,
+            },
+            incoming_message_buffer: MapMessageBuffer {
+                messages: HashMap::new(),
+                capacity: INCOMING_MESSAGE_BUFFER_CAPACITY,
+            },
+            round_trip_time: DEFAULT_ROUND_TRIP_TIME.as_nanos() as f64,
+            round_trip_time_variation: DEFAULT_ROUND_TRIP_TIME_VARIATION as f64,
+            retransmission_timeout: DEFAULT_TRANSMISSION_TIMEOUT,
+            kms_client,
+            encryption: None,
+            session_type: None,
+            is_stream_message_resend_timeout: false,
+            resend_max_attempts: RESEND_MAX_ATTEMPT,
+            output_stream_handlers: Vec::new(),
+            events,
+            agent_version: AgentVersion::default(),
+        }));
+
+        Self::spawn_incoming_message_processor(Arc::downgrade(&data_channel), incoming_rx);
+        Self::spawn_resend_stream_data_message_scheduler(Arc::downgrade(&data_channel));
+
+        (data_channel, events_rx)
+    }
+
+    /// Applies the settings of the session, to the connections opened from now on.
+    pub fn set_config(&mut self, config: &SessionConfig) {
+        self.resend_max_attempts = config.resend_max_attempts;
+        self.ws_channel.set_ping_interval(config.ping_interval());
+    }
+
+    /// Opens the web socket connection and authenticates it with the channel token.
+    pub async fn open(&mut self) -> Result<()> {
+        self.ws_channel.open().await?;
+        self.finalize_data_channel_handshake().await
+    }
+
+    /// Opens the web socket connection, retrying failed attempts with the given retryer.
+    pub async fn open_with_retry(
+        &mut self,
+        mut retryer: RepeatableExponentialRetryer,
+    ) -> Result<()> {
+        loop {
+            match self.open().await {
+                Ok(()) => return Ok(()),
+                Err(e) => {
+                    self.ws_channel.close().await?;
+                    if !retryer.backoff(&e).await {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reopens the web socket connection with the given stream url and token, then resends
+    /// all stream messages which have not been acknowledged by the agent yet.
+    pub async fn reconnect(
+        &mut self,
+        stream_url: String,
+        token_value: String,
+        retryer: RepeatableExponentialRetryer,
+    ) -> Result<()> {
+        debug!(
+            "Reconnecting with stream url {} and sequence number {}",
+            &stream_url, self.stream_data_sequence_number
+        );
+
+        self.ws_channel.close().await?;
+        self.ws_channel.set_stream_url(stream_url);
+        self.ws_channel.set_channel_token(token_value);
+        self.open_with_retry(retryer).await?;
+
+        // Attempts made over the dropped connection do not count against the new one.
+        self.is_stream_message_resend_timeout = false;
+        let now = SystemTime::now();
+        for message in self.outgoing_message_buffer.messages.iter_mut() {
+            message.resend_attempt = 0;
+            message.last_sent_time = now;
+        }
+
+        self.resend_unacknowledged_messages().await
+    }
+
+    pub async fn close(&mut self) -> Result<()> {
+        self.ws_channel.close().await
+    }
+
+    pub fn get_stream_url(&self) -> &str {
+        self.ws_channel.get_stream_url()
+    }
+
+    pub fn get_expected_sequence_number(&self) -> i64 {
+        self.expected_sequence_number
+    }
+
+    pub fn get_stream_data_sequence_number(&self) -> i64 {
+        self.stream_data_sequence_number
+    }
+
+    pub fn get_agent_version(&self) -> AgentVersion {
+        self.agent_version
+    }
+
+    /// Returns the features supported by the agent, known once the handshake started.
+    pub fn get_agent_capabilities(&self) -> AgentCapabilities {
+        self.agent_version.capabilities()
+    }
+
+    pub fn get_session_type(&self) -> Option<&SessionType> {
+        self.session_type.as_ref()
+    }
+
+    /// Sets the session type once, later calls are ignored.
+    pub fn set_session_type(&mut self, session_type: SessionType) {
+        if self.session_type.is_some() {
+            return;
+        }
+
+        debug!("Session type set to {}", &session_type);
+        self.session_type = Some(session_type.clone());
+        let _ = self
+            .events
+            .send(DataChannelEvent::SessionTypeSet(session_type));
+    }
+
+    pub fn register_output_stream_handler(&mut self, handler: OutputStreamDataMessageHandler) {
+        self.output_stream_handlers.push(handler);
+    }
+
+    /// Sends the token through the data channel to acknowledge the connection.
+    async fn finalize_data_channel_handshake(&mut self) -> Result<()> {
+        debug!(
+            "Sending token through data channel {} to acknowledge connection",
+            self.ws_channel.get_stream_url()
+        );
+
+        let input = OpenDataChannelInput::new(
+            &uuid::Uuid::new_v4().to_string(),
+            self.ws_channel.get_channel_token(),
+            &self.client_id,
+        );
+
+        self.ws_channel
+            .send_message(WebSocketMessage::Text(serde_json::to_string(&input)?))
+            .await
+    }
+
+    /// Sends a flag message to the agent.
+    pub async fn send_flag(&mut self, flag: PayloadTypeFlag) -> Result<()> {
+        self.send_input_data_message(PayloadType::Flag, ClientMessage::flag_payload(flag))
+            .await
+    }
+
+    /// Sends a stream data message of the given payload type to the agent.
+    pub async fn send_input_data_message(
+        &mut self,
+        payload_type: PayloadType,
+        input_data: Vec<u8>,
+    ) -> Result<()> {
+        // Today 'enter' is taken as 'next line' in session shell
+        let flags = if self.stream_data_sequence_number == 0 {
+            1
+        } else {
+            0
+        };
+
+        let input_data = match &self.encryption {
+            Some(encryption) if payload_type == PayloadType::Output => {
+                encryption.encrypt(&input_data)?
+            }
+            _ => input_data,
+        };
+
+        let message = ClientMessage::new(
+            MessageType::InputStreamData,
+            self.stream_data_sequence_number,
+            flags,
+            payload_type,
+            input_data,
+        );
+
+        // A message which can not be sent because the connection dropped stays buffered
+        // and is resent once the session is resumed.
+        let content = message.serialize_client_message();
+
+        self.add_data_to_outgoing_message_buffer(StreamingMessage {
+            content: content.clone(),
+            sequence_number: self.stream_data_sequence_number,
+            last_sent_time: SystemTime::now(),
+            resend_attempt: 0,
+        });
+        self.stream_data_sequence_number += 1;
+
+        if let Err(e) = self.send_message(content).await {
+            warn!(
+                "Stream data message {} will be resent: {}",
+                message.sequence_number, e
+            );
+        }
+
+        Ok(())
+    }
+
+    async fn send_message(&mut self, input: Vec<u8>) -> Result<()> {
+        self.ws_channel
+            .send_message(WebSocketMessage::Binary(input))
+            .await
+    }
+
+    /// Acknowledges a stream data message. A lost acknowledge makes the agent resend the message,
+    /// which is then acknowledged again as a duplicate, so failures are only logged.
+    async fn send_acknowledge_message(&mut self, message: &ClientMessage) -> Result<()> {
+        let ack = ClientMessage::new_acknowledge(message)?;
+        if let Err(e) = self.send_message(ack.serialize_client_message()).await {
+            debug!(
+                "Unable to acknowledge message {}: {}",
+                message.sequence_number, e
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Resends every buffered stream message in sequence order.
+    async fn resend_unacknowledged_messages(&mut self) -> Result<()> {
+        let messages: Vec<Vec<u8>> = self
+            .outgoing_message_buffer
+            .messages
+            .iter()
+            .map(|message| message.content.clone())
+            .collect();
+
+        debug!("Resending {} unacknowledged messages", messages.len());
+
+        for content in messages {
+            self.send_message(content).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Resends the oldest unacknowledged message once it exceeds the retransmission timeout.
+    async fn resend_stream_data_message(&mut self) {
+        let retransmission_timeout = self.retransmission_timeout;
+        let Some(message) = self.outgoing_message_buffer.messages.front_mut() else {
+            return;
+        };
+
+        let elapsed = message.last_sent_time.elapsed().unwrap_or_default();
+        if elapsed <= retransmission_timeout {
+            return;
+        }
+
+        debug!(
+            "Resend stream data message {} for the {} attempt.",
+            message.sequence_number, message.resend_attempt
+        );
+
+        if message.resend_attempt >= self.resend_max_attempts {
+            warn!(
+                "Message {} was resent over {} times.",
+                message.sequence_number, self.resend_max_attempts
+            );
+
+            if !self.is_stream_message_resend_timeout {
+                self.is_stream_message_resend_timeout = true;
+                let _ = self.events.send(DataChannelEvent::ResendTimeout);
+            }
+            return;
+        }
+
+        message.resend_attempt += 1;
+        message.last_sent_time = SystemTime::now();
+        let content = message.content.clone();
+
+        if let Err(e) = self.send_message(content).await {
+            debug!("Unable to send stream data message: {}", e);
+        }
+    }
+
+    /// Handles a raw message received over the web socket.
+    async fn output_message_handler(&mut self, raw_message: Vec<u8>) -> Result<()> {
+        let output_message = ClientMessage::deserialize_client_message(&raw_message)?;
+
+        match output_message.message_type {
+            MessageType::OutputStreamData => self.handle_output_message(output_message).await,
+            MessageType::Acknowledge => self.handle_acknowledge_message(&output_message),
+            MessageType::ChannelClosed => {
+                let channel_closed = output_message.deserialize_channel_closed_message()?;
+                debug!(
+                    "Session {} closed by the agent: {}",
+                    &self.session_id, &channel_closed.output
+                );
+                let _ = self
+                    .events
+                    .send(DataChannelEvent::ChannelClosed(channel_closed));
+                Ok(())
+            }
+            MessageType::AgentSessionState => {
+                let state = output_message.deserialize_agent_session_state_message()?;
+                debug!("Session {} is {}", &self.session_id, &state.session_state);
+                let _ = self.events.send(DataChannelEvent::AgentSessionState(state));
+                Ok(())
+            }
+            MessageType::StartPublication => {
+                let _ = self.events.send(DataChannelEvent::StartPublication);
+                Ok(())
+            }
+            MessageType::PausePublication => {
+                let _ = self.events.send(DataChannelEvent::PausePublication);
+                Ok(())
+            }
+            message_type => {
+                warn!("Invalid message type received: {}", message_type);
+                Ok(())
+            }
+        }
+    }
+
+    /// Processes stream data messages in sequence, buffering the ones that arrive early and
+    /// acknowledging the ones that were already delivered before a reconnect.
+    async fn handle_output_message(&mut self, output_message: ClientMessage) -> Result<()> {
+        if output_message.sequence_number == self.expected_sequence_number {
+            trace!(
+                "Process new incoming stream data message. Sequence Number: {}",
+                output_message.sequence_number
+            );
+
+            if !self.process_stream_data_message(&output_message).await? {
+                warn!(
+                    "Stream data message with sequence number {} is not processed as session handler is not ready.",
+                    output_message.sequence_number
+                );
+                return Ok(());
+            }
+
+            self.expected_sequence_number += 1;
+            self.send_acknowledge_message(&output_message).await?;
+
+            return self.process_incoming_message_buffer_items().await;
+        }
+
+        debug!(
+            "Unexpected sequence message received. Received Sequence Number: {}. Expected Sequence Number: {}",
+            output_message.sequence_number, self.expected_sequence_number
+        );
+
+        if output_message.sequence_number < self.expected_sequence_number {
+            // Already delivered, the acknowledge got lost while the connection was down.
+            return self.send_acknowledge_message(&output_message).await;
+        }
+
+        if self.incoming_message_buffer.messages.len() < self.incoming_message_buffer.capacity {
+            self.send_acknowledge_message(&output_message).await?;
+            self.incoming_message_buffer.messages.insert(
+                output_message.sequence_number,
+                StreamingMessage {
+                    content: output_message.serialize_client_message(),
+                    sequence_number: output_message.sequence_number,
+                    last_sent_time: SystemTime::now(),
+                    resend_attempt: 0,
+                },
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Processes the buffered messages which are now in sequence.
+    async fn process_incoming_message_buffer_items(&mut self) -> Result<()> {
+        while let Some(buffered) = self
+            .incoming_message_buffer
+            .messages
+            .remove(&self.expected_sequence_number)
+        {
+            trace!(
+                "Process stream data message from IncomingMessageBuffer. Sequence Number: {}",
+                buffered.sequence_number
+            );
+
+            let message = ClientMessage::deserialize_client_message(&buffered.content)?;
+            if let Err(e) = self.process_stream_data_message(&message).await {
+                error!("Failed to process stream data message: {}", e);
+            }
+
+            self.expected_sequence_number += 1;
+        }
+
+        Ok(())
+    }
+
+    /// Handles handshake payloads internally and passes every other payload to the output stream handlers.
+    async fn process_stream_data_message(&mut self, message: &ClientMessage) -> Result<bool> {
+        match message.payload_type {
+            PayloadType::HandshakeRequestPayloadType => {
+                self.handle_handshake_request(message).await?;
+                Ok(true)
+            }
+            PayloadType::HandshakeCompletePayloadType => {
+                self.handle_handshake_complete(message)?;
+                Ok(true)
+            }
+            PayloadType::EncChallengeRequest => {
+                self.handle_encryption_challenge_request(message).await?;
+                Ok(true)
+            }
+            PayloadType::Output | PayloadType::StdErr | PayloadType::ExitCode
+                if self.encryption.is_some() =>
+            {
+                let mut message = message.clone();
+                if let Some(encryption) = &self.encryption {
+                    message.payload = encryption.decrypt(&message.payload)?;
+                }
+                self.process_first_message(&message);
+                self.process_output_message_with_handlers(&message)
+            }
+            _ => {
+                self.process_first_message(message);
+                self.process_output_message_with_handlers(message)
+            }
+        }
+    }
+
+    /// Usually the session type is set by the handshake which is the first message, but older
+    /// agents do not perform the handshake and only support shell sessions.
+    fn process_first_message(&mut self, message: &ClientMessage) {
+        if self.session_type.is_none() && message.payload_type == PayloadType::Output {
+            warn!("Setting session type to shell based on PayloadType!");
+            self.set_session_type(SessionType::default());
+        }
+    }
+
+    /// Processes the actions requested by the agent and sends the handshake response.
+    async fn handle_handshake_request(&mut self, message: &ClientMessage) -> Result<()> {
+        let request: HandshakeRequestPayload = message.deserialize_payload()?;
+        info!(
+            "Handshake request received from agent version {}",
+            &request.agent_version
+        );
+        self.agent_version = request.agent_version.parse().unwrap_or_else(|e| {
+            warn!("{}, assuming an agent without optional features", e);
+            AgentVersion::default()
+        });
+
+        let mut response = HandshakeResponsePayload {
+            client_version: CLIENT_VERSION.to_string(),
+            processed_client_actions: Vec::new(),
+            errors: Vec::new(),
+        };
+
+        for action in request.requested_client_actions {
+            let result = match action.action_type {
+                ActionType::KMSEncryption => {
+                    self.process_kms_encryption_handshake_action(action.action_parameters)
+                        .await
+                }
+                ActionType::SessionType => {
+                    self.process_session_type_handshake_action(action.action_parameters)
+                }
+            };
+
+            let processed_action = match result {
+                Ok(action_result) => ProcessedClientAction {
+                    action_type: action.action_type,
+                    action_status: ActionStatus::Success,
+                    action_result,
+                    error: String::new(),
+                },
+                Err(e) => {
+                    let error = format!("Failed to process action {}: {}", action.action_type, e);
+                    response.errors.push(error.clone());
+                    ProcessedClientAction {
+                        action_type: action.action_type,
+                        action_status: ActionStatus::Failed,
+                        action_result: serde_json::Value::Null,
+                        error,
+                    }
+                }
+            };
+
+            response.processed_client_actions.push(processed_action);
+        }
+
+        self.send_input_data_message(
+            PayloadType::HandshakeResponsePayloadType,
+            serde_json::to_vec(&response)?,
+        )
+        .await
+    }
+
+    /// Generates the data key for the KMS key requested by the agent and enables encryption.
+    async fn process_kms_encryption_handshake_action(
+        &mut self,
+        action_parameters: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let request: KMSEncryptionRequest = serde_json::from_value(action_parameters)?;
+        let context = [
+            ("aws:ssm:SessionId", self.session_id.as_str()),
+            ("aws:ssm:TargetId", self.target_id.as_str()),
+        ];
+
+        let encryption = Encrypter::new(&self.kms_client, request.kms_key_id, &context).await?;
+        let cipher_text_key = encryption.get_encrypted_data_key().to_vec();
+        let response = KMSEncryptionResponse {
+            kms_cipher_text_hash: Sha256::digest(&cipher_text_key).to_vec(),
+            kms_cipher_text_key: cipher_text_key,
+        };
+
+        self.encryption = Some(encryption);
+
+        Ok(serde_json::to_value(response)?)
+    }
+
+    fn process_session_type_handshake_action(
+        &mut self,
+        action_parameters: serde_json::Value,
+    ) -> Result<serde_json::Value> {
+        let request: SessionTypeRequest = serde_json::from_value(action_parameters)?;
+        let session_type = SessionType::from_request(request)?;
+
+        // Unknown session types are kept as is, custom plugins may be registered for them and
+        // the session fails when the registry has no plugin for the type.
+        self.set_session_type(session_type);
+
+        Ok(serde_json::Value::Null)
+    }
+
+    /// Proves to the agent that the client holds the data key by decrypting the challenge and
+    /// encrypting it again with the client's key.
+    async fn handle_encryption_challenge_request(&mut self, message: &ClientMessage) -> Result<()> {
+        let request: EncryptionChallengeRequest = message.deserialize_payload()?;
+        let encryption = self
+            .encryption
+            .as_ref()
+            .ok_or_else(|| anyhow!("Encryption challenge received before encryption was set up"))?;
+
+        let challenge = encryption.encrypt(&encryption.decrypt(&request.challenge)?)?;
+        let response = EncryptionChallengeResponse { challenge };
+
+        self.send_input_data_message(
+            PayloadType::EncChallengeResponse,
+            serde_json::to_vec(&response)?,
+        )
+        .await
+    }
+
+    fn handle_handshake_complete(&mut self, message: &ClientMessage) -> Result<()> {
+        let complete: HandshakeCompletePayload = message.deserialize_payload()?;
+        debug!(
+            "Handshake completed in {:?}",
+            Duration::from_nanos(complete.handshake_time_to_complete.max(0) as u64)
+        );
+
+        let _ = self.events.send(DataChannelEvent::HandshakeComplete {
+            agent_version: self.agent_version,
+            customer_message: complete.customer_message,
+        });
+
+        Ok(())
+    }
+
+    fn process_output_message_with_handlers(&self, message: &ClientMessage) -> Result<bool> {
+        for handler in &self.output_stream_handlers {
+            if !handler(message)? {
+                return Ok(false);
+            }
+        }
+
+        let _ = self
+            .events
+            .send(DataChannelEvent::StreamData(message.clone()));
+
+        Ok(true)
+    }
+
+    fn handle_acknowledge_message(&mut self, message: &ClientMessage) -> Result<()> {
+        let acknowledge = message.deserialize_data_stream_acknowledge_content()?;
+        self.process_acknowledged_message(acknowledge.sequence_number);
+        Ok(())
+    }
+
+    /// Removes the acknowledged message from the outgoing buffer and updates the retransmission timeout.
+    fn process_acknowledged_message(&mut self, sequence_number: i64) {
+        let messages = &mut self.outgoing_message_buffer.messages;
+        let Some(position) = messages
+            .iter()
+            .position(|message| message.sequence_number == sequence_number)
+        else {
+            return;
+        };
+
+        let mut tail = messages.split_off(position);
+        let acknowledged = tail.pop_front();
+        messages.append(&mut tail);
+
+        if let Some(acknowledged) = acknowledged {
+            self.calculate_retransmission_timeout(&acknowledged);
+        }
+    }
+
+    fn calculate_retransmission_timeout(&mut self, message: &StreamingMessage) {
+        let new_round_trip_time = message
+            .last_sent_time
+            .elapsed()
+            .unwrap_or_default()
+            .as_nanos() as f64;
+
+        self.round_trip_time_variation = ((1.0 - RTTV_CONSTANT as f64)
+            * self.round_trip_time_variation)
+            + (RTTV_CONSTANT as f64 * (self.round_trip_time - new_round_trip_time).abs());
+
+        self.round_trip_time = ((1.0 - RTT_CONSTANT as f64) * self.round_trip_time)
+            + (RTT_CONSTANT as f64 * new_round_trip_time);
+
+        let timeout = self.round_trip_time
+            + (CLOCK_GRANULARITY.as_nanos() as f64).max(4.0 * self.round_trip_time_variation);
+
+        // Ensure the retransmission timeout does not exceed the maximum timeout defined
+        self.retransmission_timeout =
+            Duration::from_nanos(timeout as u64).min(MAX_TRANSMISSION_TIMEOUT);
+    }
+
+    fn add_data_to_outgoing_message_buffer(&mut self, message: StreamingMessage) {
+        let buffer = &mut self.outgoing_message_buffer;
+        if buffer.messages.len() >= buffer.capacity {
+            buffer.messages.pop_front();
+        }
+        buffer.messages.push_back(message);
+    }
+
+    fn spawn_incoming_message_processor(
+        data_channel: Weak<Mutex<Self>>,
+        mut incoming: UnboundedReceiver<Vec<u8>>,
+    ) {
+        tokio::spawn(async move {
+            while let Some(raw_message) = incoming.recv().await {
+                let Some(data_channel) = data_channel.upgrade() else {
+                    break;
+                };
+
+                let mut data_channel = data_channel.lock().await;
+                if let Err(e) = data_channel.output_message_handler(raw_message).await {
+                    error!("Failed to process incoming message: {}", e);
+                }
+            }
+        });
+    }
+
+    fn spawn_resend_stream_data_message_scheduler(data_channel: Weak<Mutex<Self>>) {
+        tokio::spawn(async move {
+            loop {
+                tokio::time::sleep(RESEND_SLEEP_INTERVAL).await;
+
+                let Some(data_channel) = data_channel.upgrade() else {
+                    break;
+                };
+
+                data_channel.lock().await.resend_stream_data_message().await;
+            }
+        });
+    }
+}
@@ -34,7 +34,6 @@ pub trait IEncrypter {
 }
 
 pub struct Encrypter {
-    kms_client: KmsClient,
     kms_key_id: String,
     cipher_text_key: Vec<u8>,
     encryption_key: Vec<u8>,
@@ -43,36 +42,36 @@ pub struct Encrypter {
 
 impl Encrypter {
     pub async fn new(
-        kms_client: KmsClient,
+        kms_client: &KmsClient,
         kms_key_id: String,
-        context: (&str, &str),
+        context: &[(&str, &str)],
     ) -> Result<Self> {
-        let keys = Self::generate_encryption_key(&kms_client, &kms_key_id, context).await?;
+        let keys = Self::generate_encryption_key(kms_client, &kms_key_id, context).await?;
+
+        Ok(Self::with_keys(kms_key_id, keys))
+    }
 
-        Ok(Self {
-            kms_client,
+    fn with_keys(kms_key_id: String, keys: Keys) -> Self {
+        Self {
             kms_key_id,
             cipher_text_key: keys.cypher_text_key,
             encryption_key: keys.encryption_key,
             decryption_key: keys.decryption_key,
-        })
+        }
     }
 
     /// Calls KMS to generate a new encryption key.
     async fn generate_encryption_key(
         kms_client: &KmsClient,
         kms_key_id: &str,
-        context: (&str, &str),
+        context: &[(&str, &str)],
     ) -> Result<Keys> {
-        let blobs = kms_generate_data_key(kms_client, kms_key_id, (context.0, context.1)).await?;
+        let blobs = kms_generate_data_key(kms_client, kms_key_id, context).await?;
 
-        let key_size = blobs.plain_text.as_ref().len() / 2;
-
-        Ok(Keys {
-            encryption_key: blobs.plain_text.as_ref()[..key_size].to_vec(),
-            decryption_key: blobs.plain_text.as_ref()[key_size..].to_vec(),
-            cypher_text_key: blobs.cipher_text.as_ref().to_vec(),
-        })
+        Ok(Keys::from_data_key(
+            blobs.plain_text.as_ref(),
+            blobs.cipher_text.as_ref(),
+        ))
     }
 
     /// Gets AEAD which is a GCM cipher mode providing authenticated encryption with associated data.
@@ -112,6 +111,10 @@ impl IEncrypter for Encrypter {
         let key = GenericArray::from_slice(&self.decryption_key);
         let cipher = Aes256Gcm::new(key);
 
+        if cipher_text.len() < NONCE_SIZE {
+            bail!("Unable to decrypt: cipher text is shorter than the nonce");
+        }
+
         // Pull the nonce out of the cipher_text
         let nonce = &cipher_text[..NONCE_SIZE];
         let cipher_text_without_nonce = &cipher_text[NONCE_SIZE..];
@@ -135,3 +138,81 @@ struct Keys {
     decryption_key: Vec<u8>,
     cypher_text_key: Vec<u8>,
 }
+
+impl Keys {
+    /// Splits the data key generated by KMS. The agent encrypts with the first half of the data
+    /// key, so the client decrypts with it.
+    fn from_data_key(plain_text: &[u8], cipher_text: &[u8]) -> Self {
+        let key_size = plain_text.len() / 2;
+
+        Self {
+            decryption_key: plain_text[..key_size].to_vec(),
+            encryption_key: plain_text[key_size..].to_vec(),
+            cypher_text_key: cipher_text.to_vec(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AGENT_KEY: [u8; 32] = [1; 32];
+    const CLIENT_KEY: [u8; 32] = [2; 32];
+
+    fn encrypter() -> Encrypter {
+        let data_key = [AGENT_KEY, CLIENT_KEY].concat();
+        Encrypter::with_keys(
+            "key-id".to_string(),
+            Keys::from_data_key(&data_key, b"encrypted data key"),
+        )
+    }
+
+    /// Encrypts like the other end of the channel, with the nonce in front of the cipher text.
+    fn encrypt_with(key: &[u8], plain_text: &[u8]) -> Vec<u8> {
+        let nonce = [7; NONCE_SIZE];
+        let cipher_text = Encrypter::get_aead(key)
+            .encrypt(GenericArray::from_slice(&nonce), plain_text)
+            .unwrap();
+        [nonce.as_slice(), &cipher_text].concat()
+    }
+
+    fn decrypt_with(key: &[u8], cipher_text: &[u8]) -> Option<Vec<u8>> {
+        let (nonce, cipher_text) = cipher_text.split_at(NONCE_SIZE);
+        Encrypter::get_aead(key)
+            .decrypt(GenericArray::from_slice(nonce), cipher_text)
+            .ok()
+    }
+
+    #[test]
+    fn decrypts_with_the_first_half_of_the_data_key() {
+        let encrypter = encrypter();
+        let from_agent = encrypt_with(&AGENT_KEY, b"output");
+
+        assert_eq!(encrypter.decrypt(&from_agent).unwrap(), b"output");
+        assert!(encrypter
+            .decrypt(&encrypt_with(&CLIENT_KEY, b"output"))
+            .is_err());
+    }
+
+    #[test]
+    fn encrypts_with_the_second_half_of_the_data_key() {
+        let to_agent = encrypter().encrypt(b"input").unwrap();
+
+        assert_eq!(decrypt_with(&CLIENT_KEY, &to_agent).unwrap(), b"input");
+        assert_eq!(decrypt_with(&AGENT_KEY, &to_agent), None);
+    }
+
+    #[test]
+    fn keeps_the_encrypted_data_key_for_the_agent() {
+        let encrypter = encrypter();
+
+        assert_eq!(encrypter.get_encrypted_data_key(), b"encrypted data key");
+        assert_eq!(encrypter.get_kms_key_id(), "key-id");
+    }
+
+    #[test]
+    fn rejects_cipher_text_shorter_than_the_nonce() {
+        assert!(encrypter().decrypt(&[0; NONCE_SIZE - 1]).is_err());
+    }
+}
@@ -17,20 +17,22 @@ use aws_sdk_kms::Client as KmsClient;
 
 const KMS_KEY_SIZE_IN_BYTES: i32 = 64;
 
-async fn new_kms_service() -> Result<KmsClient> {
+pub async fn new_kms_service() -> Result<KmsClient> {
     let config = aws_config::load_from_env().await;
     Ok(aws_sdk_kms::Client::new(&config))
 }
 
-async fn kms_decrypt(
+pub async fn kms_decrypt(
     kms_client: &KmsClient,
     ciphertext_blob: Blob,
-    context: (&str, &str),
+    context: &[(&str, &str)],
 ) -> Result<Blob> {
-    let decrypt = kms_client
-        .decrypt()
-        .ciphertext_blob(ciphertext_blob)
-        .encryption_context(context.0, context.1)
+    let decrypt = context
+        .iter()
+        .fold(
+            kms_client.decrypt().ciphertext_blob(ciphertext_blob),
+            |request, (key, value)| request.encryption_context(*key, *value),
+        )
         .send()
         .await?;
 
@@ -42,13 +44,17 @@ async fn kms_decrypt(
 pub async fn kms_generate_data_key(
     kms_client: &KmsClient,
     kms_key_id: &str,
-    context: (&str, &str),
+    context: &[(&str, &str)],
 ) -> Result<Blobs> {
-    let generate_data_key = kms_client
-        .generate_data_key()
-        .key_id(kms_key_id)
-        .number_of_bytes(KMS_KEY_SIZE_IN_BYTES)
-        .encryption_context(context.0, context.1)
+    let generate_data_key = context
+        .iter()
+        .fold(
+            kms_client
+                .generate_data_key()
+                .key_id(kms_key_id)
+                .number_of_bytes(KMS_KEY_SIZE_IN_BYTES),
+            |request, (key, value)| request.encryption_context(*key, *value),
+        )
         .send()
         .await?;
 
@@ -1,7 +1,11 @@
-mod communicator;
+pub mod communicator;
 pub mod config;
-mod data_channel;
+pub mod data_channel;
 pub mod encryption;
 pub mod message;
+pub mod retry;
 pub mod service;
-mod session_manager_plugin;
+pub mod session_manager_plugin;
+pub mod smux;
+pub mod terminal;
+pub mod version;
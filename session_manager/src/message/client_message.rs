@@ -20,7 +20,9 @@ pub mod message {
     use uuid::Uuid;
 
     /// MessageType represents the type of message.
-    #[derive(Serialize, Deserialize, EnumString, AsRefStr, Display, Debug, PartialEq)]
+    #[derive(
+        Serialize, Deserialize, EnumString, AsRefStr, Display, Clone, Copy, Debug, PartialEq,
+    )]
     #[strum(serialize_all = "snake_case")]
     #[serde(rename_all = "snake_case")]
     pub enum MessageType {
@@ -102,13 +104,71 @@ pub mod message {
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     pub struct ChannelClosed {
-        message_id: Uuid,
-        created_date: String,
-        destination_id: String,
-        session_id: String,
-        message_type: String,
-        schema_version: i32,
-        output: String,
+        pub message_id: Uuid,
+        pub created_date: String,
+        pub destination_id: String,
+        pub session_id: String,
+        pub message_type: String,
+        pub schema_version: i32,
+        pub output: String,
+    }
+
+    /// AgentSessionState is sent by the agent when the state of the session changes.
+    /// * SchemaVersion is a 4 byte integer containing the message schema version number.
+    /// * SessionState is a string field containing the new state, such as `Connected` or `Terminating`.
+    /// * SessionId is a string field representing the session whose state changed.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+    #[serde(default, rename_all = "PascalCase")]
+    pub struct AgentSessionState {
+        pub schema_version: i32,
+        pub session_state: SessionState,
+        pub session_id: String,
+    }
+
+    impl Default for AgentSessionState {
+        fn default() -> Self {
+            Self {
+                schema_version: 1,
+                session_state: SessionState::Unknown(String::new()),
+                session_id: String::new(),
+            }
+        }
+    }
+
+    /// SessionState reported by the agent in AgentSessionState messages, states introduced by
+    /// newer agents are kept as Unknown.
+    #[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+    #[serde(from = "String", into = "String")]
+    pub enum SessionState {
+        Connected,
+        Terminating,
+        Unknown(String),
+    }
+
+    impl From<String> for SessionState {
+        fn from(value: String) -> Self {
+            match value.as_str() {
+                "Connected" => Self::Connected,
+                "Terminating" => Self::Terminating,
+                _ => Self::Unknown(value),
+            }
+        }
+    }
+
+    impl From<SessionState> for String {
+        fn from(value: SessionState) -> Self {
+            value.to_string()
+        }
+    }
+
+    impl std::fmt::Display for SessionState {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            match self {
+                Self::Connected => f.write_str("Connected"),
+                Self::Terminating => f.write_str("Terminating"),
+                Self::Unknown(state) => f.write_str(state),
+            }
+        }
     }
 
     #[derive(Display, Copy, Clone, PartialEq, Debug)]
@@ -170,7 +230,7 @@ pub mod message {
         }
     }
 
-    #[derive(Display, Debug)]
+    #[derive(Display, Copy, Clone, PartialEq, Debug)]
     #[repr(u32)]
     pub enum PayloadTypeFlag {
         DisconnectToPort = 1,
@@ -216,8 +276,8 @@ pub mod message {
     ///
     /// * | HL|         MessageType           |Ver|  CD   |  Seq  | Flags |
     /// * |         MessageId                     |           Digest              | PayType | PayLen|
-    /// * |         Payload      			|
-    #[derive(Debug)]
+    /// * |         Payload                 |
+    #[derive(Clone, Debug)]
     pub struct ClientMessage {
         /// * HL - HeaderLength is a 4 byte unsigned integer that represents the header length.
         pub header_length: u32,
@@ -252,7 +312,7 @@ pub mod message {
         pub payload_length: u32,
 
         /// * Payload is a variable length byte data.
-        pub payload: String,
+        pub payload: Vec<u8>,
     }
 
     impl ClientMessage {
@@ -13,25 +13,64 @@
 
 /// message package defines data channel messages structure.
 pub mod message {
-    use serde::{Serialize, Deserialize};
-    use std::time::Duration;
+    use serde::{Deserialize, Deserializer, Serialize, Serializer};
     use strum_macros::Display;
 
     /// ActionType used in Handshake to determine action requested by the agent
-    #[derive(Serialize, Deserialize, Display, Debug)]
+    #[derive(Serialize, Deserialize, Display, Copy, Clone, PartialEq, Debug)]
     pub enum ActionType {
         KMSEncryption,
         SessionType,
     }
 
     /// This is used in Handshake to determine status of the action requested by the agent.
-    #[derive(Serialize, Deserialize, Display, Debug)]
+    /// Serialized as its numeric value.
+    #[derive(Display, Copy, Clone, PartialEq, Debug)]
+    #[repr(u32)]
     pub enum ActionStatus {
         Success = 1,
         Failed = 2,
         Unsupported = 3,
     }
 
+    impl Serialize for ActionStatus {
+        fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_u32(*self as u32)
+        }
+    }
+
+    impl<'de> Deserialize<'de> for ActionStatus {
+        fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+            match u32::deserialize(deserializer)? {
+                1 => Ok(ActionStatus::Success),
+                2 => Ok(ActionStatus::Failed),
+                3 => Ok(ActionStatus::Unsupported),
+                value => Err(serde::de::Error::custom(format!(
+                    "Invalid value for ActionStatus: {}",
+                    value
+                ))),
+            }
+        }
+    }
+
+    /// Byte fields are exchanged as base64 encoded strings.
+    mod base64_bytes {
+        use base64::engine::general_purpose::STANDARD;
+        use base64::Engine;
+        use serde::{Deserialize, Deserializer, Serializer};
+
+        pub fn serialize<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+            serializer.serialize_str(&STANDARD.encode(bytes))
+        }
+
+        pub fn deserialize<'de, D: Deserializer<'de>>(
+            deserializer: D,
+        ) -> Result<Vec<u8>, D::Error> {
+            let encoded = String::deserialize(deserializer)?;
+            STANDARD.decode(encoded).map_err(serde::de::Error::custom)
+        }
+    }
+
     /// This is sent by the agent to initialize KMS encryption.
     #[derive(Serialize, Deserialize, Debug)]
     pub struct KMSEncryptionRequest {
@@ -42,9 +81,9 @@ pub mod message {
     /// This is received by the agent to set up KMS encryption.
     #[derive(Serialize, Deserialize, Debug)]
     pub struct KMSEncryptionResponse {
-        #[serde(rename = "KMSCipherTextKey")]
+        #[serde(rename = "KMSCipherTextKey", with = "base64_bytes")]
         pub kms_cipher_text_key: Vec<u8>,
-        #[serde(rename = "KMSCipherTextHash")]
+        #[serde(rename = "KMSCipherTextHash", with = "base64_bytes")]
         pub kms_cipher_text_hash: Vec<u8>,
     }
 
@@ -53,6 +92,7 @@ pub mod message {
     #[serde(rename_all = "PascalCase")]
     pub struct SessionTypeRequest {
         pub session_type: String,
+        #[serde(default)]
         pub properties: serde_json::Value,
     }
 
@@ -97,6 +137,7 @@ pub mod message {
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     pub struct EncryptionChallengeRequest {
+        #[serde(with = "base64_bytes")]
         pub challenge: Vec<u8>,
     }
 
@@ -106,6 +147,7 @@ pub mod message {
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     pub struct EncryptionChallengeResponse {
+        #[serde(with = "base64_bytes")]
         pub challenge: Vec<u8>,
     }
 
@@ -114,7 +156,109 @@ pub mod message {
     #[derive(Serialize, Deserialize, Debug)]
     #[serde(rename_all = "PascalCase")]
     pub struct HandshakeCompletePayload {
-        pub handshake_time_to_complete: Duration,
+        /// Time the handshake took in nanoseconds.
+        pub handshake_time_to_complete: i64,
         pub customer_message: String,
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::message::*;
+    use serde_json::json;
+
+    #[test]
+    fn encodes_bytes_of_the_kms_response_as_base64() {
+        let response = KMSEncryptionResponse {
+            kms_cipher_text_key: b"key".to_vec(),
+            kms_cipher_text_hash: vec![0xff, 0x00],
+        };
+
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({"KMSCipherTextKey": "a2V5", "KMSCipherTextHash": "/wA="})
+        );
+    }
+
+    #[test]
+    fn decodes_the_challenge_from_base64() {
+        let request: EncryptionChallengeRequest =
+            serde_json::from_value(json!({"Challenge": "Y2hhbGxlbmdl"})).unwrap();
+        assert_eq!(request.challenge, b"challenge");
+
+        let invalid = serde_json::from_value::<EncryptionChallengeRequest>(
+            json!({"Challenge": "not base64!"}),
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn encodes_the_action_status_as_a_number() {
+        let response = HandshakeResponsePayload {
+            client_version: "1.2.553.0".to_string(),
+            processed_client_actions: vec![ProcessedClientAction {
+                action_type: ActionType::SessionType,
+                action_status: ActionStatus::Unsupported,
+                action_result: serde_json::Value::Null,
+                error: "unsupported".to_string(),
+            }],
+            errors: Vec::new(),
+        };
+
+        assert_eq!(
+            serde_json::to_value(&response).unwrap(),
+            json!({
+                "ClientVersion": "1.2.553.0",
+                "ProcessedClientActions": [{
+                    "ActionType": "SessionType",
+                    "ActionStatus": 3,
+                    "ActionResult": null,
+                    "Error": "unsupported",
+                }],
+                "Errors": [],
+            })
+        );
+    }
+
+    #[test]
+    fn decodes_the_action_status_from_a_number() {
+        let action = |status| {
+            serde_json::from_value::<ProcessedClientAction>(json!({
+                "ActionType": "KMSEncryption",
+                "ActionStatus": status,
+                "ActionResult": null,
+                "Error": "",
+            }))
+        };
+
+        assert_eq!(action(1).unwrap().action_status, ActionStatus::Success);
+        assert_eq!(action(2).unwrap().action_status, ActionStatus::Failed);
+        assert!(action(4).is_err());
+    }
+
+    #[test]
+    fn decodes_the_handshake_request() {
+        let request: HandshakeRequestPayload = serde_json::from_value(json!({
+            "AgentVersion": "3.2.582.0",
+            "RequestedClientActions": [
+                {"ActionType": "KMSEncryption", "ActionParameters": {"KMSKeyId": "key-id"}},
+                {"ActionType": "SessionType", "ActionParameters": {"SessionType": "Standard_Stream"}},
+            ],
+        }))
+        .unwrap();
+
+        assert_eq!(request.agent_version, "3.2.582.0");
+        let [kms, session_type] = request.requested_client_actions.as_slice() else {
+            panic!("expected two actions");
+        };
+        assert_eq!(kms.action_type, ActionType::KMSEncryption);
+        let kms: KMSEncryptionRequest =
+            serde_json::from_value(kms.action_parameters.clone()).unwrap();
+        assert_eq!(kms.kms_key_id, "key-id");
+
+        let session_type: SessionTypeRequest =
+            serde_json::from_value(session_type.action_parameters.clone()).unwrap();
+        assert_eq!(session_type.session_type, "Standard_Stream");
+        assert_eq!(session_type.properties, serde_json::Value::Null);
+    }
+}
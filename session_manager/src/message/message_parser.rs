@@ -11,13 +11,139 @@
 // either express or implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
-use crate::message::client_message::message::{ClientMessage, ClientMessageError, MessageType};
+use crate::message::client_message::message::{
+    AcknowledgeContent, AgentSessionState, ChannelClosed, ClientMessage, ClientMessageError,
+    MessageType, PayloadType, PayloadTypeFlag,
+};
 use byteorder::{BigEndian, ByteOrder};
 use chrono::{DateTime, Utc};
+use serde::de::DeserializeOwned;
+use sha2::{Digest, Sha256};
 use std::mem::size_of;
 use uuid::Uuid;
 
 impl ClientMessage {
+    /// Header length of every message built by this client, excluding the payload length field.
+    pub const CLIENT_HEADER_LENGTH: u32 = Self::PAYLOAD_LENGTH_OFFSET as u32;
+
+    /// Builds a new message with a fresh message id, creation date and payload digest.
+    pub fn new(
+        message_type: MessageType,
+        sequence_number: i64,
+        flags: u64,
+        payload_type: PayloadType,
+        payload: Vec<u8>,
+    ) -> Self {
+        Self {
+            header_length: Self::CLIENT_HEADER_LENGTH,
+            message_type,
+            schema_version: 1,
+            created_date: Utc::now(),
+            sequence_number,
+            flags,
+            message_id: Uuid::new_v4(),
+            payload_digest: Sha256::digest(&payload).to_vec(),
+            payload_type,
+            payload_length: payload.len() as u32,
+            payload,
+        }
+    }
+
+    /// Builds the acknowledge message for a received stream data message.
+    pub fn new_acknowledge(message: &ClientMessage) -> Result<Self, ClientMessageError> {
+        let content = AcknowledgeContent {
+            message_type: message.message_type,
+            message_id: message.message_id,
+            sequence_number: message.sequence_number,
+            is_sequential_message: true,
+        };
+
+        let payload = serde_json::to_vec(&content)
+            .map_err(|e| ClientMessageError::SerializationError(e.to_string()))?;
+
+        Ok(Self::new(
+            MessageType::Acknowledge,
+            0,
+            3,
+            PayloadType::Null,
+            payload,
+        ))
+    }
+
+    /// Deserializes the payload of an acknowledge message.
+    pub fn deserialize_data_stream_acknowledge_content(
+        &self,
+    ) -> Result<AcknowledgeContent, ClientMessageError> {
+        if self.message_type != MessageType::Acknowledge {
+            return Err(ClientMessageError::DeserializationError(format!(
+                "ClientMessage is not of type Acknowledge. Found message type: {}",
+                self.message_type
+            )));
+        }
+
+        self.deserialize_payload()
+    }
+
+    /// Deserializes the payload of a channel closed message.
+    pub fn deserialize_channel_closed_message(&self) -> Result<ChannelClosed, ClientMessageError> {
+        if self.message_type != MessageType::ChannelClosed {
+            return Err(ClientMessageError::DeserializationError(format!(
+                "ClientMessage is not of type ChannelClosed. Found message type: {}",
+                self.message_type
+            )));
+        }
+
+        self.deserialize_payload()
+    }
+
+    /// Deserializes the payload of an agent session state message.
+    pub fn deserialize_agent_session_state_message(
+        &self,
+    ) -> Result<AgentSessionState, ClientMessageError> {
+        if self.message_type != MessageType::AgentSessionState {
+            return Err(ClientMessageError::DeserializationError(format!(
+                "ClientMessage is not of type AgentSessionState. Found message type: {}",
+                self.message_type
+            )));
+        }
+
+        self.deserialize_payload()
+    }
+
+    /// Deserializes the JSON payload of the message into `T`.
+    pub fn deserialize_payload<T: DeserializeOwned>(&self) -> Result<T, ClientMessageError> {
+        serde_json::from_slice(&self.payload).map_err(|e| {
+            log::error!("Could not deserialize payload of {}: {}", self.message_type, e);
+            ClientMessageError::DeserializationError(e.to_string())
+        })
+    }
+
+    /// Serializes a flag into the 4 byte payload expected by the agent.
+    pub fn flag_payload(flag: PayloadTypeFlag) -> Vec<u8> {
+        (flag as u32).to_be_bytes().to_vec()
+    }
+
+    /// Deserializes the 4 byte flag payload sent by the agent.
+    pub fn deserialize_flag(&self) -> Result<PayloadTypeFlag, ClientMessageError> {
+        let flag = self
+            .payload
+            .get(..4)
+            .map(|bytes| u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+            .ok_or_else(|| {
+                ClientMessageError::DeserializationError("Flag payload too short".to_string())
+            })?;
+
+        match flag {
+            1 => Ok(PayloadTypeFlag::DisconnectToPort),
+            2 => Ok(PayloadTypeFlag::TerminateSession),
+            3 => Ok(PayloadTypeFlag::ConnectToPortError),
+            _ => Err(ClientMessageError::DeserializationError(format!(
+                "Unknown flag {}",
+                flag
+            ))),
+        }
+    }
+
     pub fn deserialize_client_message(input: &[u8]) -> Result<Self, ClientMessageError> {
         let message_type = get_string(input, Self::MESSAGE_TYPE_OFFSET, Self::MESSAGE_TYPE_LENGTH)
             .and_then(|s| {
@@ -44,15 +170,10 @@ impl ClientMessage {
                 e
             })
             .and_then(|cd| {
-                Ok(
-                    DateTime::<Utc>::from_timestamp_millis(cd as i64).ok_or_else(|| {
-                        ClientMessageError::DeserializationError(format!(
-                            "Invalid timestamp: {}",
-                            cd
-                        ))
-                    }),
-                )
-            })??;
+                DateTime::<Utc>::from_timestamp_millis(cd as i64).ok_or_else(|| {
+                    ClientMessageError::DeserializationError(format!("Invalid timestamp: {}", cd))
+                })
+            })?;
 
         let sequence_number = get_i64(input, Self::SEQUENCE_NUMBER_OFFSET).map_err(|e| {
             log::error!(
@@ -108,9 +229,15 @@ impl ClientMessage {
             e
         })?;
 
-        let payload =
-            String::from_utf8_lossy(&input[header_length as usize + Self::PAYLOAD_LENGTH_LENGTH..])
-                .to_string();
+        let payload_offset = header_length as usize + Self::PAYLOAD_LENGTH_LENGTH;
+        if payload_offset > input.len() {
+            log::error!("Could not deserialize field payload: Offset is invalid.");
+            return Err(ClientMessageError::DeserializationError(
+                "Offset is outside the byte array.".to_string(),
+            ));
+        }
+
+        let payload = input[payload_offset..].to_vec();
 
         Ok(Self {
             header_length,
@@ -144,7 +271,7 @@ impl ClientMessage {
         let payload_type: u32 = self.payload_type.into();
         bytes.extend_from_slice(&payload_type.to_be_bytes());
         bytes.extend_from_slice(&self.payload_length.to_be_bytes());
-        bytes.extend_from_slice(&self.payload.as_bytes());
+        bytes.extend_from_slice(&self.payload);
 
         bytes
     }
@@ -233,16 +360,12 @@ fn get_uuid(byte_array: &[u8], offset: usize) -> Result<Uuid, ClientMessageError
     Ok(Uuid::from_bytes(uuid_bytes))
 }
 
-/// Converts the little-endian Uuid to a big-endian Uuid.
+/// Converts the Uuid to the wire format, least significant half first. Inverse of `get_uuid`.
 fn put_uuid(uuid: &Uuid) -> [u8; 16] {
-    let mut uuid_bytes = uuid.as_bytes().clone();
-
-    uuid_bytes.swap(0, 3);
-    uuid_bytes.swap(1, 2);
-    uuid_bytes.swap(4, 5);
-    uuid_bytes.swap(6, 7);
+    let mut uuid_bytes = *uuid.as_bytes();
+    uuid_bytes.rotate_left(8);
 
-    uuid_bytes.into()
+    uuid_bytes
 }
 
 fn get_u32(byte_array: &[u8], offset: usize) -> Result<u32, ClientMessageError> {
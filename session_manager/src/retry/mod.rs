@@ -0,0 +1,2 @@
+/// Retry package implements retrying of fallible async calls with exponential backoff.
+pub mod retryer;
@@ -0,0 +1,279 @@
+use crate::config::config::{
+    DATA_CHANNEL_NUM_MAX_RETRIES, DATA_CHANNEL_RETRY_INITIAL_DELAY_MILLIS,
+    DATA_CHANNEL_RETRY_MAX_INTERVAL_MILLIS, RETRY_BASE,
+};
+use log::{debug, warn};
+use rand::Rng;
+use std::fmt::Display;
+use std::future::Future;
+use std::time::Duration;
+
+pub trait IRetryer {
+    /// Returns the delay before the given attempt, without jitter applied.
+    fn next_sleep_time(&self, attempt: u32) -> Duration;
+}
+
+/// Retries a call with exponentially growing, jittered delays. Once the delay would exceed
+/// `max_delay` the sequence starts over from `initial_delay`, until `max_retries` retries failed
+/// as well as the first call.
+#[derive(Clone, Debug)]
+pub struct RepeatableExponentialRetryer {
+    /// Name of the retried operation, used for logging.
+    pub operation: String,
+    pub geometric_ratio: u32,
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    pub max_retries: u32,
+    attempt: u32,
+    failed_attempts: u32,
+}
+
+impl Default for RepeatableExponentialRetryer {
+    fn default() -> Self {
+        Self {
+            operation: String::new(),
+            geometric_ratio: RETRY_BASE,
+            initial_delay: Duration::from_millis(DATA_CHANNEL_RETRY_INITIAL_DELAY_MILLIS),
+            max_delay: Duration::from_millis(DATA_CHANNEL_RETRY_MAX_INTERVAL_MILLIS),
+            max_retries: DATA_CHANNEL_NUM_MAX_RETRIES,
+            attempt: 0,
+            failed_attempts: 0,
+        }
+    }
+}
+
+impl IRetryer for RepeatableExponentialRetryer {
+    fn next_sleep_time(&self, attempt: u32) -> Duration {
+        self.initial_delay
+            .saturating_mul(self.geometric_ratio.saturating_pow(attempt))
+    }
+}
+
+impl RepeatableExponentialRetryer {
+    /// Creates a retryer for the operation using the data channel retry configuration.
+    pub fn new(operation: &str) -> Self {
+        Self {
+            operation: operation.to_string(),
+            ..Default::default()
+        }
+    }
+
+    /// Returns a copy of this retryer for another operation, with the attempts reset.
+    pub fn for_operation(&self, operation: &str) -> Self {
+        Self {
+            operation: operation.to_string(),
+            attempt: 0,
+            failed_attempts: 0,
+            ..self.clone()
+        }
+    }
+
+    /// Records a failed attempt and sleeps until the next one is due.
+    /// Returns false once the first call and `max_retries` retries have failed.
+    pub async fn backoff(&mut self, error: &(dyn Display + Send + Sync)) -> bool {
+        self.failed_attempts += 1;
+
+        if self.failed_attempts > self.max_retries {
+            warn!(
+                "{} failed after {} attempts: {}",
+                &self.operation, self.failed_attempts, error
+            );
+            return false;
+        }
+
+        let sleep = self.next_jittered_sleep_time();
+        warn!(
+            "{} attempt {} of {} failed, retrying in {:?}: {}",
+            &self.operation,
+            self.failed_attempts,
+            self.max_retries + 1,
+            sleep,
+            error
+        );
+
+        tokio::time::sleep(sleep).await;
+
+        true
+    }
+
+    /// Returns the delay before the next retry and advances the sequence, starting it over
+    /// once the delay would exceed `max_delay`.
+    fn next_jittered_sleep_time(&mut self) -> Duration {
+        let mut sleep = self.next_sleep_time(self.attempt);
+        if sleep > self.max_delay {
+            self.attempt = 0;
+            sleep = self.next_sleep_time(self.attempt);
+        }
+        self.attempt += 1;
+
+        // Spread out retries of concurrent clients by sleeping between half and the full delay.
+        rand::thread_rng().gen_range(sleep / 2..=sleep)
+    }
+
+    /// Calls `callable` until it succeeds or all attempts failed.
+    pub async fn call<T, E, F, Fut>(&mut self, callable: F) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Display + Send + Sync,
+    {
+        self.call_if(callable, |_| true).await
+    }
+
+    /// Calls `callable` until it succeeds, all attempts failed or it fails with an error
+    /// for which `is_retryable` returns false.
+    pub async fn call_if<T, E, F, Fut, P>(
+        &mut self,
+        mut callable: F,
+        is_retryable: P,
+    ) -> Result<T, E>
+    where
+        F: FnMut() -> Fut,
+        Fut: Future<Output = Result<T, E>>,
+        E: Display + Send + Sync,
+        P: Fn(&E) -> bool,
+    {
+        loop {
+            debug!(
+                "{} attempt {} of {}",
+                &self.operation,
+                self.failed_attempts + 1,
+                self.max_retries + 1
+            );
+
+            match callable().await {
+                Ok(value) => return Ok(value),
+                Err(e) if !is_retryable(&e) => return Err(e),
+                Err(e) => {
+                    if !self.backoff(&e).await {
+                        return Err(e);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn retryer(max_retries: u32) -> RepeatableExponentialRetryer {
+        RepeatableExponentialRetryer {
+            initial_delay: Duration::from_millis(1),
+            max_delay: Duration::from_millis(4),
+            max_retries,
+            ..RepeatableExponentialRetryer::new("test")
+        }
+    }
+
+    #[test]
+    fn jitters_between_half_and_the_full_delay() {
+        let mut retryer = RepeatableExponentialRetryer {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(400),
+            ..RepeatableExponentialRetryer::new("test")
+        };
+
+        for _ in 0..100 {
+            for delay in [100, 200, 400] {
+                let delay = Duration::from_millis(delay);
+                let sleep = retryer.next_jittered_sleep_time();
+                assert!(
+                    sleep >= delay / 2 && sleep <= delay,
+                    "{:?} not within {:?}",
+                    sleep,
+                    delay
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn starts_over_from_the_initial_delay_past_the_max_delay() {
+        let mut retryer = RepeatableExponentialRetryer {
+            initial_delay: Duration::from_millis(100),
+            max_delay: Duration::from_millis(250),
+            ..RepeatableExponentialRetryer::new("test")
+        };
+
+        let sleeps = (0..5)
+            .map(|_| retryer.next_jittered_sleep_time())
+            .collect::<Vec<_>>();
+        let upper_bounds = [100, 200, 100, 200, 100].map(Duration::from_millis);
+        for (sleep, upper_bound) in sleeps.iter().zip(upper_bounds) {
+            assert!(
+                sleep <= &upper_bound,
+                "{:?} above {:?}",
+                sleeps,
+                upper_bound
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_the_first_call_and_every_retry_failed() {
+        let mut calls = 0;
+        let result: Result<(), String> = retryer(3)
+            .call(|| {
+                calls += 1;
+                async { Err("unavailable".to_string()) }
+            })
+            .await;
+
+        assert_eq!(result, Err("unavailable".to_string()));
+        assert_eq!(calls, 4);
+    }
+
+    #[tokio::test]
+    async fn returns_the_first_success() {
+        let mut calls = 0;
+        let result = retryer(3)
+            .call(|| {
+                calls += 1;
+                let result = if calls < 3 {
+                    Err("unavailable")
+                } else {
+                    Ok(calls)
+                };
+                async move { result }
+            })
+            .await;
+
+        assert_eq!(result, Ok(3));
+    }
+
+    #[tokio::test]
+    async fn does_not_retry_errors_that_are_not_retryable() {
+        let mut calls = 0;
+        let result: Result<(), &str> = retryer(3)
+            .call_if(
+                || {
+                    calls += 1;
+                    async { Err("AccessDenied") }
+                },
+                |e| *e == "ThrottlingException",
+            )
+            .await;
+
+        assert_eq!(result, Err("AccessDenied"));
+        assert_eq!(calls, 1);
+    }
+
+    #[tokio::test]
+    async fn retries_are_counted_per_operation() {
+        let mut retryer = retryer(1);
+        let _: Result<(), &str> = retryer.call(|| async { Err("unavailable") }).await;
+
+        let mut calls = 0;
+        let _: Result<(), &str> = retryer
+            .for_operation("other")
+            .call(|| {
+                calls += 1;
+                async { Err("unavailable") }
+            })
+            .await;
+
+        assert_eq!(calls, 2);
+    }
+}
@@ -11,9 +11,8 @@
 // either express or implied. See the License for the specific language governing
 // permissions and limitations under the License.
 
-use crate::config::config::MESSAGE_SCHEMA_VERSION;
+use crate::config::config::{CLIENT_VERSION, MESSAGE_SCHEMA_VERSION};
 use serde::Serialize;
-use uuid::Uuid;
 
 #[derive(Debug, Serialize)]
 pub struct OpenDataChannelInput {
@@ -32,20 +31,25 @@ pub struct OpenDataChannelInput {
     // ClientId is a required field
     #[serde(rename = "ClientId")]
     pub client_id: String,
+
+    // ClientVersion is a required field
+    #[serde(rename = "ClientVersion")]
+    pub client_version: &'static str,
 }
 
 // TODO: Unofficial
 impl OpenDataChannelInput {
-    pub fn new(request_id: &str, token_value: &str) -> Self {
+    pub fn new(request_id: &str, token_value: &str, client_id: &str) -> Self {
         let request_id = request_id.to_string();
         let token_value = token_value.to_string();
-        let client_id = Uuid::new_v4().to_string();
+        let client_id = client_id.to_string();
 
         Self {
             message_schema_version: MESSAGE_SCHEMA_VERSION,
             request_id,
             token_value,
             client_id,
+            client_version: CLIENT_VERSION,
         }
     }
 }
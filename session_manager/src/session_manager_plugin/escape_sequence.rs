@@ -0,0 +1,227 @@
+/// Local command entered with the escape character at the start of a line.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EscapeCommand {
+    /// `~.` terminates the session.
+    Disconnect,
+
+    /// `~?` lists the escape sequences.
+    Help,
+
+    /// `~#` shows information about the session.
+    Info,
+}
+
+/// Recognises escape sequences in the input like OpenSSH does, only right after a newline so the
+/// escape character can be typed anywhere else.
+#[derive(Debug, Clone)]
+pub struct EscapeSequences {
+    escape_char: u8,
+    after_newline: bool,
+    pending: bool,
+}
+
+impl EscapeSequences {
+    pub fn new(escape_char: u8) -> Self {
+        Self {
+            escape_char,
+            after_newline: true,
+            pending: false,
+        }
+    }
+
+    /// Removes the escape sequences from the input, returns the input left to send and the
+    /// commands entered. An escape character alone is held back until the next input.
+    pub fn process(&mut self, input: &[u8]) -> (Vec<u8>, Vec<EscapeCommand>) {
+        let mut output = Vec::with_capacity(input.len());
+        let mut commands = Vec::new();
+
+        for &byte in input {
+            if self.pending {
+                self.pending = false;
+
+                let command = match byte {
+                    b'.' => Some(EscapeCommand::Disconnect),
+                    b'?' => Some(EscapeCommand::Help),
+                    b'#' => Some(EscapeCommand::Info),
+                    _ => None,
+                };
+                if let Some(command) = command {
+                    // Another sequence may follow right away.
+                    commands.push(command);
+                    continue;
+                }
+
+                // Not a sequence, the escape character is sent once, followed by anything else.
+                output.push(self.escape_char);
+                if byte == self.escape_char {
+                    self.after_newline = false;
+                    continue;
+                }
+            } else if self.after_newline && byte == self.escape_char {
+                self.pending = true;
+                continue;
+            }
+
+            output.push(byte);
+            self.after_newline = byte == b'\r' || byte == b'\n';
+        }
+
+        (output, commands)
+    }
+
+    /// Lists the escape sequences, with line endings for a terminal in raw mode.
+    pub fn help(&self) -> String {
+        let escape = display_escape_char(self.escape_char);
+
+        format!(
+            "Supported escape sequences:\r\n \
+             {escape}.   - terminate the session\r\n \
+             {escape}?   - this message\r\n \
+             {escape}#   - session information\r\n \
+             {escape}{escape}   - send the escape character by typing it twice\r\n\
+             (Note that escapes are only recognized immediately after newline.)\r\n"
+        )
+    }
+}
+
+/// Shows control characters in caret notation, such as `^]`.
+pub fn display_escape_char(escape_char: u8) -> String {
+    match escape_char {
+        0..=0x1f => format!("^{}", (escape_char + 0x40) as char),
+        0x7f => "^?".to_string(),
+        _ => (escape_char as char).to_string(),
+    }
+}
+
+/// Parses an escape character as OpenSSH does, a single character, a control character in caret
+/// notation such as `^]`, or `none` to disable escapes.
+pub fn parse_escape_char(value: &str) -> Result<Option<u8>, String> {
+    match value.as_bytes() {
+        b"none" => Ok(None),
+        [c] if c.is_ascii() => Ok(Some(*c)),
+        [b'^', b'?'] => Ok(Some(0x7f)),
+        [b'^', c @ b'@'..=b'_'] => Ok(Some(c - 0x40)),
+        [b'^', c @ b'a'..=b'z'] => Ok(Some(c - b'a' + 1)),
+        _ => Err(format!(
+            "invalid escape character {:?}, expected a single character, ^X or none",
+            value
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn process(escapes: &mut EscapeSequences, input: &str) -> (String, Vec<EscapeCommand>) {
+        let (output, commands) = escapes.process(input.as_bytes());
+        (String::from_utf8(output).unwrap(), commands)
+    }
+
+    #[test]
+    fn recognises_sequences_at_the_start_of_a_line() {
+        let mut escapes = EscapeSequences::new(b'~');
+
+        assert_eq!(
+            process(&mut escapes, "~."),
+            (String::new(), vec![EscapeCommand::Disconnect])
+        );
+        assert_eq!(
+            process(&mut escapes, "~?~#"),
+            (
+                String::new(),
+                vec![EscapeCommand::Help, EscapeCommand::Info]
+            )
+        );
+    }
+
+    #[test]
+    fn recognises_sequences_only_after_a_newline() {
+        let mut escapes = EscapeSequences::new(b'~');
+
+        assert_eq!(
+            process(&mut escapes, "ls ~."),
+            ("ls ~.".to_string(), vec![])
+        );
+        assert_eq!(
+            process(&mut escapes, "\r~."),
+            ("\r".to_string(), vec![EscapeCommand::Disconnect])
+        );
+        assert_eq!(
+            process(&mut escapes, "cd\n~#pwd"),
+            ("cd\npwd".to_string(), vec![EscapeCommand::Info])
+        );
+    }
+
+    #[test]
+    fn sends_a_doubled_escape_character_once() {
+        let mut escapes = EscapeSequences::new(b'~');
+
+        assert_eq!(process(&mut escapes, "~~"), ("~".to_string(), vec![]));
+        // The line no longer starts with the escape character.
+        assert_eq!(process(&mut escapes, "~."), ("~.".to_string(), vec![]));
+    }
+
+    #[test]
+    fn sends_the_escape_character_before_other_input() {
+        let mut escapes = EscapeSequences::new(b'~');
+
+        assert_eq!(
+            process(&mut escapes, "~/bin"),
+            ("~/bin".to_string(), vec![])
+        );
+        assert_eq!(
+            process(&mut escapes, "\r~\r"),
+            ("\r~\r".to_string(), vec![])
+        );
+    }
+
+    #[test]
+    fn holds_back_a_lone_escape_character() {
+        let mut escapes = EscapeSequences::new(b'~');
+
+        assert_eq!(process(&mut escapes, "~"), (String::new(), vec![]));
+        assert_eq!(
+            process(&mut escapes, "."),
+            (String::new(), vec![EscapeCommand::Disconnect])
+        );
+
+        assert_eq!(process(&mut escapes, "~"), (String::new(), vec![]));
+        assert_eq!(process(&mut escapes, "x"), ("~x".to_string(), vec![]));
+    }
+
+    #[test]
+    fn uses_the_configured_escape_character() {
+        let mut escapes = EscapeSequences::new(0x1d);
+
+        assert_eq!(process(&mut escapes, "~."), ("~.".to_string(), vec![]));
+        assert_eq!(
+            process(&mut escapes, "\r\x1d."),
+            ("\r".to_string(), vec![EscapeCommand::Disconnect])
+        );
+        assert!(escapes.help().contains("^]."));
+    }
+
+    #[test]
+    fn parses_escape_characters() {
+        assert_eq!(parse_escape_char("~"), Ok(Some(b'~')));
+        assert_eq!(parse_escape_char("none"), Ok(None));
+        assert_eq!(parse_escape_char("^]"), Ok(Some(0x1d)));
+        assert_eq!(parse_escape_char("^@"), Ok(Some(0)));
+        assert_eq!(parse_escape_char("^a"), Ok(Some(1)));
+        assert_eq!(parse_escape_char("^?"), Ok(Some(0x7f)));
+        assert_eq!(parse_escape_char("^"), Ok(Some(b'^')));
+
+        for invalid in ["", "~~", "^1", "^~", "é", "None"] {
+            assert!(parse_escape_char(invalid).is_err(), "{:?}", invalid);
+        }
+    }
+
+    #[test]
+    fn displays_escape_characters_as_parsed() {
+        for value in ["~", "^]", "^@", "^?", "#"] {
+            let escape_char = parse_escape_char(value).unwrap().unwrap();
+            assert_eq!(display_escape_char(escape_char), value);
+        }
+    }
+}
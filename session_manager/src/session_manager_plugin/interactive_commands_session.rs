@@ -0,0 +1,63 @@
+use crate::config::config::INTERACTIVE_COMMANDS_PLUGIN_NAME;
+use crate::session_manager_plugin::session::ISessionPlugin;
+use crate::session_manager_plugin::session_handle::{SessionEvents, SessionInput};
+use crate::session_manager_plugin::session_type::SessionType;
+use crate::session_manager_plugin::shell_session::ShellSession;
+use anyhow::{bail, Result};
+use futures_util::future::BoxFuture;
+
+/// Plugin for interactive command sessions, such as `sudo -iu app bash`. Handles the terminal
+/// like a shell session, but ends the session once the remote command exits.
+pub struct InteractiveCommandsSession {
+    shell: ShellSession,
+}
+
+impl Default for InteractiveCommandsSession {
+    fn default() -> Self {
+        Self {
+            shell: ShellSession::for_command(),
+        }
+    }
+}
+
+impl InteractiveCommandsSession {
+    /// Sets the character starting escape sequences, None disables them.
+    pub fn with_escape_char(mut self, escape_char: Option<u8>) -> Self {
+        self.shell = self.shell.with_escape_char(escape_char);
+        self
+    }
+}
+
+impl ISessionPlugin for InteractiveCommandsSession {
+    fn initialize(&mut self, session_type: &SessionType) -> Result<()> {
+        if !matches!(session_type, SessionType::InteractiveCommands(_)) {
+            bail!(
+                "{} plugin cannot handle {} sessions",
+                self.name(),
+                session_type
+            );
+        }
+
+        self.shell.initialize(session_type)
+    }
+
+    fn set_session_handlers(
+        &mut self,
+        events: SessionEvents,
+        input: SessionInput,
+    ) -> BoxFuture<'_, Result<()>> {
+        self.shell.set_session_handlers(events, input)
+    }
+
+    fn stop(&mut self) {
+        self.shell.stop();
+    }
+
+    fn name(&self) -> &str {
+        INTERACTIVE_COMMANDS_PLUGIN_NAME
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        self.shell.exit_code()
+    }
+}
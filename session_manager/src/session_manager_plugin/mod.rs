@@ -1 +1,9 @@
+pub mod escape_sequence;
+pub mod interactive_commands_session;
+pub mod non_interactive_commands_session;
+pub mod port_session;
+pub mod registry;
 pub mod session;
+pub mod session_handle;
+pub mod session_type;
+pub mod shell_session;
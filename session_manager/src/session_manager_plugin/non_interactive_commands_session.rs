@@ -0,0 +1,169 @@
+use crate::config::config::{NON_INTERACTIVE_COMMANDS_PLUGIN_NAME, STREAM_DATA_PAYLOAD_SIZE};
+use crate::session_manager_plugin::session::ISessionPlugin;
+use crate::session_manager_plugin::session_handle::{SessionEvent, SessionEvents, SessionInput};
+use crate::session_manager_plugin::session_type::SessionType;
+use anyhow::{bail, Result};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use log::debug;
+use std::io::{IsTerminal, Read};
+use std::thread;
+use tokio::io::{self, AsyncWriteExt};
+use tokio::sync::mpsc;
+
+/// Plugin for non interactive command sessions, writes the output of the command to stdout and
+/// its error output to stderr, like `ssh host command` does. Stdin is forwarded when piped.
+#[derive(Default)]
+pub struct NonInteractiveCommandsSession {
+    exit_code: Option<i32>,
+}
+
+impl ISessionPlugin for NonInteractiveCommandsSession {
+    fn initialize(&mut self, session_type: &SessionType) -> Result<()> {
+        if !matches!(session_type, SessionType::NonInteractiveCommands(_)) {
+            bail!(
+                "{} plugin cannot handle {} sessions",
+                self.name(),
+                session_type
+            );
+        }
+
+        Ok(())
+    }
+
+    fn set_session_handlers(
+        &mut self,
+        mut events: SessionEvents,
+        input: SessionInput,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let mut stdout = io::stdout();
+            let mut stderr = io::stderr();
+
+            // Reading a terminal would wait for a line the command never asked for.
+            let mut stdin_open = !std::io::stdin().is_terminal();
+            let mut stdin = read_in_background(stdin_open.then(std::io::stdin));
+
+            loop {
+                tokio::select! {
+                    read = stdin.recv(), if stdin_open => match read {
+                        Some(data) => input.send_input(&data).await?,
+                        None => stdin_open = false,
+                    },
+                    event = events.next() => match event {
+                        Some(SessionEvent::Output(output)) => {
+                            stdout.write_all(&output).await?;
+                            stdout.flush().await?;
+                        }
+                        Some(SessionEvent::StdErr(output)) => {
+                            stderr.write_all(&output).await?;
+                            stderr.flush().await?;
+                        }
+                        Some(SessionEvent::ExitCode(exit_code)) => {
+                            debug!("Command exited with {}", exit_code);
+                            self.exit_code = Some(exit_code);
+                        }
+                        Some(SessionEvent::Closed { reason }) => {
+                            debug!("{}", reason);
+                            break;
+                        }
+                        Some(event) => debug!("{:?}", event),
+                        None => break,
+                    },
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn stop(&mut self) {}
+
+    fn name(&self) -> &str {
+        NON_INTERACTIVE_COMMANDS_PLUGIN_NAME
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+}
+
+/// Reads stdin on its own thread, as a pending read of tokio's stdin would keep the runtime from
+/// shutting down once the command exited. The channel closes at the end of stdin, right away
+/// without a reader.
+fn read_in_background<R: Read + Send + 'static>(reader: Option<R>) -> mpsc::Receiver<Vec<u8>> {
+    let (sender, receiver) = mpsc::channel(1);
+
+    if let Some(mut reader) = reader {
+        thread::spawn(move || {
+            let mut buffer = vec![0; STREAM_DATA_PAYLOAD_SIZE];
+
+            loop {
+                match reader.read(&mut buffer) {
+                    Ok(0) => break,
+                    Ok(read) => {
+                        if sender.blocking_send(buffer[..read].to_vec()).is_err() {
+                            break;
+                        }
+                    }
+                    Err(e) => {
+                        debug!("Failed to read stdin: {}", e);
+                        break;
+                    }
+                }
+            }
+        });
+    }
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::{self, Cursor};
+
+    async fn read_all(mut receiver: mpsc::Receiver<Vec<u8>>) -> Vec<Vec<u8>> {
+        let mut chunks = Vec::new();
+        while let Some(chunk) = receiver.recv().await {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+
+    #[tokio::test]
+    async fn forwards_piped_input_in_payload_sized_chunks() {
+        let input: Vec<u8> = (0..STREAM_DATA_PAYLOAD_SIZE * 2 + 10)
+            .map(|i| i as u8)
+            .collect();
+
+        let chunks = read_all(read_in_background(Some(Cursor::new(input.clone())))).await;
+
+        assert!(chunks
+            .iter()
+            .all(|chunk| !chunk.is_empty() && chunk.len() <= STREAM_DATA_PAYLOAD_SIZE));
+        assert_eq!(chunks.concat(), input);
+    }
+
+    #[tokio::test]
+    async fn closes_without_a_reader() {
+        assert!(read_all(read_in_background(None::<io::Empty>))
+            .await
+            .is_empty());
+    }
+
+    struct FailingReader;
+
+    impl Read for FailingReader {
+        fn read(&mut self, _buffer: &mut [u8]) -> io::Result<usize> {
+            Err(io::Error::new(io::ErrorKind::BrokenPipe, "closed"))
+        }
+    }
+
+    #[tokio::test]
+    async fn closes_when_reading_fails() {
+        assert!(read_all(read_in_background(Some(FailingReader)))
+            .await
+            .is_empty());
+    }
+}
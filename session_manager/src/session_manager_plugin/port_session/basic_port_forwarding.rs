@@ -0,0 +1,90 @@
+use crate::config::config::STREAM_DATA_PAYLOAD_SIZE;
+use crate::message::client_message::message::PayloadTypeFlag;
+use crate::session_manager_plugin::session_handle::{
+    SessionEvent, SessionEvents, SessionInput, SessionNotice,
+};
+use crate::session_manager_plugin::session_type::PortProperties;
+use anyhow::Result;
+use futures_util::StreamExt;
+use log::{debug, warn};
+use std::future;
+use std::io;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpListener;
+
+/// Forwards a single local TCP connection at a time, for agents without multiplexing support.
+pub async fn execute(
+    mut events: SessionEvents,
+    input: SessionInput,
+    properties: &PortProperties,
+) -> Result<()> {
+    let listener =
+        TcpListener::bind(("localhost", properties.local_port_number.unwrap_or(0))).await?;
+
+    input.notify(SessionNotice::PortOpened {
+        session_id: input.get_session_id().to_string(),
+        port: listener.local_addr()?.port(),
+    });
+
+    let mut reader: Option<OwnedReadHalf> = None;
+    let mut writer: Option<OwnedWriteHalf> = None;
+    let mut buffer = vec![0; STREAM_DATA_PAYLOAD_SIZE];
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept(), if reader.is_none() => {
+                let (stream, address) = accepted?;
+                input.notify(SessionNotice::ConnectionAccepted {
+                    session_id: input.get_session_id().to_string(),
+                    address,
+                });
+                let (read_half, write_half) = stream.into_split();
+                reader = Some(read_half);
+                writer = Some(write_half);
+            }
+            read = read_local(&mut reader, &mut buffer) => match read {
+                Ok(0) | Err(_) => {
+                    debug!("Connection closed by local client, waiting for new connection");
+                    reader = None;
+                    writer = None;
+                    input.send_flag(PayloadTypeFlag::DisconnectToPort).await?;
+                }
+                Ok(read) => input.send_input(&buffer[..read]).await?,
+            },
+            event = events.next() => match event {
+                Some(SessionEvent::Output(output)) => match writer.as_mut() {
+                    Some(local) => {
+                        if let Err(e) = local.write_all(&output).await {
+                            warn!("Failed to write to local connection: {}", e);
+                        }
+                    }
+                    None => debug!("Dropping {} bytes without local connection", output.len()),
+                },
+                Some(SessionEvent::Flag(PayloadTypeFlag::ConnectToPortError)) => {
+                    input.notify(SessionNotice::ConnectToPortFailed {
+                        destination: properties.destination(),
+                    });
+                    reader = None;
+                    writer = None;
+                }
+                Some(SessionEvent::Closed { reason }) => {
+                    debug!("{}", reason);
+                    break;
+                }
+                Some(event) => debug!("{:?}", event),
+                None => break,
+            },
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads from the local connection, or never completes without one.
+async fn read_local(reader: &mut Option<OwnedReadHalf>, buffer: &mut [u8]) -> io::Result<usize> {
+    match reader {
+        Some(reader) => reader.read(buffer).await,
+        None => future::pending().await,
+    }
+}
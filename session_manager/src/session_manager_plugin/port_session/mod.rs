@@ -0,0 +1,5 @@
+pub mod basic_port_forwarding;
+pub mod mux_port_forwarding;
+#[allow(clippy::module_inception)]
+pub mod port_session;
+pub mod standard_stream_forwarding;
@@ -0,0 +1,260 @@
+use crate::config::config::STREAM_DATA_PAYLOAD_SIZE;
+use crate::message::client_message::message::PayloadTypeFlag;
+use crate::session_manager_plugin::session_handle::{
+    SessionEvent, SessionEvents, SessionInput, SessionNotice,
+};
+use crate::session_manager_plugin::session_type::PortProperties;
+use crate::smux::mux::{Mux, MuxConfig, MuxStream};
+use anyhow::{bail, Result};
+use futures_util::StreamExt;
+use log::{debug, warn};
+use std::collections::VecDeque;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::oneshot;
+
+/// Size of the in-memory pipe between the multiplexer and the data channel.
+const MUX_PIPE_SIZE: usize = 64 * 1024;
+
+/// Forwards any number of concurrent local TCP connections, each carried by its own smux stream
+/// over the data channel.
+pub async fn execute(
+    mut events: SessionEvents,
+    input: SessionInput,
+    properties: &PortProperties,
+    config: MuxConfig,
+) -> Result<()> {
+    let listener =
+        TcpListener::bind(("localhost", properties.local_port_number.unwrap_or(0))).await?;
+
+    // The multiplexer talks to one end of the pipe, the other end is bridged to the data channel.
+    let (mux_transport, mut bridge) = tokio::io::duplex(MUX_PIPE_SIZE);
+    let mux = Arc::new(Mux::client(mux_transport, config));
+
+    input.notify(SessionNotice::PortOpened {
+        session_id: input.get_session_id().to_string(),
+        port: listener.local_addr()?.port(),
+    });
+
+    // The agent does not tell which connection its ConnectToPortError is about. It connects to
+    // the destination as streams are opened, so the error is taken for the oldest connection
+    // which has not received any data yet.
+    let mut pending: VecDeque<oneshot::Sender<()>> = VecDeque::new();
+    let mut buffer = vec![0; STREAM_DATA_PAYLOAD_SIZE];
+
+    loop {
+        tokio::select! {
+            accepted = listener.accept() => {
+                let (stream, address) = accepted?;
+                input.notify(SessionNotice::ConnectionAccepted {
+                    session_id: input.get_session_id().to_string(),
+                    address,
+                });
+                let (reset, reset_receiver) = oneshot::channel();
+                pending.retain(|reset| !reset.is_closed());
+                pending.push_back(reset);
+                tokio::spawn(forward_connection(
+                    Arc::clone(&mux),
+                    stream,
+                    address,
+                    reset_receiver,
+                ));
+            }
+            read = bridge.read(&mut buffer) => match read? {
+                0 => bail!("Multiplexer stopped unexpectedly"),
+                read => input.send_input(&buffer[..read]).await?,
+            },
+            event = events.next() => match event {
+                Some(SessionEvent::Output(output)) => bridge.write_all(&output).await?,
+                Some(SessionEvent::Flag(PayloadTypeFlag::ConnectToPortError)) => {
+                    input.notify(SessionNotice::ConnectToPortFailed {
+                        destination: properties.destination(),
+                    });
+                    while let Some(reset) = pending.pop_front() {
+                        if reset.send(()).is_ok() {
+                            break;
+                        }
+                    }
+                }
+                Some(SessionEvent::Closed { reason }) => {
+                    debug!("{}", reason);
+                    break;
+                }
+                Some(event) => debug!("{:?}", event),
+                None => break,
+            },
+            () = mux.closed() => bail!("Multiplexed connection to the agent was lost"),
+        }
+    }
+
+    mux.close();
+
+    Ok(())
+}
+
+/// Copies data between a local connection and a new stream until either side closes, or
+/// resets the local connection when the agent failed to connect to the destination.
+async fn forward_connection(
+    mux: Arc<Mux>,
+    mut local: TcpStream,
+    address: SocketAddr,
+    reset: oneshot::Receiver<()>,
+) {
+    let mut stream = match mux.open_stream().await {
+        Ok(stream) => stream,
+        Err(e) => {
+            warn!(
+                "Failed to open stream for connection from {}: {}",
+                address, e
+            );
+            return;
+        }
+    };
+
+    match copy_bidirectional(&mut local, &mut stream, reset).await {
+        Ok(Copied::Closed) => {}
+        Ok(Copied::Reset) => {
+            warn!(
+                "Resetting connection from {} as the agent failed to connect to the destination",
+                address
+            );
+            if let Err(e) = local.set_linger(Some(Duration::ZERO)) {
+                debug!("Failed to reset connection from {}: {}", address, e);
+            }
+        }
+        Err(e) => debug!("Connection from {} failed: {}", address, e),
+    }
+
+    if let Err(e) = stream.close().await {
+        debug!("Failed to close stream {}: {}", stream.id(), e);
+    }
+
+    debug!("Connection from {} closed", address);
+}
+
+/// How copying between a local connection and its stream ended.
+#[derive(Debug, PartialEq)]
+enum Copied {
+    /// Either side closed the connection.
+    Closed,
+
+    /// The agent failed to connect to the destination before any data came back.
+    Reset,
+}
+
+async fn copy_bidirectional(
+    local: &mut TcpStream,
+    stream: &mut MuxStream,
+    mut reset: oneshot::Receiver<()>,
+) -> Result<Copied> {
+    let (mut reader, mut writer) = local.split();
+    let mut buffer = vec![0; STREAM_DATA_PAYLOAD_SIZE];
+    let mut resettable = true;
+
+    loop {
+        tokio::select! {
+            result = &mut reset, if resettable => match result {
+                Ok(()) => return Ok(Copied::Reset),
+                Err(_) => resettable = false,
+            },
+            read = reader.read(&mut buffer) => match read? {
+                0 => return Ok(Copied::Closed),
+                read => stream.write(&buffer[..read]).await?,
+            },
+            data = stream.read() => match data {
+                Some(data) => {
+                    if resettable {
+                        // Data came back, so the error of the agent is about another connection.
+                        resettable = false;
+                        reset.close();
+                    }
+                    writer.write_all(&data).await?;
+                }
+                None => return Ok(Copied::Closed),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::ErrorKind;
+    use tokio::time;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Returns the multiplexer of the client and the one of the agent, connected to each other.
+    fn pair() -> (Arc<Mux>, Mux) {
+        let config = MuxConfig {
+            keep_alive_disabled: true,
+            ..MuxConfig::default()
+        };
+        let (client, agent) = tokio::io::duplex(MUX_PIPE_SIZE);
+        (
+            Arc::new(Mux::client(client, config.clone())),
+            Mux::server(agent, config),
+        )
+    }
+
+    /// Returns a local client and the connection accepted for it.
+    async fn connect() -> (TcpStream, TcpStream, SocketAddr) {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (local, address) = listener.accept().await.unwrap();
+        (client, local, address)
+    }
+
+    async fn accept_stream(agent: &Mux) -> MuxStream {
+        time::timeout(TIMEOUT, agent.accept_stream())
+            .await
+            .expect("timed out waiting for a stream")
+            .unwrap()
+    }
+
+    #[tokio::test]
+    async fn resets_the_connection_the_agent_failed_to_connect_for() {
+        let (mux, agent) = pair();
+        let (mut client, local, address) = connect().await;
+        let (reset, reset_receiver) = oneshot::channel();
+        let forwarding = tokio::spawn(forward_connection(mux, local, address, reset_receiver));
+
+        let mut stream = accept_stream(&agent).await;
+        reset.send(()).unwrap();
+        time::timeout(TIMEOUT, forwarding).await.unwrap().unwrap();
+
+        let read = client.read(&mut [0; 16]).await;
+        assert!(
+            matches!(&read, Err(e) if e.kind() == ErrorKind::ConnectionReset),
+            "{:?}",
+            read
+        );
+        assert_eq!(time::timeout(TIMEOUT, stream.read()).await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn keeps_connections_the_agent_answered() {
+        let (mux, agent) = pair();
+        let (mut client, local, address) = connect().await;
+        let (reset, reset_receiver) = oneshot::channel();
+        tokio::spawn(forward_connection(mux, local, address, reset_receiver));
+
+        let mut stream = accept_stream(&agent).await;
+        client.write_all(b"ping").await.unwrap();
+        assert_eq!(stream.read().await.unwrap(), "ping");
+        stream.write(b"pong").await.unwrap();
+        let mut pong = [0; 4];
+        client.read_exact(&mut pong).await.unwrap();
+        assert_eq!(&pong, b"pong");
+
+        // A later error of the agent is about another connection.
+        assert!(reset.send(()).is_err());
+        client.write_all(b"more").await.unwrap();
+        assert_eq!(stream.read().await.unwrap(), "more");
+    }
+}
@@ -0,0 +1,225 @@
+use crate::config::config::PORT_PLUGIN_NAME;
+use crate::session_manager_plugin::port_session::{
+    basic_port_forwarding, mux_port_forwarding, standard_stream_forwarding,
+};
+use crate::session_manager_plugin::session::ISessionPlugin;
+use crate::session_manager_plugin::session_handle::{
+    SessionEvent, SessionEvents, SessionInput, SessionNotice,
+};
+use crate::session_manager_plugin::session_type::{PortProperties, SessionType};
+use crate::smux::mux::MuxConfig;
+use crate::version::agent_version::{AgentFeature, AgentVersion};
+use anyhow::{anyhow, bail, Result};
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use log::{debug, info};
+
+/// Port type of documents forwarding a local TCP port, other documents such as
+/// AWS-StartSSHSession forward stdin and stdout instead.
+pub const LOCAL_PORT_FORWARDING_TYPE: &str = "LocalPortForwarding";
+
+/// Plugin for port sessions.
+#[derive(Default)]
+pub struct PortSession {
+    properties: Option<PortProperties>,
+}
+
+impl PortSession {
+    /// Waits for the end of the handshake, which tells the version of the agent.
+    async fn wait_for_agent_version(events: &mut SessionEvents) -> Result<AgentVersion> {
+        while let Some(event) = events.next().await {
+            match event {
+                SessionEvent::HandshakeComplete { agent_version, .. } => return Ok(agent_version),
+                SessionEvent::Closed { reason } => bail!(reason),
+                event => debug!("{:?}", event),
+            }
+        }
+
+        bail!("Session closed before the handshake completed")
+    }
+}
+
+impl ISessionPlugin for PortSession {
+    fn initialize(&mut self, session_type: &SessionType) -> Result<()> {
+        let SessionType::Port(properties) = session_type else {
+            bail!(
+                "{} plugin cannot handle {} sessions",
+                self.name(),
+                session_type
+            );
+        };
+
+        self.properties = Some(properties.clone());
+
+        Ok(())
+    }
+
+    fn set_session_handlers(
+        &mut self,
+        mut events: SessionEvents,
+        input: SessionInput,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            let properties = self
+                .properties
+                .as_ref()
+                .ok_or_else(|| anyhow!("{} plugin is not initialized", PORT_PLUGIN_NAME))?;
+
+            if properties.port_type != LOCAL_PORT_FORWARDING_TYPE {
+                return standard_stream_forwarding::execute(events, input).await;
+            }
+
+            let agent_version = Self::wait_for_agent_version(&mut events).await?;
+            let capabilities = agent_version.capabilities();
+            if capabilities.tcp_multiplexing {
+                if !capabilities.smux_keep_alive {
+                    info!(
+                        "Agent version {} does not expect smux keepalive, it is disabled",
+                        agent_version
+                    );
+                }
+                let config = MuxConfig {
+                    keep_alive_disabled: !capabilities.smux_keep_alive,
+                    ..MuxConfig::default()
+                };
+
+                return mux_port_forwarding::execute(events, input, properties, config).await;
+            }
+
+            input.notify(SessionNotice::AgentTooOld {
+                agent_version,
+                feature: AgentFeature::TcpMultiplexing,
+            });
+
+            basic_port_forwarding::execute(events, input, properties).await
+        })
+    }
+
+    fn stop(&mut self) {}
+
+    fn name(&self) -> &str {
+        PORT_PLUGIN_NAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data_channel::streaming::DataChannel;
+    use crate::session_manager_plugin::session_handle::SessionNotice;
+    use aws_sdk_kms::config::{BehaviorVersion, Region};
+    use std::time::Duration;
+    use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+    use tokio::time;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Runs a local port forwarding session until it is closed, returning the notices it
+    /// reported once the agent completed the handshake with the version.
+    async fn notices_for(agent_version: &str) -> Vec<SessionNotice> {
+        let kms = aws_sdk_kms::Client::from_conf(
+            aws_sdk_kms::Config::builder()
+                .behavior_version(BehaviorVersion::latest())
+                .region(Region::new("us-east-1"))
+                .build(),
+        );
+        let (data_channel, _) = DataChannel::new(
+            kms,
+            "client".to_string(),
+            "session".to_string(),
+            "target".to_string(),
+            "ws://127.0.0.1:1".to_string(),
+            "token".to_string(),
+        );
+        let (events, event_receiver) = mpsc::unbounded_channel();
+        let (notices, mut notice_receiver) = mpsc::unbounded_channel();
+        let input = SessionInput::new(
+            "session".to_string(),
+            data_channel,
+            mpsc::unbounded_channel().0,
+            Some(notices),
+        );
+
+        let mut plugin = PortSession::default();
+        plugin
+            .initialize(&SessionType::Port(PortProperties {
+                port_number: 80,
+                local_port_number: None,
+                port_type: LOCAL_PORT_FORWARDING_TYPE.to_string(),
+                host: None,
+            }))
+            .unwrap();
+
+        let session = plugin.set_session_handlers(SessionEvents::new(event_receiver), input);
+        let agent = async {
+            events
+                .send(SessionEvent::HandshakeComplete {
+                    agent_version: agent_version.parse().unwrap(),
+                    customer_message: String::new(),
+                })
+                .unwrap();
+            let notices = wait_for_port(&mut notice_receiver).await;
+            close(&events);
+            notices
+        };
+
+        let (result, notices) = time::timeout(TIMEOUT, async { tokio::join!(session, agent) })
+            .await
+            .expect("timed out waiting for the session");
+        result.unwrap();
+        notices
+    }
+
+    /// Returns the notices up to the one telling the local port.
+    async fn wait_for_port(notices: &mut UnboundedReceiver<SessionNotice>) -> Vec<SessionNotice> {
+        let mut received = Vec::new();
+        while let Some(notice) = notices.recv().await {
+            let opened = matches!(notice, SessionNotice::PortOpened { .. });
+            received.push(notice);
+            if opened {
+                break;
+            }
+        }
+        received
+    }
+
+    fn close(events: &UnboundedSender<SessionEvent>) {
+        events
+            .send(SessionEvent::Closed {
+                reason: "closed".to_string(),
+            })
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn warns_when_the_agent_is_too_old_for_multiplexing() {
+        let notices = notices_for("3.0.196.0").await;
+
+        assert_eq!(notices.len(), 2, "{:?}", notices);
+        assert_eq!(
+            notices[0],
+            SessionNotice::AgentTooOld {
+                agent_version: "3.0.196.0".parse().unwrap(),
+                feature: AgentFeature::TcpMultiplexing,
+            }
+        );
+        assert_eq!(
+            notices[0].to_string(),
+            "Agent version 3.0.196.0 does not support multiplexing, connections are forwarded \
+             one at a time."
+        );
+        assert!(matches!(notices[1], SessionNotice::PortOpened { .. }));
+    }
+
+    #[tokio::test]
+    async fn multiplexes_without_warning_on_newer_agents() {
+        let notices = notices_for("3.2.582.0").await;
+
+        assert_eq!(notices.len(), 1, "{:?}", notices);
+        let SessionNotice::PortOpened { session_id, port } = &notices[0] else {
+            panic!("unexpected {:?}", notices[0]);
+        };
+        assert_eq!(session_id, "session");
+        assert_ne!(*port, 0);
+    }
+}
@@ -0,0 +1,38 @@
+use crate::config::config::STREAM_DATA_PAYLOAD_SIZE;
+use crate::session_manager_plugin::session_handle::{SessionEvent, SessionEvents, SessionInput};
+use anyhow::Result;
+use futures_util::StreamExt;
+use log::debug;
+use tokio::io::{self, AsyncReadExt, AsyncWriteExt};
+
+/// Forwards stdin and stdout, used when the port session is a proxy such as an SSH
+/// ProxyCommand.
+pub async fn execute(mut events: SessionEvents, input: SessionInput) -> Result<()> {
+    let mut stdin = io::stdin();
+    let mut stdout = io::stdout();
+    let mut stdin_open = true;
+    let mut buffer = vec![0; STREAM_DATA_PAYLOAD_SIZE];
+
+    loop {
+        tokio::select! {
+            read = stdin.read(&mut buffer), if stdin_open => match read? {
+                0 => stdin_open = false,
+                read => input.send_input(&buffer[..read]).await?,
+            },
+            event = events.next() => match event {
+                Some(SessionEvent::Output(output)) => {
+                    stdout.write_all(&output).await?;
+                    stdout.flush().await?;
+                }
+                Some(SessionEvent::Closed { reason }) => {
+                    debug!("{}", reason);
+                    break;
+                }
+                Some(event) => debug!("{:?}", event),
+                None => break,
+            },
+        }
+    }
+
+    Ok(())
+}
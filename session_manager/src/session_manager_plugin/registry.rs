@@ -0,0 +1,179 @@
+use crate::config::config::{
+    DEFAULT_ESCAPE_CHAR, INTERACTIVE_COMMANDS_PLUGIN_NAME, NON_INTERACTIVE_COMMANDS_PLUGIN_NAME,
+    PORT_PLUGIN_NAME, SHELL_PLUGIN_NAME,
+};
+use crate::session_manager_plugin::interactive_commands_session::InteractiveCommandsSession;
+use crate::session_manager_plugin::non_interactive_commands_session::NonInteractiveCommandsSession;
+use crate::session_manager_plugin::port_session::port_session::PortSession;
+use crate::session_manager_plugin::session::ISessionPlugin;
+use crate::session_manager_plugin::session_type::SessionType;
+use crate::session_manager_plugin::shell_session::ShellSession;
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+
+/// Creates a new plugin instance for every session.
+pub type SessionPluginFactory = Box<dyn Fn() -> Box<dyn ISessionPlugin> + Send + Sync>;
+
+/// Plugins keyed by the name of the session type requested by the agent.
+pub struct SessionPluginRegistry {
+    plugins: HashMap<String, SessionPluginFactory>,
+}
+
+impl Default for SessionPluginRegistry {
+    /// Creates a registry with the built-in plugins.
+    fn default() -> Self {
+        Self::with_escape_char(Some(DEFAULT_ESCAPE_CHAR))
+    }
+}
+
+impl SessionPluginRegistry {
+    /// Creates a registry with the built-in plugins, shells starting escape sequences with the
+    /// character given, or without escape sequences when None.
+    pub fn with_escape_char(escape_char: Option<u8>) -> Self {
+        let mut registry = Self::empty();
+
+        registry.register(SHELL_PLUGIN_NAME, move || {
+            Box::new(ShellSession::default().with_escape_char(escape_char))
+        });
+        registry.register(INTERACTIVE_COMMANDS_PLUGIN_NAME, move || {
+            Box::new(InteractiveCommandsSession::default().with_escape_char(escape_char))
+        });
+        registry.register(NON_INTERACTIVE_COMMANDS_PLUGIN_NAME, || {
+            Box::<NonInteractiveCommandsSession>::default()
+        });
+        registry.register(PORT_PLUGIN_NAME, || Box::<PortSession>::default());
+
+        registry
+    }
+
+    /// Creates a registry without any plugins.
+    pub fn empty() -> Self {
+        Self {
+            plugins: HashMap::new(),
+        }
+    }
+
+    /// Registers the plugin for a session type, replacing the plugin registered before.
+    pub fn register<F>(&mut self, session_type: &str, factory: F)
+    where
+        F: Fn() -> Box<dyn ISessionPlugin> + Send + Sync + 'static,
+    {
+        self.plugins
+            .insert(session_type.to_string(), Box::new(factory));
+    }
+
+    pub fn is_registered(&self, session_type: &str) -> bool {
+        self.plugins.contains_key(session_type)
+    }
+
+    /// Creates the plugin for the session type.
+    pub fn create(&self, session_type: &SessionType) -> Result<Box<dyn ISessionPlugin>> {
+        let factory = self
+            .plugins
+            .get(session_type.name())
+            .ok_or_else(|| anyhow!("No plugin registered for session type {}", session_type))?;
+
+        Ok(factory())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::session_manager_plugin::session_handle::{SessionEvents, SessionInput};
+    use crate::session_manager_plugin::session_type::ShellProperties;
+    use futures_util::future::BoxFuture;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+
+    struct StubPlugin(&'static str);
+
+    impl ISessionPlugin for StubPlugin {
+        fn initialize(&mut self, _session_type: &SessionType) -> Result<()> {
+            Ok(())
+        }
+
+        fn set_session_handlers(
+            &mut self,
+            _events: SessionEvents,
+            _input: SessionInput,
+        ) -> BoxFuture<'_, Result<()>> {
+            Box::pin(async { Ok(()) })
+        }
+
+        fn stop(&mut self) {}
+
+        fn name(&self) -> &str {
+            self.0
+        }
+    }
+
+    fn unknown(session_type: &str) -> SessionType {
+        SessionType::Unknown {
+            session_type: session_type.to_string(),
+            properties: serde_json::Value::Null,
+        }
+    }
+
+    #[test]
+    fn registers_the_built_in_plugins() {
+        let registry = SessionPluginRegistry::default();
+
+        for session_type in [
+            SHELL_PLUGIN_NAME,
+            INTERACTIVE_COMMANDS_PLUGIN_NAME,
+            NON_INTERACTIVE_COMMANDS_PLUGIN_NAME,
+        ] {
+            assert!(registry.is_registered(session_type), "{}", session_type);
+        }
+        assert!(!registry.is_registered("Custom_Plugin"));
+        assert!(!SessionPluginRegistry::empty().is_registered(SHELL_PLUGIN_NAME));
+    }
+
+    #[test]
+    fn creates_the_plugin_registered_for_the_session_type() {
+        let mut registry = SessionPluginRegistry::default();
+        registry.register("Custom_Plugin", || Box::new(StubPlugin("custom")));
+
+        let plugin = registry.create(&unknown("Custom_Plugin")).unwrap();
+        assert_eq!(plugin.name(), "custom");
+    }
+
+    #[test]
+    fn replaces_plugins_registered_before() {
+        let mut registry = SessionPluginRegistry::default();
+        registry.register(SHELL_PLUGIN_NAME, || Box::new(StubPlugin("custom shell")));
+
+        let session_type = SessionType::StandardStream(ShellProperties::default());
+        let plugin = registry.create(&session_type).unwrap();
+        assert_eq!(plugin.name(), "custom shell");
+    }
+
+    #[test]
+    fn creates_a_new_plugin_for_every_session() {
+        let created = Arc::new(AtomicUsize::new(0));
+        let mut registry = SessionPluginRegistry::empty();
+        let counter = Arc::clone(&created);
+        registry.register("Custom_Plugin", move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+            Box::new(StubPlugin("custom"))
+        });
+
+        registry.create(&unknown("Custom_Plugin")).unwrap();
+        registry.create(&unknown("Custom_Plugin")).unwrap();
+        assert_eq!(created.load(Ordering::SeqCst), 2);
+    }
+
+    #[test]
+    fn fails_for_session_types_without_a_plugin() {
+        let error = SessionPluginRegistry::default()
+            .create(&unknown("Custom_Plugin"))
+            .err()
+            .unwrap();
+
+        assert_eq!(
+            error.to_string(),
+            "No plugin registered for session type Custom_Plugin"
+        );
+    }
+}
@@ -1,41 +1,984 @@
-use crate::message::client_message::message::ClientMessage;
-use std::any::Any;
+use crate::config::config::SessionConfig;
+use crate::data_channel::streaming::{DataChannel, DataChannelEvent};
+use crate::message::client_message::message::{
+    ChannelClosed, ClientMessage, PayloadType, PayloadTypeFlag,
+};
+use crate::retry::retryer::RepeatableExponentialRetryer;
+use crate::session_manager_plugin::registry::SessionPluginRegistry;
+use crate::session_manager_plugin::session_handle::{
+    self, SessionCommand, SessionEvent, SessionEvents, SessionExit, SessionHandle, SessionInput,
+    SessionNotice,
+};
+use crate::session_manager_plugin::session_type::SessionType;
+use crate::terminal::signal::{shutdown_signal, SignalHandler};
+use crate::version::agent_version::{AgentFeature, AgentVersion};
+use anyhow::{bail, Result};
+use aws_sdk_ssm::error::ProvideErrorMetadata;
+use aws_sdk_ssm::operation::start_session::builders::StartSessionFluentBuilder;
+use aws_sdk_ssm::operation::start_session::StartSessionOutput;
+use aws_types::SdkConfig;
+use bytes::Bytes;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use log::{debug, error, info, warn};
+use std::sync::Arc;
+use thiserror::Error;
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::Mutex;
+use tokio::time;
+use uuid::Uuid;
 
-use anyhow::Result;
+/// Plugin handling the local side of a session type, such as the terminal of a shell session.
+pub trait ISessionPlugin: Send {
+    /// Prepares the plugin for the session type and properties requested by the agent.
+    fn initialize(&mut self, session_type: &SessionType) -> Result<()>;
 
-pub trait ISessionPlugin {
-    fn set_session_handlers(&mut self) -> Result<()>;
-    fn process_stream_message_payload(
+    /// Runs the plugin until the session closes, consuming the session output from `events` and
+    /// sending local input through `input`.
+    fn set_session_handlers(
         &mut self,
-        stream_data_message: ClientMessage,
-    ) -> Result<bool>;
-    fn initialize(&mut self, session_var: &Session);
+        events: SessionEvents,
+        input: SessionInput,
+    ) -> BoxFuture<'_, Result<()>>;
+
+    /// Releases what the plugin acquired, such as the raw mode of the terminal.
     fn stop(&mut self);
+
     fn name(&self) -> &str;
+
+    /// Exit code of the remote process, for plugins running a command.
+    fn exit_code(&self) -> Option<i32> {
+        None
+    }
 }
 
+#[allow(async_fn_in_trait)]
 pub trait ISession {
-    fn execute(&mut self) -> Result<()>;
-    fn open_data_channel(&mut self) -> Result<()>;
-    fn process_first_message(&mut self, output_message: ClientMessage) -> Result<bool>;
-    fn stop(&mut self);
-    fn get_resume_session_params(&self) -> Result<String>;
-    fn resume_session_handler(&mut self) -> Result<()>;
-    fn terminate_session(&mut self) -> Result<()>;
+    async fn execute(&mut self) -> Result<()>;
+    async fn open_data_channel(&mut self) -> Result<()>;
+    async fn stop(&mut self);
+    async fn get_resume_session_params(&self) -> Result<Option<ResumeSessionParams>>;
+    async fn resume_session_handler(&mut self) -> Result<()>;
+    async fn terminate_session(&mut self) -> Result<()>;
+}
+
+#[derive(Error, Debug)]
+pub enum SessionError {
+    #[error("Session {0} timed out")]
+    TimedOut(String),
+
+    #[error("Terminating session {0} as stream message resend timed out")]
+    ResendTimeout(String),
+}
+
+/// Stream url and token returned by SSM to reconnect to an existing session.
+#[derive(Debug)]
+pub struct ResumeSessionParams {
+    pub stream_url: String,
+    pub token_value: String,
 }
 
 pub struct Session {
-    // data_channel: datachannel::IDataChannel,
+    data_channel: Arc<Mutex<DataChannel>>,
+    data_channel_events: UnboundedReceiver<DataChannelEvent>,
     session_id: String,
     stream_url: String,
     token_value: String,
-    is_aws_cli_upgrade_needed: bool,
-    endpoint: String,
     client_id: String,
     target_id: String,
     sdk: Box<aws_sdk_ssm::Client>,
-    // retry_params: retry::RepeatableExponentialRetryer,
-    session_type: String,
-    session_properties: Box<dyn Any>,
-    // display_mode: sessionutil::DisplayMode,
+    retry_params: RepeatableExponentialRetryer,
+    config: SessionConfig,
+    events: Option<UnboundedSender<SessionEvent>>,
+    notices: Option<UnboundedSender<SessionNotice>>,
+    commands: UnboundedReceiver<SessionCommand>,
+    command_sender: UnboundedSender<SessionCommand>,
+    exit: Option<SessionExit>,
+    session_type: Option<SessionType>,
+}
+
+impl Session {
+    pub fn new(
+        sdk_config: &SdkConfig,
+        session_id: String,
+        stream_url: String,
+        token_value: String,
+        target_id: String,
+    ) -> Self {
+        let client_id = Uuid::new_v4().to_string();
+        let (data_channel, data_channel_events) = DataChannel::new(
+            aws_sdk_kms::Client::new(sdk_config),
+            client_id.clone(),
+            session_id.clone(),
+            target_id.clone(),
+            stream_url.clone(),
+            token_value.clone(),
+        );
+        let (command_sender, commands) = mpsc::unbounded_channel();
+
+        Self {
+            data_channel,
+            data_channel_events,
+            session_id,
+            stream_url,
+            token_value,
+            client_id,
+            target_id,
+            sdk: Box::new(aws_sdk_ssm::Client::new(sdk_config)),
+            retry_params: RepeatableExponentialRetryer::default(),
+            config: SessionConfig::default(),
+            events: None,
+            notices: None,
+            commands,
+            command_sender,
+            exit: None,
+            session_type: None,
+        }
+    }
+
+    /// Executes the session in a background task and returns a handle to consume its events and
+    /// send input to it.
+    pub fn spawn(mut self) -> SessionHandle {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.events = Some(sender);
+
+        let session_id = self.session_id.clone();
+        let input = SessionInput::new(
+            session_id.clone(),
+            self.get_data_channel(),
+            self.command_sender.clone(),
+            self.notices.clone(),
+        );
+
+        let task = tokio::spawn(async move {
+            let exit = match self.execute().await {
+                Ok(()) => self.exit.take().unwrap_or_else(|| {
+                    SessionExit::new(format!("Session {} closed", &self.session_id), false)
+                }),
+                Err(e) => SessionExit::new(e.to_string(), true),
+            };
+
+            self.emit(SessionEvent::Closed {
+                reason: exit.reason.clone(),
+            });
+            exit
+        });
+
+        SessionHandle::new(session_id, SessionEvents::new(receiver), input, task)
+    }
+
+    /// Executes the session with the plugin registered for the session type requested by the
+    /// agent, until the session closes. Returns how the session ended once the plugin restored
+    /// the terminal, so the reason can be shown to the user.
+    pub async fn run(self, registry: &SessionPluginRegistry) -> Result<SessionExit> {
+        let (mut events, input, task) = self.spawn().into_parts();
+
+        let session_type = loop {
+            match events.next().await {
+                Some(SessionEvent::SessionTypeSet(session_type)) => break *session_type,
+                Some(SessionEvent::Closed { .. }) => return Ok(task.await?),
+                Some(event) => debug!("Session event before session type was set: {:?}", event),
+                None => bail!("Session closed before the session type was set"),
+            }
+        };
+
+        let plugin = registry.create(&session_type).and_then(|mut plugin| {
+            plugin.initialize(&session_type)?;
+            Ok(plugin)
+        });
+
+        let mut plugin = match plugin {
+            Ok(plugin) => plugin,
+            Err(e) => {
+                if let Err(e) = input.terminate().await {
+                    debug!("Failed to terminate session: {}", e);
+                }
+                return Err(e);
+            }
+        };
+
+        debug!("Starting {} plugin", plugin.name());
+        let result = {
+            let handlers = plugin.set_session_handlers(events, input.clone());
+            tokio::pin!(handlers);
+            let _signals = SignalHandler::register();
+
+            tokio::select! {
+                result = &mut handlers => result,
+                signal = shutdown_signal() => {
+                    info!("Received {}, terminating session {}", signal, input.get_session_id());
+                    if let Err(e) = input.terminate().await {
+                        warn!("Failed to terminate session: {}", e);
+                    }
+                    handlers.await
+                }
+            }
+        };
+        plugin.stop();
+
+        if !task.is_finished() {
+            // The plugin failed or ended without waiting for the session to close.
+            if let Err(e) = input.terminate().await {
+                debug!("Failed to terminate session: {}", e);
+            }
+        }
+
+        result?;
+
+        let mut exit = task.await?;
+        exit.exit_code = plugin.exit_code();
+        exit.session_type = Some(session_type);
+
+        Ok(exit)
+    }
+
+    /// Sends the StartSession request, retrying it while SSM throttles the caller.
+    pub async fn start_session(request: StartSessionFluentBuilder) -> Result<StartSessionOutput> {
+        let output = RepeatableExponentialRetryer::new("StartSession")
+            .call_if(
+                || request.clone().send(),
+                |e| e.code() == Some("ThrottlingException"),
+            )
+            .await?;
+
+        Ok(output)
+    }
+
+    pub fn get_session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn get_client_id(&self) -> &str {
+        &self.client_id
+    }
+
+    pub fn get_target_id(&self) -> &str {
+        &self.target_id
+    }
+
+    /// Returns the session type once the agent made it known.
+    pub fn get_session_type(&self) -> Option<&SessionType> {
+        self.session_type.as_ref()
+    }
+
+    /// Replaces the SSM client used to resume and terminate the session, e.g. to use a custom
+    /// endpoint for SSM only.
+    pub fn with_ssm_client(mut self, ssm: aws_sdk_ssm::Client) -> Self {
+        self.sdk = Box::new(ssm);
+        self
+    }
+
+    /// Replaces the default settings of the session, such as its reconnect attempts.
+    pub fn with_config(mut self, config: SessionConfig) -> Self {
+        self.retry_params = config.reconnect_retryer();
+        self.config = config;
+        self
+    }
+
+    /// Sends notices for the user, such as the port a port session listens on, to `notices`.
+    pub fn with_notices(mut self, notices: UnboundedSender<SessionNotice>) -> Self {
+        self.notices = Some(notices);
+        self
+    }
+
+    /// Returns the data channel of the session, used to register output handlers and send input
+    /// while the session is executing.
+    pub fn get_data_channel(&self) -> Arc<Mutex<DataChannel>> {
+        Arc::clone(&self.data_channel)
+    }
+
+    fn emit(&self, event: SessionEvent) {
+        if let Some(events) = &self.events {
+            let _ = events.send(event);
+        }
+    }
+
+    /// Waits until the agent closes the channel, emitting the output that is still in flight.
+    async fn wait_for_channel_closed(&mut self) {
+        let channel_closed = time::timeout(self.config.terminate_timeout(), async {
+            while let Some(event) = self.data_channel_events.recv().await {
+                match event {
+                    DataChannelEvent::ChannelClosed(channel_closed) => return Some(channel_closed),
+                    DataChannelEvent::StreamData(message) => {
+                        if let Some(event) = output_event(&message) {
+                            self.emit(event);
+                        }
+                    }
+                    event => debug!("Ignoring {:?} while terminating", event),
+                }
+            }
+
+            None
+        })
+        .await;
+
+        match channel_closed {
+            Ok(Some(channel_closed)) => debug!(
+                "Session {} closed: {}",
+                &self.session_id, &channel_closed.output
+            ),
+            _ => debug!(
+                "Agent did not close the channel of session {} in time",
+                &self.session_id
+            ),
+        }
+    }
+
+    async fn handle_command(&mut self, command: SessionCommand) {
+        match command {
+            SessionCommand::Terminate(done) => {
+                let result = self.terminate_session().await;
+                self.exit = Some(SessionExit::new(
+                    format!("Session {} terminated", &self.session_id),
+                    false,
+                ));
+                let _ = done.send(result);
+            }
+        }
+    }
+}
+
+/// The agent leaves the output empty when the session ended normally, otherwise it explains the
+/// failure, e.g. an idle timeout.
+fn channel_closed_exit(session_id: &str, channel_closed: ChannelClosed) -> SessionExit {
+    if channel_closed.output.is_empty() {
+        return SessionExit::new(
+            format!("Exiting session with sessionId: {}.", session_id),
+            false,
+        );
+    }
+
+    SessionExit::new(
+        format!("SessionId: {} : {}", session_id, channel_closed.output),
+        true,
+    )
+}
+
+/// Converts the output of the remote process to a session event.
+fn output_event(message: &ClientMessage) -> Option<SessionEvent> {
+    match message.payload_type {
+        PayloadType::Output => Some(SessionEvent::Output(Bytes::copy_from_slice(
+            &message.payload,
+        ))),
+        PayloadType::StdErr => Some(SessionEvent::StdErr(Bytes::copy_from_slice(
+            &message.payload,
+        ))),
+        PayloadType::ExitCode => {
+            let exit_code = String::from_utf8_lossy(&message.payload);
+            match exit_code.trim().parse() {
+                Ok(exit_code) => Some(SessionEvent::ExitCode(exit_code)),
+                Err(e) => {
+                    warn!("Invalid exit code {:?}: {}", exit_code, e);
+                    None
+                }
+            }
+        }
+        PayloadType::Flag => match message.deserialize_flag() {
+            Ok(flag) => Some(SessionEvent::Flag(flag)),
+            Err(e) => {
+                warn!("Invalid flag: {}", e);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+impl ISession for Session {
+    /// Opens the data channel and keeps the session alive until the agent closes the channel,
+    /// resuming it whenever the connection drops.
+    async fn execute(&mut self) -> Result<()> {
+        self.open_data_channel().await?;
+        self.emit(SessionEvent::Connected);
+
+        loop {
+            tokio::select! {
+                Some(command) = self.commands.recv() => {
+                    self.handle_command(command).await;
+                    return Ok(());
+                }
+                event = self.data_channel_events.recv() => match event {
+                    Some(DataChannelEvent::ConnectionLost(e)) => {
+                        let data_channel = self.data_channel.lock().await;
+                        warn!(
+                            "Trying to reconnect the session {} with sequence number {}: {}",
+                            data_channel.get_stream_url(),
+                            data_channel.get_stream_data_sequence_number(),
+                            e
+                        );
+                        drop(data_channel);
+
+                        self.emit(SessionEvent::Reconnecting);
+                        if let Err(e) = self.resume_session_handler().await {
+                            error!("Failed to resume session {}: {}", &self.session_id, e);
+                            self.stop().await;
+                            return Err(e);
+                        }
+                        self.emit(SessionEvent::Connected);
+                    }
+                    Some(DataChannelEvent::ChannelClosed(channel_closed)) => {
+                        debug!(
+                            "Session {} closed: {}",
+                            &self.session_id, &channel_closed.output
+                        );
+                        self.exit = Some(channel_closed_exit(&self.session_id, channel_closed));
+                        self.stop().await;
+                        return Ok(());
+                    }
+                    Some(DataChannelEvent::ResendTimeout) => {
+                        self.stop().await;
+                        bail!(SessionError::ResendTimeout(self.session_id.clone()));
+                    }
+                    Some(DataChannelEvent::SessionTypeSet(session_type)) => {
+                        debug!(
+                            "Session {} is a {} session",
+                            &self.session_id, &session_type
+                        );
+                        self.session_type = Some(session_type.clone());
+                        self.emit(SessionEvent::SessionTypeSet(Box::new(session_type)));
+                    }
+                    Some(DataChannelEvent::StreamData(message)) => {
+                        if let Some(event) = output_event(&message) {
+                            self.emit(event);
+                        }
+                    }
+                    Some(DataChannelEvent::HandshakeComplete {
+                        agent_version,
+                        customer_message,
+                    }) => self.emit(SessionEvent::HandshakeComplete {
+                        agent_version,
+                        customer_message,
+                    }),
+                    Some(DataChannelEvent::AgentSessionState(state)) => {
+                        self.emit(SessionEvent::AgentSessionState(state.session_state))
+                    }
+                    Some(DataChannelEvent::PausePublication) => self.emit(SessionEvent::Paused),
+                    Some(DataChannelEvent::StartPublication) => self.emit(SessionEvent::Resumed),
+                    None => return Ok(()),
+                },
+            }
+        }
+    }
+
+    async fn open_data_channel(&mut self) -> Result<()> {
+        debug!(
+            "Opening data channel for session {} to target {}",
+            &self.session_id, &self.target_id
+        );
+
+        let retryer = self.retry_params.for_operation("OpenDataChannel");
+        let mut data_channel = self.data_channel.lock().await;
+        data_channel.set_config(&self.config);
+        data_channel.open_with_retry(retryer).await
+    }
+
+    async fn stop(&mut self) {
+        if let Err(e) = self.data_channel.lock().await.close().await {
+            debug!("Failed to close data channel: {}", e);
+        }
+    }
+
+    async fn get_resume_session_params(&self) -> Result<Option<ResumeSessionParams>> {
+        let output = self
+            .sdk
+            .resume_session()
+            .session_id(&self.session_id)
+            .send()
+            .await?;
+
+        let Some(token_value) = output.token_value else {
+            return Ok(None);
+        };
+
+        Ok(Some(ResumeSessionParams {
+            stream_url: output.stream_url.unwrap_or_else(|| self.stream_url.clone()),
+            token_value,
+        }))
+    }
+
+    async fn resume_session_handler(&mut self) -> Result<()> {
+        let params = self
+            .retry_params
+            .for_operation("ResumeSession")
+            .call(|| self.get_resume_session_params())
+            .await?;
+
+        let Some(params) = params else {
+            bail!(SessionError::TimedOut(self.session_id.clone()));
+        };
+
+        self.stream_url = params.stream_url;
+        self.token_value = params.token_value;
+
+        let retryer = self.retry_params.for_operation("ReconnectDataChannel");
+        self.data_channel
+            .lock()
+            .await
+            .reconnect(self.stream_url.clone(), self.token_value.clone(), retryer)
+            .await
+    }
+
+    /// Asks the agent to end the session when it supports it, then terminates the session with
+    /// SSM and closes the data channel.
+    async fn terminate_session(&mut self) -> Result<()> {
+        let mut data_channel = self.data_channel.lock().await;
+        if data_channel.get_agent_capabilities().terminate_session_flag {
+            let sent = data_channel
+                .send_flag(PayloadTypeFlag::TerminateSession)
+                .await;
+            drop(data_channel);
+
+            match sent {
+                Ok(()) => self.wait_for_channel_closed().await,
+                Err(e) => debug!("Failed to send TerminateSession flag: {}", e),
+            }
+        } else {
+            let agent_version = data_channel.get_agent_version();
+            drop(data_channel);
+
+            // Agents which never completed the handshake did not tell their version.
+            if agent_version != AgentVersion::default() {
+                session_handle::notify(
+                    &self.notices,
+                    SessionNotice::AgentTooOld {
+                        agent_version,
+                        feature: AgentFeature::TerminateSessionFlag,
+                    },
+                );
+            }
+        }
+
+        let result = self
+            .sdk
+            .terminate_session()
+            .session_id(&self.session_id)
+            .send()
+            .await;
+
+        self.stop().await;
+
+        result?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::config::TERMINATE_SESSION_TIMEOUT;
+    use crate::message::client_message::message::MessageType;
+    use aws_sdk_ssm::config::{BehaviorVersion, Credentials, Region, SharedCredentialsProvider};
+    use futures_util::SinkExt;
+    use std::time::Duration;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use tokio_websockets::{Message, ServerBuilder, WebSocketStream};
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    /// Web socket of the agent side, as served by the local listener.
+    type Agent = WebSocketStream<TcpStream>;
+
+    /// Answers every SSM request with the body, returning the bodies of the requests.
+    fn serve_ssm(listener: TcpListener, response: String) -> UnboundedReceiver<String> {
+        let (sender, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            while let Ok((mut stream, _)) = listener.accept().await {
+                let mut request = Vec::new();
+                let mut buffer = [0; 4096];
+                let body = loop {
+                    let read = stream.read(&mut buffer).await.unwrap();
+                    assert!(read > 0, "SSM request ended early");
+                    request.extend_from_slice(&buffer[..read]);
+
+                    let request = String::from_utf8_lossy(&request);
+                    let Some((headers, body)) = request.split_once("\r\n\r\n") else {
+                        continue;
+                    };
+                    let length = headers
+                        .lines()
+                        .find_map(|line| {
+                            let (name, value) = line.split_once(':')?;
+                            name.eq_ignore_ascii_case("content-length")
+                                .then(|| value.trim().parse::<usize>().unwrap())
+                        })
+                        .unwrap_or(0);
+                    if body.len() >= length {
+                        break body.to_string();
+                    }
+                };
+                let _ = sender.send(body);
+
+                let head = format!(
+                    "HTTP/1.1 200 OK\r\nContent-Type: application/x-amz-json-1.1\r\n\
+                     Content-Length: {}\r\nConnection: close\r\n\r\n",
+                    response.len()
+                );
+                stream.write_all(head.as_bytes()).await.unwrap();
+                stream.write_all(response.as_bytes()).await.unwrap();
+            }
+        });
+
+        receiver
+    }
+
+    fn sdk_config(endpoint_url: String) -> SdkConfig {
+        SdkConfig::builder()
+            .behavior_version(BehaviorVersion::latest())
+            .region(Region::new("us-east-1"))
+            .endpoint_url(endpoint_url)
+            .credentials_provider(SharedCredentialsProvider::new(Credentials::new(
+                "access-key",
+                "secret-key",
+                None,
+                None,
+                "test",
+            )))
+            .build()
+    }
+
+    async fn accept(listener: &TcpListener) -> Agent {
+        let (stream, _) = time::timeout(TIMEOUT, listener.accept())
+            .await
+            .expect("timed out waiting for the client to connect")
+            .unwrap();
+        ServerBuilder::new().accept(stream).await.unwrap()
+    }
+
+    async fn receive(agent: &mut Agent) -> Message {
+        loop {
+            let message = time::timeout(TIMEOUT, agent.next())
+                .await
+                .expect("timed out waiting for the client")
+                .expect("client disconnected")
+                .unwrap();
+
+            if message.is_text() || message.is_binary() {
+                return message;
+            }
+        }
+    }
+
+    /// Returns the token of the OpenDataChannel request the client sends first.
+    async fn receive_token(agent: &mut Agent) -> String {
+        let message = receive(agent).await;
+        let input: serde_json::Value = serde_json::from_str(message.as_text().unwrap()).unwrap();
+        input["TokenValue"].as_str().unwrap().to_string()
+    }
+
+    async fn receive_client_message(agent: &mut Agent) -> ClientMessage {
+        let message = receive(agent).await;
+        ClientMessage::deserialize_client_message(message.as_payload()).unwrap()
+    }
+
+    /// Returns the next message of the type, skipping the others such as resends.
+    async fn receive_of_type(agent: &mut Agent, message_type: MessageType) -> ClientMessage {
+        let receive = async {
+            loop {
+                let message = receive_client_message(agent).await;
+                if message.message_type == message_type {
+                    return message;
+                }
+            }
+        };
+
+        time::timeout(TIMEOUT, receive)
+            .await
+            .unwrap_or_else(|_| panic!("timed out waiting for {} message", message_type))
+    }
+
+    async fn send(agent: &mut Agent, message: ClientMessage) {
+        let content = message.serialize_client_message();
+        agent
+            .send(Message::binary(Bytes::from(content)))
+            .await
+            .unwrap();
+    }
+
+    fn output(sequence_number: i64, payload: &str) -> ClientMessage {
+        ClientMessage::new(
+            MessageType::OutputStreamData,
+            sequence_number,
+            0,
+            PayloadType::Output,
+            payload.as_bytes().to_vec(),
+        )
+    }
+
+    async fn next_event(handle: &mut SessionHandle) -> SessionEvent {
+        time::timeout(TIMEOUT, handle.next())
+            .await
+            .expect("timed out waiting for a session event")
+            .expect("session events ended")
+    }
+
+    /// Returns the next output, failing on any event of the connection in between.
+    async fn next_output(handle: &mut SessionHandle) -> Bytes {
+        loop {
+            match next_event(handle).await {
+                SessionEvent::Output(output) => return output,
+                SessionEvent::SessionTypeSet(_) => {}
+                event => panic!("unexpected {:?}", event),
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn resumes_the_session_after_the_connection_dropped() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let ssm = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint_url = format!("http://{}", ssm.local_addr().unwrap());
+        let mut resume_requests = serve_ssm(
+            ssm,
+            serde_json::json!({
+                "SessionId": "session",
+                "StreamUrl": url,
+                "TokenValue": "token-2",
+            })
+            .to_string(),
+        );
+
+        let session = Session::new(
+            &sdk_config(endpoint_url),
+            "session".to_string(),
+            url.clone(),
+            "token-1".to_string(),
+            "target".to_string(),
+        );
+        let data_channel = session.get_data_channel();
+        let mut handle = session.spawn();
+
+        let mut agent = accept(&listener).await;
+        assert_eq!(receive_token(&mut agent).await, "token-1");
+        assert_eq!(next_event(&mut handle).await, SessionEvent::Connected);
+
+        // The agent acknowledges only the first input message.
+        handle.send_input(b"0").await.unwrap();
+        handle.send_input(b"1").await.unwrap();
+        let first = receive_of_type(&mut agent, MessageType::InputStreamData).await;
+        assert_eq!(first.sequence_number, 0);
+        send(&mut agent, ClientMessage::new_acknowledge(&first).unwrap()).await;
+
+        send(&mut agent, output(0, "a")).await;
+        send(&mut agent, output(1, "b")).await;
+        assert_eq!(next_output(&mut handle).await, "a");
+        assert_eq!(next_output(&mut handle).await, "b");
+
+        // The socket drops before the agent reads the acknowledge of its second message.
+        drop(agent);
+        assert_eq!(next_event(&mut handle).await, SessionEvent::Reconnecting);
+        handle.send_input(b"2").await.unwrap();
+
+        // The session asks SSM for a new token and reconnects with it.
+        let mut agent = accept(&listener).await;
+        assert_eq!(receive_token(&mut agent).await, "token-2");
+        let request = resume_requests.recv().await.unwrap();
+        let request: serde_json::Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(request["SessionId"], "session");
+
+        // Unacknowledged input is replayed in order right after the token.
+        for expected in [1, 2] {
+            let message = receive_client_message(&mut agent).await;
+            assert_eq!(message.message_type, MessageType::InputStreamData);
+            assert_eq!(message.sequence_number, expected);
+            assert_eq!(message.payload, expected.to_string().as_bytes());
+        }
+        assert_eq!(next_event(&mut handle).await, SessionEvent::Connected);
+
+        // The agent resends its unacknowledged message, which is acknowledged again but not
+        // delivered twice.
+        send(&mut agent, output(1, "b")).await;
+        send(&mut agent, output(2, "c")).await;
+        assert_eq!(next_output(&mut handle).await, "c");
+
+        let mut acknowledged = Vec::new();
+        while acknowledged.len() < 2 {
+            let ack = receive_of_type(&mut agent, MessageType::Acknowledge).await;
+            let content = ack.deserialize_data_stream_acknowledge_content().unwrap();
+            acknowledged.push(content.sequence_number);
+        }
+        assert_eq!(acknowledged, [1, 2]);
+
+        let data_channel = data_channel.lock().await;
+        assert_eq!(data_channel.get_expected_sequence_number(), 3);
+        assert_eq!(data_channel.get_stream_data_sequence_number(), 3);
+    }
+
+    fn handshake_message(
+        sequence_number: i64,
+        payload_type: PayloadType,
+        payload: serde_json::Value,
+    ) -> ClientMessage {
+        ClientMessage::new(
+            MessageType::OutputStreamData,
+            sequence_number,
+            0,
+            payload_type,
+            payload.to_string().into_bytes(),
+        )
+    }
+
+    /// Runs the handshake of a shell session as the agent of the version, using sequence numbers
+    /// 0 and 1.
+    async fn complete_handshake(
+        agent: &mut Agent,
+        handle: &mut SessionHandle,
+        agent_version: &str,
+    ) {
+        let request = serde_json::json!({
+            "AgentVersion": agent_version,
+            "RequestedClientActions": [{
+                "ActionType": "SessionType",
+                "ActionParameters": {"SessionType": "Standard_Stream"},
+            }],
+        });
+        let complete = serde_json::json!({"HandshakeTimeToComplete": 0, "CustomerMessage": ""});
+        send(
+            agent,
+            handshake_message(0, PayloadType::HandshakeRequestPayloadType, request),
+        )
+        .await;
+        send(
+            agent,
+            handshake_message(1, PayloadType::HandshakeCompletePayloadType, complete),
+        )
+        .await;
+        loop {
+            if let SessionEvent::HandshakeComplete { .. } = next_event(handle).await {
+                break;
+            }
+        }
+    }
+
+    fn channel_closed(sequence_number: i64, output: &str) -> ClientMessage {
+        let payload = serde_json::json!({
+            "MessageId": Uuid::new_v4(),
+            "CreatedDate": "",
+            "DestinationId": "target",
+            "SessionId": "session",
+            "MessageType": "channel_closed",
+            "SchemaVersion": 1,
+            "Output": output,
+        });
+        ClientMessage::new(
+            MessageType::ChannelClosed,
+            sequence_number,
+            0,
+            PayloadType::Output,
+            payload.to_string().into_bytes(),
+        )
+    }
+
+    #[tokio::test]
+    async fn terminates_with_the_flag_and_closes_the_channel_after_the_agent() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let ssm = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint_url = format!("http://{}", ssm.local_addr().unwrap());
+        let mut ssm_requests = serve_ssm(ssm, r#"{"SessionId": "session"}"#.to_string());
+
+        let mut handle = Session::new(
+            &sdk_config(endpoint_url),
+            "session".to_string(),
+            url,
+            "token".to_string(),
+            "target".to_string(),
+        )
+        .spawn();
+
+        let mut agent = accept(&listener).await;
+        receive_token(&mut agent).await;
+        complete_handshake(&mut agent, &mut handle, "3.2.582.0").await;
+
+        let agent_side = async {
+            let flag = loop {
+                let message = receive_of_type(&mut agent, MessageType::InputStreamData).await;
+                if message.payload_type == PayloadType::Flag {
+                    break message;
+                }
+            };
+            assert_eq!(
+                flag.deserialize_flag().unwrap(),
+                PayloadTypeFlag::TerminateSession
+            );
+
+            // Output still in flight when the agent closes the channel.
+            send(&mut agent, output(2, "bye")).await;
+            send(&mut agent, channel_closed(3, "Session terminated")).await;
+
+            // The client closes the web socket with a close frame, which is answered while the
+            // stream is read to its end.
+            let mut closed = false;
+            while let Some(message) = time::timeout(TIMEOUT, agent.next())
+                .await
+                .expect("timed out waiting for the close frame")
+            {
+                closed |= message.unwrap().is_close();
+            }
+            assert!(closed, "client disconnected without a close frame");
+        };
+
+        let start = time::Instant::now();
+        let (terminated, ()) = tokio::join!(handle.terminate(), agent_side);
+        terminated.unwrap();
+        assert!(start.elapsed() < TERMINATE_SESSION_TIMEOUT);
+
+        let request = ssm_requests.recv().await.unwrap();
+        let request: serde_json::Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(request["SessionId"], "session");
+        assert_eq!(next_output(&mut handle).await, "bye");
+    }
+
+    #[tokio::test]
+    async fn terminates_through_ssm_only_when_the_agent_is_too_old() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("ws://{}", listener.local_addr().unwrap());
+        let ssm = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let endpoint_url = format!("http://{}", ssm.local_addr().unwrap());
+        let mut ssm_requests = serve_ssm(ssm, r#"{"SessionId": "session"}"#.to_string());
+
+        let (notices, mut notice_receiver) = mpsc::unbounded_channel();
+        let mut handle = Session::new(
+            &sdk_config(endpoint_url),
+            "session".to_string(),
+            url,
+            "token".to_string(),
+            "target".to_string(),
+        )
+        .with_notices(notices)
+        .spawn();
+
+        let mut agent = accept(&listener).await;
+        receive_token(&mut agent).await;
+        complete_handshake(&mut agent, &mut handle, "2.3.722.0").await;
+
+        handle.terminate().await.unwrap();
+
+        assert_eq!(
+            notice_receiver.recv().await.unwrap(),
+            SessionNotice::AgentTooOld {
+                agent_version: "2.3.722.0".parse().unwrap(),
+                feature: AgentFeature::TerminateSessionFlag,
+            }
+        );
+        let request = ssm_requests.recv().await.unwrap();
+        let request: serde_json::Value = serde_json::from_str(&request).unwrap();
+        assert_eq!(request["SessionId"], "session");
+
+        // The agent only received the handshake response, no flag.
+        while let Some(Ok(message)) = agent.next().await {
+            if !message.is_binary() {
+                continue;
+            }
+            let message = ClientMessage::deserialize_client_message(message.as_payload()).unwrap();
+            if message.message_type == MessageType::InputStreamData {
+                assert_eq!(
+                    message.payload_type,
+                    PayloadType::HandshakeResponsePayloadType
+                );
+            }
+        }
+    }
 }
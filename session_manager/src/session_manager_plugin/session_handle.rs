@@ -0,0 +1,327 @@
+use crate::config::config::STREAM_DATA_PAYLOAD_SIZE;
+use crate::data_channel::streaming::DataChannel;
+use crate::message::client_message::message::{
+    PayloadType, PayloadTypeFlag, SessionState, SizeData,
+};
+use crate::session_manager_plugin::session_type::SessionType;
+use crate::version::agent_version::{AgentFeature, AgentVersion};
+use anyhow::{anyhow, Result};
+use bytes::Bytes;
+use futures_util::Stream;
+use log::info;
+use std::fmt;
+use std::net::SocketAddr;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+use tokio::sync::mpsc::{UnboundedReceiver, UnboundedSender};
+use tokio::sync::{oneshot, Mutex};
+use tokio::task::JoinHandle;
+
+/// Events emitted by a running session, in the order they happened.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionEvent {
+    /// The data channel is open, either for the first time or after the session was resumed.
+    Connected,
+
+    /// The agent requested the session type, either through the handshake or implicitly by
+    /// sending shell output.
+    SessionTypeSet(Box<SessionType>),
+
+    /// The agent completed the handshake.
+    HandshakeComplete {
+        agent_version: AgentVersion,
+        customer_message: String,
+    },
+
+    /// Output of the remote process.
+    Output(Bytes),
+
+    /// Error output of the remote process, only sent separately by non interactive commands.
+    StdErr(Bytes),
+
+    /// Exit code of the remote process.
+    ExitCode(i32),
+
+    /// Control flag sent by the agent, such as `ConnectToPortError`.
+    Flag(PayloadTypeFlag),
+
+    /// The agent reported a change of the session state.
+    AgentSessionState(SessionState),
+
+    /// The agent asked the client to stop sending input.
+    Paused,
+
+    /// The agent asked the client to resume sending input.
+    Resumed,
+
+    /// The connection dropped and the session is being resumed.
+    Reconnecting,
+
+    /// The session ended, no further events follow.
+    Closed { reason: String },
+}
+
+/// Messages for the user about a running session, such as the local port of a port session.
+/// The library does not write to the terminal, clients choose how to show them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionNotice {
+    /// The local port of a port session accepts connections.
+    PortOpened { session_id: String, port: u16 },
+
+    /// A local connection is forwarded through a port session.
+    ConnectionAccepted {
+        session_id: String,
+        address: SocketAddr,
+    },
+
+    /// The agent failed to connect to the destination of a port session.
+    ConnectToPortFailed { destination: String },
+
+    /// The agent is too old for a feature, the session falls back to doing without it.
+    AgentTooOld {
+        agent_version: AgentVersion,
+        feature: AgentFeature,
+    },
+}
+
+impl fmt::Display for SessionNotice {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SessionNotice::PortOpened { session_id, port } => write!(
+                f,
+                "Port {} opened for sessionId {}.\nWaiting for connections...",
+                port, session_id
+            ),
+            SessionNotice::ConnectionAccepted {
+                session_id,
+                address,
+            } => write!(
+                f,
+                "Connection accepted from {} for session {}.",
+                address, session_id
+            ),
+            SessionNotice::ConnectToPortFailed { destination } => write!(
+                f,
+                "Connection to destination {} failed, check SSM Agent logs.",
+                destination
+            ),
+            SessionNotice::AgentTooOld {
+                agent_version,
+                feature,
+            } => write!(
+                f,
+                "Agent version {} does not support {}, {}.",
+                agent_version,
+                feature,
+                feature.fallback()
+            ),
+        }
+    }
+}
+
+/// How a session ended, returned by `Session::run` once the plugin restored the terminal.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SessionExit {
+    /// Human readable reason, such as the message of the agent when it closed the channel.
+    pub reason: String,
+
+    /// Set when the session ended because of an error, such as an idle timeout or a failure to
+    /// start the session as the run as user.
+    pub error: bool,
+
+    /// Exit code of the remote process, for plugins running a command.
+    pub exit_code: Option<i32>,
+
+    /// Type of the session, unknown when the session closed before the handshake.
+    pub session_type: Option<SessionType>,
+}
+
+impl SessionExit {
+    pub(crate) fn new(reason: String, error: bool) -> Self {
+        Self {
+            reason,
+            error,
+            exit_code: None,
+            session_type: None,
+        }
+    }
+
+    /// Exit code for the local process, the one of the remote process when known.
+    pub fn process_exit_code(&self) -> i32 {
+        self.exit_code.unwrap_or(if self.error { 1 } else { 0 })
+    }
+}
+
+/// Commands sent to the task executing the session.
+pub(crate) enum SessionCommand {
+    Terminate(oneshot::Sender<Result<()>>),
+}
+
+/// Stream of the events of a session, ending after [`SessionEvent::Closed`].
+pub struct SessionEvents {
+    receiver: UnboundedReceiver<SessionEvent>,
+}
+
+impl SessionEvents {
+    pub(crate) fn new(receiver: UnboundedReceiver<SessionEvent>) -> Self {
+        Self { receiver }
+    }
+}
+
+impl Stream for SessionEvents {
+    type Item = SessionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        self.receiver.poll_recv(cx)
+    }
+}
+
+/// Sends input and control requests to a session, can be cloned freely.
+#[derive(Clone)]
+pub struct SessionInput {
+    session_id: String,
+    data_channel: Arc<Mutex<DataChannel>>,
+    commands: UnboundedSender<SessionCommand>,
+    notices: Option<UnboundedSender<SessionNotice>>,
+}
+
+impl SessionInput {
+    pub(crate) fn new(
+        session_id: String,
+        data_channel: Arc<Mutex<DataChannel>>,
+        commands: UnboundedSender<SessionCommand>,
+        notices: Option<UnboundedSender<SessionNotice>>,
+    ) -> Self {
+        Self {
+            session_id,
+            data_channel,
+            commands,
+            notices,
+        }
+    }
+
+    pub fn get_session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    /// Reports a notice to the client, when it asked for them with `Session::with_notices`.
+    pub fn notify(&self, notice: SessionNotice) {
+        notify(&self.notices, notice);
+    }
+
+    /// Sends input to the remote process, split into messages of at most
+    /// `STREAM_DATA_PAYLOAD_SIZE` bytes.
+    pub async fn send_input(&self, data: &[u8]) -> Result<()> {
+        let mut data_channel = self.data_channel.lock().await;
+        for chunk in data.chunks(STREAM_DATA_PAYLOAD_SIZE) {
+            data_channel
+                .send_input_data_message(PayloadType::Output, chunk.to_vec())
+                .await?;
+        }
+
+        Ok(())
+    }
+
+    /// Sends a control flag, such as `DisconnectToPort`, to the agent.
+    pub async fn send_flag(&self, flag: PayloadTypeFlag) -> Result<()> {
+        self.data_channel.lock().await.send_flag(flag).await
+    }
+
+    /// Informs the agent about the size of the local terminal.
+    pub async fn resize(&self, cols: u32, rows: u32) -> Result<()> {
+        let size = serde_json::to_vec(&SizeData { cols, rows })?;
+        self.data_channel
+            .lock()
+            .await
+            .send_input_data_message(PayloadType::Size, size)
+            .await
+    }
+
+    /// Terminates the session and waits until it is closed.
+    pub async fn terminate(&self) -> Result<()> {
+        let (sender, receiver) = oneshot::channel();
+        self.commands
+            .send(SessionCommand::Terminate(sender))
+            .map_err(|_| anyhow!("Session is already closed"))?;
+
+        receiver
+            .await
+            .map_err(|_| anyhow!("Session closed before it was terminated"))?
+    }
+}
+
+/// Logs the notice and sends it to the client, if any.
+pub(crate) fn notify(notices: &Option<UnboundedSender<SessionNotice>>, notice: SessionNotice) {
+    info!("{}", notice);
+    if let Some(notices) = notices {
+        let _ = notices.send(notice);
+    }
+}
+
+/// Handle to a session executing in the background, returned by `Session::spawn`.
+/// Dropping the handle does not stop the session, call [`SessionHandle::terminate`] to end it.
+pub struct SessionHandle {
+    session_id: String,
+    events: SessionEvents,
+    input: SessionInput,
+    task: JoinHandle<SessionExit>,
+}
+
+impl SessionHandle {
+    pub(crate) fn new(
+        session_id: String,
+        events: SessionEvents,
+        input: SessionInput,
+        task: JoinHandle<SessionExit>,
+    ) -> Self {
+        Self {
+            session_id,
+            events,
+            input,
+            task,
+        }
+    }
+
+    pub fn get_session_id(&self) -> &str {
+        &self.session_id
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.task.is_finished()
+    }
+
+    pub async fn send_input(&self, data: &[u8]) -> Result<()> {
+        self.input.send_input(data).await
+    }
+
+    pub async fn resize(&self, cols: u32, rows: u32) -> Result<()> {
+        self.input.resize(cols, rows).await
+    }
+
+    pub async fn terminate(&self) -> Result<()> {
+        self.input.terminate().await
+    }
+
+    /// Splits the handle so events and input can be used from different tasks.
+    pub fn split(self) -> (SessionEvents, SessionInput) {
+        (self.events, self.input)
+    }
+
+    /// Waits until the session ended and returns how it ended.
+    pub async fn wait(self) -> Result<SessionExit> {
+        Ok(self.task.await?)
+    }
+
+    pub(crate) fn into_parts(self) -> (SessionEvents, SessionInput, JoinHandle<SessionExit>) {
+        (self.events, self.input, self.task)
+    }
+}
+
+impl Stream for SessionHandle {
+    type Item = SessionEvent;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        Pin::new(&mut self.events).poll_next(cx)
+    }
+}
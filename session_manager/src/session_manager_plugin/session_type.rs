@@ -0,0 +1,319 @@
+use crate::config::config::{
+    INTERACTIVE_COMMANDS_PLUGIN_NAME, NON_INTERACTIVE_COMMANDS_PLUGIN_NAME, PORT_PLUGIN_NAME,
+    SHELL_PLUGIN_NAME,
+};
+use crate::message::handshake_message::message::SessionTypeRequest;
+use anyhow::{Context, Result};
+use serde::de::Error;
+use serde::{Deserialize, Deserializer};
+use std::fmt;
+
+/// Type of the session requested by the agent during the handshake, together with the
+/// properties of the session document for the plugin handling it.
+#[derive(Debug, Clone, PartialEq)]
+pub enum SessionType {
+    StandardStream(ShellProperties),
+    Port(PortProperties),
+    InteractiveCommands(ShellProperties),
+    NonInteractiveCommands(ShellProperties),
+    Unknown {
+        session_type: String,
+        properties: serde_json::Value,
+    },
+}
+
+impl SessionType {
+    /// Parses the session type and validates its properties.
+    pub fn from_request(request: SessionTypeRequest) -> Result<Self> {
+        let properties = request.properties;
+
+        let session_type = match request.session_type.as_str() {
+            SHELL_PLUGIN_NAME => Self::StandardStream(parse_shell_properties(properties)?),
+            PORT_PLUGIN_NAME => Self::Port(parse_properties(properties)?),
+            INTERACTIVE_COMMANDS_PLUGIN_NAME => {
+                Self::InteractiveCommands(parse_shell_properties(properties)?)
+            }
+            NON_INTERACTIVE_COMMANDS_PLUGIN_NAME => {
+                Self::NonInteractiveCommands(parse_shell_properties(properties)?)
+            }
+            _ => Self::Unknown {
+                session_type: request.session_type,
+                properties,
+            },
+        };
+
+        Ok(session_type)
+    }
+
+    /// Returns the plugin name of the session type as sent by the agent.
+    pub fn name(&self) -> &str {
+        match self {
+            Self::StandardStream(_) => SHELL_PLUGIN_NAME,
+            Self::Port(_) => PORT_PLUGIN_NAME,
+            Self::InteractiveCommands(_) => INTERACTIVE_COMMANDS_PLUGIN_NAME,
+            Self::NonInteractiveCommands(_) => NON_INTERACTIVE_COMMANDS_PLUGIN_NAME,
+            Self::Unknown { session_type, .. } => session_type,
+        }
+    }
+
+    /// Returns whether the session is attached to the local terminal.
+    pub fn is_terminal(&self) -> bool {
+        matches!(self, Self::StandardStream(_) | Self::InteractiveCommands(_))
+    }
+}
+
+impl Default for SessionType {
+    /// Agents predating the handshake only support shell sessions.
+    fn default() -> Self {
+        Self::StandardStream(ShellProperties::default())
+    }
+}
+
+impl fmt::Display for SessionType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+/// Properties of shell and command sessions, per platform of the target.
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default)]
+pub struct ShellProperties {
+    pub windows: ShellConfig,
+    pub linux: ShellConfig,
+    pub macos: ShellConfig,
+}
+
+#[derive(Deserialize, Debug, Default, Clone, PartialEq)]
+#[serde(default, rename_all = "camelCase")]
+pub struct ShellConfig {
+    pub commands: String,
+    #[serde(deserialize_with = "bool_or_string")]
+    pub run_as_elevated: bool,
+    #[serde(deserialize_with = "bool_or_string")]
+    pub separate_output_stream: bool,
+    pub std_out_separator_prefix: String,
+    pub std_err_separator_prefix: String,
+}
+
+/// Properties of port forwarding sessions.
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "camelCase")]
+pub struct PortProperties {
+    /// Port on the target, or on the remote host, the agent connects to.
+    #[serde(deserialize_with = "port_number")]
+    pub port_number: u16,
+    /// Local port to listen on, a random port is used when not set.
+    #[serde(default, deserialize_with = "optional_port_number")]
+    pub local_port_number: Option<u16>,
+    #[serde(default, rename = "type")]
+    pub port_type: String,
+    /// Remote host the agent forwards to, only set by remote host port forwarding documents.
+    #[serde(default)]
+    pub host: Option<String>,
+}
+
+impl PortProperties {
+    /// Describes where the agent connects to, such as `5432` or `db.example.com:5432`.
+    pub fn destination(&self) -> String {
+        match self.host.as_deref() {
+            Some(host) if !host.is_empty() => format!("{}:{}", host, self.port_number),
+            _ => self.port_number.to_string(),
+        }
+    }
+}
+
+fn parse_properties<T: for<'de> Deserialize<'de>>(properties: serde_json::Value) -> Result<T> {
+    serde_json::from_value(properties).context("Invalid session properties")
+}
+
+/// Shell documents may not define any properties.
+fn parse_shell_properties(properties: serde_json::Value) -> Result<ShellProperties> {
+    if properties.is_null() {
+        return Ok(ShellProperties::default());
+    }
+
+    parse_properties(properties)
+}
+
+/// Document parameters are strings, so booleans may be sent either way.
+fn bool_or_string<'de, D: Deserializer<'de>>(deserializer: D) -> Result<bool, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Bool(value) => Ok(value),
+        serde_json::Value::String(value) => match value.trim() {
+            "" => Ok(false),
+            value => value.parse().map_err(D::Error::custom),
+        },
+        serde_json::Value::Null => Ok(false),
+        value => Err(D::Error::custom(format!(
+            "expected a boolean, got {}",
+            value
+        ))),
+    }
+}
+
+fn port_number<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u16, D::Error> {
+    let port = match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Number(value) => value.to_string(),
+        serde_json::Value::String(value) => value,
+        value => return Err(D::Error::custom(format!("expected a port, got {}", value))),
+    };
+
+    port.trim()
+        .parse()
+        .map_err(|_| D::Error::custom(format!("invalid port number {:?}", port)))
+}
+
+/// Document parameters without a value are sent as empty strings.
+fn optional_port_number<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u16>, D::Error> {
+    match serde_json::Value::deserialize(deserializer)? {
+        serde_json::Value::Null => Ok(None),
+        serde_json::Value::String(value) if value.trim().is_empty() => Ok(None),
+        value => port_number(value).map(Some).map_err(D::Error::custom),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn session_type(session_type: &str, properties: serde_json::Value) -> Result<SessionType> {
+        SessionType::from_request(SessionTypeRequest {
+            session_type: session_type.to_string(),
+            properties,
+        })
+    }
+
+    fn port(properties: serde_json::Value) -> Result<PortProperties> {
+        match session_type(PORT_PLUGIN_NAME, properties)? {
+            SessionType::Port(properties) => Ok(properties),
+            session_type => panic!("unexpected {:?}", session_type),
+        }
+    }
+
+    #[test]
+    fn parses_the_session_type_of_every_plugin() {
+        let shell = json!({"linux": {"commands": "bash", "runAsElevated": true}});
+
+        let SessionType::StandardStream(properties) =
+            session_type(SHELL_PLUGIN_NAME, shell.clone()).unwrap()
+        else {
+            panic!("expected a shell session");
+        };
+        assert_eq!(properties.linux.commands, "bash");
+        assert!(properties.linux.run_as_elevated);
+
+        assert!(matches!(
+            session_type(INTERACTIVE_COMMANDS_PLUGIN_NAME, shell.clone()).unwrap(),
+            SessionType::InteractiveCommands(_)
+        ));
+        assert!(matches!(
+            session_type(NON_INTERACTIVE_COMMANDS_PLUGIN_NAME, shell).unwrap(),
+            SessionType::NonInteractiveCommands(_)
+        ));
+        assert!(matches!(
+            session_type(PORT_PLUGIN_NAME, json!({"portNumber": "22"})).unwrap(),
+            SessionType::Port(_)
+        ));
+    }
+
+    #[test]
+    fn keeps_unknown_session_types_with_their_properties() {
+        let session_type = session_type("Custom_Plugin", json!({"key": "value"})).unwrap();
+
+        assert_eq!(
+            session_type,
+            SessionType::Unknown {
+                session_type: "Custom_Plugin".to_string(),
+                properties: json!({"key": "value"}),
+            }
+        );
+        assert_eq!(session_type.name(), "Custom_Plugin");
+    }
+
+    #[test]
+    fn defaults_shell_properties_when_there_are_none() {
+        assert_eq!(
+            session_type(SHELL_PLUGIN_NAME, serde_json::Value::Null).unwrap(),
+            SessionType::StandardStream(ShellProperties::default())
+        );
+    }
+
+    #[test]
+    fn accepts_ports_as_numbers_or_strings() {
+        let properties = port(json!({"portNumber": " 5432 ", "type": "LocalPortForwarding"}));
+        let properties = properties.unwrap();
+        assert_eq!(properties.port_number, 5432);
+        assert_eq!(properties.port_type, "LocalPortForwarding");
+
+        assert_eq!(port(json!({"portNumber": 80})).unwrap().port_number, 80);
+    }
+
+    #[test]
+    fn rejects_invalid_ports() {
+        for properties in [
+            json!({}),
+            json!({"portNumber": "http"}),
+            json!({"portNumber": 65536}),
+            json!({"portNumber": -1}),
+            json!({"portNumber": true}),
+        ] {
+            assert!(port(properties.clone()).is_err(), "{}", properties);
+        }
+    }
+
+    #[test]
+    fn leaves_the_local_port_unset_when_empty() {
+        let local_port = |value: serde_json::Value| {
+            port(json!({"portNumber": 22, "localPortNumber": value}))
+                .map(|properties| properties.local_port_number)
+        };
+
+        assert_eq!(local_port(json!(2222)).unwrap(), Some(2222));
+        assert_eq!(local_port(json!("2222")).unwrap(), Some(2222));
+        assert_eq!(local_port(json!("")).unwrap(), None);
+        assert_eq!(local_port(json!(null)).unwrap(), None);
+        assert!(local_port(json!("x")).is_err());
+        assert_eq!(
+            port(json!({"portNumber": 22})).unwrap().local_port_number,
+            None
+        );
+    }
+
+    #[test]
+    fn describes_the_destination_with_the_remote_host() {
+        let destination = |properties: serde_json::Value| port(properties).unwrap().destination();
+
+        assert_eq!(destination(json!({"portNumber": 22})), "22");
+        assert_eq!(
+            destination(json!({"portNumber": "5432", "host": "db.internal"})),
+            "db.internal:5432"
+        );
+        assert_eq!(destination(json!({"portNumber": 22, "host": ""})), "22");
+    }
+
+    #[test]
+    fn accepts_booleans_as_strings() {
+        let parse = |value: serde_json::Value| {
+            session_type(
+                SHELL_PLUGIN_NAME,
+                json!({"linux": {"runAsElevated": value}}),
+            )
+            .map(|session_type| match session_type {
+                SessionType::StandardStream(properties) => properties.linux.run_as_elevated,
+                _ => unreachable!(),
+            })
+        };
+
+        assert!(parse(json!(true)).unwrap());
+        assert!(parse(json!("true")).unwrap());
+        assert!(!parse(json!("false")).unwrap());
+        assert!(!parse(json!("")).unwrap());
+        assert!(!parse(json!(null)).unwrap());
+        assert!(parse(json!("yes")).is_err());
+        assert!(parse(json!(1)).is_err());
+    }
+}
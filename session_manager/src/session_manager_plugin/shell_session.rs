@@ -0,0 +1,611 @@
+use crate::config::config::{
+    DEFAULT_ESCAPE_CHAR, RESIZE_DEBOUNCE_INTERVAL, RESIZE_SLEEP_INTERVAL, SHELL_PLUGIN_NAME,
+};
+use crate::session_manager_plugin::escape_sequence::{EscapeCommand, EscapeSequences};
+use crate::session_manager_plugin::session::ISessionPlugin;
+use crate::session_manager_plugin::session_handle::{SessionEvent, SessionEvents, SessionInput};
+use crate::session_manager_plugin::session_type::SessionType;
+use crate::terminal::terminal_guard::TerminalGuard;
+use crate::version::agent_version::AgentVersion;
+use anyhow::Result;
+use crossterm::event::{Event, EventStream, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+use crossterm::terminal;
+use futures_util::future::BoxFuture;
+use futures_util::StreamExt;
+use log::{debug, warn};
+use std::pin::Pin;
+use std::time::Duration;
+use tokio::io::{self, AsyncWriteExt};
+use tokio::time::{self, Instant, MissedTickBehavior, Sleep};
+
+const ESC: u8 = 0x1b;
+
+/// Plugin for shell sessions, puts the terminal into raw mode, forwards key presses to the
+/// session and writes the session output to stdout unmodified.
+pub struct ShellSession {
+    /// Restores the terminal once the session ends, however it ends.
+    terminal: Option<TerminalGuard>,
+
+    /// Escape sequences acting locally, None when disabled.
+    escape_sequences: Option<EscapeSequences>,
+
+    session_type: Option<SessionType>,
+    agent_version: Option<AgentVersion>,
+
+    /// Set while the remote application enabled application cursor keys (DECCKM), e.g. vim.
+    application_cursor_keys: bool,
+
+    /// Last terminal size sent to the agent.
+    terminal_size: Option<(u16, u16)>,
+
+    /// Ends the session once the remote process exited, for sessions bound to a command.
+    end_on_exit_code: bool,
+
+    exit_code: Option<i32>,
+}
+
+impl Default for ShellSession {
+    fn default() -> Self {
+        Self {
+            terminal: None,
+            escape_sequences: Some(EscapeSequences::new(DEFAULT_ESCAPE_CHAR)),
+            session_type: None,
+            agent_version: None,
+            application_cursor_keys: false,
+            terminal_size: None,
+            end_on_exit_code: false,
+            exit_code: None,
+        }
+    }
+}
+
+impl ShellSession {
+    /// Creates a plugin ending the session when the remote command exits.
+    pub(crate) fn for_command() -> Self {
+        Self {
+            end_on_exit_code: true,
+            ..Self::default()
+        }
+    }
+
+    /// Sets the character starting escape sequences, None disables them.
+    pub fn with_escape_char(mut self, escape_char: Option<u8>) -> Self {
+        self.escape_sequences = escape_char.map(EscapeSequences::new);
+        self
+    }
+
+    /// Sends the size of the terminal if it changed since it was sent last.
+    async fn send_terminal_size(&mut self, input: &SessionInput, size: (u16, u16)) -> Result<()> {
+        if self.terminal_size == Some(size) {
+            return Ok(());
+        }
+
+        debug!("Terminal resized to {}x{}", size.0, size.1);
+        input.resize(size.0 as u32, size.1 as u32).await?;
+        self.terminal_size = Some(size);
+
+        Ok(())
+    }
+
+    /// Follows the cursor key mode requested by the remote application, the last request wins.
+    fn track_cursor_key_mode(&mut self, output: &[u8]) {
+        const SET: &[u8] = b"\x1b[?1h";
+        const RESET: &[u8] = b"\x1b[?1l";
+
+        let last_set = output.windows(SET.len()).rposition(|w| w == SET);
+        let last_reset = output.windows(RESET.len()).rposition(|w| w == RESET);
+
+        match (last_set, last_reset) {
+            (Some(set), Some(reset)) => self.application_cursor_keys = set > reset,
+            (Some(_), None) => self.application_cursor_keys = true,
+            (None, Some(_)) => self.application_cursor_keys = false,
+            (None, None) => {}
+        }
+    }
+
+    /// Sends the input of a key press, after acting on the escape sequences it completes. Returns
+    /// false once the session is disconnected.
+    async fn send_key_input(&mut self, input: &SessionInput, bytes: &[u8]) -> Result<bool> {
+        let Some(escape_sequences) = &mut self.escape_sequences else {
+            input.send_input(bytes).await?;
+            return Ok(true);
+        };
+
+        let (bytes, commands) = escape_sequences.process(bytes);
+        if !bytes.is_empty() {
+            input.send_input(&bytes).await?;
+        }
+
+        let mut stdout = io::stdout();
+        for command in commands {
+            match command {
+                EscapeCommand::Disconnect => {
+                    debug!("Disconnecting session {}", input.get_session_id());
+                    if let Err(e) = input.terminate().await {
+                        warn!("Failed to terminate session: {}", e);
+                    }
+                    return Ok(false);
+                }
+                EscapeCommand::Help => {
+                    if let Some(escape_sequences) = &self.escape_sequences {
+                        let help = format!("\r\n{}", escape_sequences.help());
+                        stdout.write_all(help.as_bytes()).await?;
+                    }
+                }
+                EscapeCommand::Info => {
+                    let info = self.info(input);
+                    stdout.write_all(info.as_bytes()).await?;
+                }
+            }
+            stdout.flush().await?;
+        }
+
+        Ok(true)
+    }
+
+    /// Describes the session for the `~#` escape sequence.
+    fn info(&self, input: &SessionInput) -> String {
+        let mut info = format!("\r\nSession {}\r\n", input.get_session_id());
+        if let Some(session_type) = &self.session_type {
+            info.push_str(&format!("  type: {}\r\n", session_type));
+        }
+        if let Some(agent_version) = &self.agent_version {
+            info.push_str(&format!("  agent version: {}\r\n", agent_version));
+        }
+        if let Some((cols, rows)) = self.terminal_size {
+            info.push_str(&format!("  terminal size: {}x{}\r\n", cols, rows));
+        }
+        if let Some(exit_code) = self.exit_code {
+            info.push_str(&format!("  remote exit code: {}\r\n", exit_code));
+        }
+
+        info
+    }
+
+    /// Converts a terminal event to the bytes a terminal would send for it.
+    fn encode_event(&self, event: &Event) -> Option<Vec<u8>> {
+        match event {
+            Event::Key(key) => encode_key_event(key, self.application_cursor_keys),
+            Event::Paste(text) => Some(text.as_bytes().to_vec()),
+            _ => None,
+        }
+    }
+}
+
+impl ISessionPlugin for ShellSession {
+    fn initialize(&mut self, session_type: &SessionType) -> Result<()> {
+        self.terminal = Some(TerminalGuard::raw_mode()?);
+        self.session_type = Some(session_type.clone());
+
+        Ok(())
+    }
+
+    fn set_session_handlers(
+        &mut self,
+        mut events: SessionEvents,
+        input: SessionInput,
+    ) -> BoxFuture<'_, Result<()>> {
+        Box::pin(async move {
+            // The agent starts the shell with the first size it receives.
+            match terminal::size() {
+                Ok(size) => self.send_terminal_size(&input, size).await?,
+                Err(e) => warn!("Could not get size of the terminal: {}", e),
+            }
+
+            let mut stdout = io::stdout();
+            let mut terminal_events = EventStream::new();
+            let mut stdin_open = true;
+
+            let mut resize_debounce = ResizeDebounce::new(RESIZE_DEBOUNCE_INTERVAL);
+
+            // Polls the size as well, for terminals that do not report resizes.
+            let mut resize_poll = time::interval(RESIZE_SLEEP_INTERVAL);
+            resize_poll.set_missed_tick_behavior(MissedTickBehavior::Delay);
+
+            loop {
+                tokio::select! {
+                    event = events.next() => match event {
+                        Some(SessionEvent::HandshakeComplete {
+                            agent_version,
+                            customer_message,
+                        }) => {
+                            self.agent_version = Some(agent_version);
+                            if !customer_message.is_empty() {
+                                stdout.write_all(customer_message.as_bytes()).await?;
+                                stdout.write_all(b"\r\n").await?;
+                            }
+                        }
+                        Some(SessionEvent::Output(output)) => {
+                            self.track_cursor_key_mode(&output);
+                            if let Some(terminal) = &self.terminal {
+                                terminal.track_output(&output);
+                            }
+                            stdout.write_all(&output).await?;
+                            stdout.flush().await?;
+                        }
+                        Some(SessionEvent::ExitCode(exit_code)) => {
+                            debug!("Remote process exited with {}", exit_code);
+                            self.exit_code = Some(exit_code);
+                            if self.end_on_exit_code {
+                                if let Err(e) = input.terminate().await {
+                                    debug!("Failed to terminate session: {}", e);
+                                }
+                                break;
+                            }
+                        }
+                        Some(SessionEvent::Closed { reason }) => {
+                            debug!("{}", reason);
+                            break;
+                        }
+                        Some(event) => debug!("{:?}", event),
+                        None => break,
+                    },
+                    event = terminal_events.next(), if stdin_open => match event {
+                        Some(Ok(Event::Resize(cols, rows))) => resize_debounce.resized((cols, rows)),
+                        Some(Ok(event @ Event::Key(_))) => {
+                            if let Some(bytes) = self.encode_event(&event) {
+                                if !self.send_key_input(&input, &bytes).await? {
+                                    break;
+                                }
+                            }
+                        }
+                        Some(Ok(event)) => {
+                            if let Some(bytes) = self.encode_event(&event) {
+                                input.send_input(&bytes).await?;
+                            }
+                        }
+                        Some(Err(e)) => return Err(e.into()),
+                        None => stdin_open = false,
+                    },
+                    Some(size) = resize_debounce.settled(), if resize_debounce.is_pending() => {
+                        self.send_terminal_size(&input, size).await?;
+                    }
+                    _ = resize_poll.tick(), if !resize_debounce.is_pending() => {
+                        if let Ok(size) = terminal::size() {
+                            self.send_terminal_size(&input, size).await?;
+                        }
+                    }
+                }
+            }
+
+            Ok(())
+        })
+    }
+
+    fn stop(&mut self) {
+        self.terminal = None;
+    }
+
+    fn name(&self) -> &str {
+        SHELL_PLUGIN_NAME
+    }
+
+    fn exit_code(&self) -> Option<i32> {
+        self.exit_code
+    }
+}
+
+/// Resize events arrive in bursts while a window is dragged, only the last one is sent once the
+/// terminal kept its size for the debounce interval.
+struct ResizeDebounce {
+    interval: Duration,
+    pending_size: Option<(u16, u16)>,
+    deadline: Pin<Box<Sleep>>,
+}
+
+impl ResizeDebounce {
+    fn new(interval: Duration) -> Self {
+        Self {
+            interval,
+            pending_size: None,
+            deadline: Box::pin(time::sleep(interval)),
+        }
+    }
+
+    /// Replaces the pending size and restarts the debounce interval.
+    fn resized(&mut self, size: (u16, u16)) {
+        self.pending_size = Some(size);
+        self.deadline.as_mut().reset(Instant::now() + self.interval);
+    }
+
+    fn is_pending(&self) -> bool {
+        self.pending_size.is_some()
+    }
+
+    /// Waits for the debounce interval to pass since the last resize and takes the pending size.
+    async fn settled(&mut self) -> Option<(u16, u16)> {
+        self.deadline.as_mut().await;
+        self.pending_size.take()
+    }
+}
+
+/// Converts a key press to the byte sequence an xterm compatible terminal sends for it.
+/// Returns None for key releases and keys without a sequence.
+pub fn encode_key_event(key: &KeyEvent, application_cursor_keys: bool) -> Option<Vec<u8>> {
+    if key.kind == KeyEventKind::Release {
+        return None;
+    }
+
+    let alt = key.modifiers.contains(KeyModifiers::ALT);
+    let ctrl = key.modifiers.contains(KeyModifiers::CONTROL);
+
+    let mut bytes = match key.code {
+        KeyCode::Char(c) if ctrl => vec![control_character(c)?],
+        KeyCode::Char(c) => c.to_string().into_bytes(),
+        KeyCode::Enter => vec![b'\r'],
+        KeyCode::Tab => vec![b'\t'],
+        KeyCode::BackTab => return Some(b"\x1b[Z".to_vec()),
+        KeyCode::Backspace if ctrl => vec![0x08],
+        KeyCode::Backspace => vec![0x7f],
+        KeyCode::Esc => vec![ESC],
+        KeyCode::Null => vec![0x00],
+        KeyCode::Up => return Some(cursor_key(b'A', key.modifiers, application_cursor_keys)),
+        KeyCode::Down => return Some(cursor_key(b'B', key.modifiers, application_cursor_keys)),
+        KeyCode::Right => return Some(cursor_key(b'C', key.modifiers, application_cursor_keys)),
+        KeyCode::Left => return Some(cursor_key(b'D', key.modifiers, application_cursor_keys)),
+        KeyCode::Home => return Some(cursor_key(b'H', key.modifiers, application_cursor_keys)),
+        KeyCode::End => return Some(cursor_key(b'F', key.modifiers, application_cursor_keys)),
+        KeyCode::Insert => return Some(tilde_key(2, key.modifiers)),
+        KeyCode::Delete => return Some(tilde_key(3, key.modifiers)),
+        KeyCode::PageUp => return Some(tilde_key(5, key.modifiers)),
+        KeyCode::PageDown => return Some(tilde_key(6, key.modifiers)),
+        KeyCode::F(n) => return function_key(n, key.modifiers),
+        _ => return None,
+    };
+
+    // Meta sends escape as prefix.
+    if alt {
+        bytes.insert(0, ESC);
+    }
+
+    Some(bytes)
+}
+
+/// Maps Ctrl combined with a character to its C0 control code.
+fn control_character(c: char) -> Option<u8> {
+    match c.to_ascii_lowercase() {
+        c @ 'a'..='z' => Some(c as u8 - b'a' + 1),
+        '@' | ' ' | '2' => Some(0x00),
+        '[' | '3' => Some(ESC),
+        '\\' | '4' => Some(0x1c),
+        ']' | '5' => Some(0x1d),
+        '^' | '6' => Some(0x1e),
+        '_' | '-' | '7' => Some(0x1f),
+        '?' | '8' => Some(0x7f),
+        _ => None,
+    }
+}
+
+/// Returns the xterm modifier parameter, or None without modifiers.
+fn modifier_parameter(modifiers: KeyModifiers) -> Option<u8> {
+    let mut parameter = 1;
+    if modifiers.contains(KeyModifiers::SHIFT) {
+        parameter += 1;
+    }
+    if modifiers.contains(KeyModifiers::ALT) {
+        parameter += 2;
+    }
+    if modifiers.contains(KeyModifiers::CONTROL) {
+        parameter += 4;
+    }
+
+    (parameter > 1).then_some(parameter)
+}
+
+fn cursor_key(code: u8, modifiers: KeyModifiers, application_cursor_keys: bool) -> Vec<u8> {
+    match modifier_parameter(modifiers) {
+        Some(parameter) => format!("\x1b[1;{}{}", parameter, code as char).into_bytes(),
+        None if application_cursor_keys => vec![ESC, b'O', code],
+        None => vec![ESC, b'[', code],
+    }
+}
+
+fn tilde_key(number: u8, modifiers: KeyModifiers) -> Vec<u8> {
+    match modifier_parameter(modifiers) {
+        Some(parameter) => format!("\x1b[{};{}~", number, parameter).into_bytes(),
+        None => format!("\x1b[{}~", number).into_bytes(),
+    }
+}
+
+fn function_key(n: u8, modifiers: KeyModifiers) -> Option<Vec<u8>> {
+    let parameter = modifier_parameter(modifiers);
+
+    let code = match n {
+        1..=4 => {
+            let code = b"PQRS"[n as usize - 1] as char;
+            return Some(match parameter {
+                Some(parameter) => format!("\x1b[1;{}{}", parameter, code).into_bytes(),
+                None => format!("\x1bO{}", code).into_bytes(),
+            });
+        }
+        5 => 15,
+        6 => 17,
+        7 => 18,
+        8 => 19,
+        9 => 20,
+        10 => 21,
+        11 => 23,
+        12 => 24,
+        _ => return None,
+    };
+
+    Some(match parameter {
+        Some(parameter) => format!("\x1b[{};{}~", code, parameter).into_bytes(),
+        None => format!("\x1b[{}~", code).into_bytes(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(code: KeyCode, modifiers: KeyModifiers, application_cursor_keys: bool) -> Vec<u8> {
+        encode_key_event(&KeyEvent::new(code, modifiers), application_cursor_keys).unwrap()
+    }
+
+    fn plain(code: KeyCode) -> Vec<u8> {
+        encode(code, KeyModifiers::NONE, false)
+    }
+
+    #[test]
+    fn encodes_characters() {
+        assert_eq!(plain(KeyCode::Char('a')), b"a");
+        assert_eq!(plain(KeyCode::Char('é')), "é".as_bytes());
+        assert_eq!(plain(KeyCode::Enter), b"\r");
+        assert_eq!(plain(KeyCode::Tab), b"\t");
+        assert_eq!(plain(KeyCode::BackTab), b"\x1b[Z");
+        assert_eq!(plain(KeyCode::Backspace), b"\x7f");
+        assert_eq!(plain(KeyCode::Esc), b"\x1b");
+    }
+
+    #[test]
+    fn encodes_control_and_alt_characters() {
+        let ctrl = |c| encode(KeyCode::Char(c), KeyModifiers::CONTROL, false);
+
+        assert_eq!(ctrl('c'), b"\x03");
+        assert_eq!(ctrl('C'), b"\x03");
+        assert_eq!(ctrl('@'), b"\x00");
+        assert_eq!(ctrl(']'), b"\x1d");
+        assert_eq!(ctrl('?'), b"\x7f");
+        assert_eq!(
+            encode(KeyCode::Backspace, KeyModifiers::CONTROL, false),
+            b"\x08"
+        );
+        assert_eq!(
+            encode(KeyCode::Char('b'), KeyModifiers::ALT, false),
+            b"\x1bb"
+        );
+        assert_eq!(
+            encode(
+                KeyCode::Char('x'),
+                KeyModifiers::ALT | KeyModifiers::CONTROL,
+                false
+            ),
+            b"\x1b\x18"
+        );
+        assert_eq!(
+            encode_key_event(
+                &KeyEvent::new(KeyCode::Char('!'), KeyModifiers::CONTROL),
+                false
+            ),
+            None
+        );
+    }
+
+    #[test]
+    fn encodes_cursor_keys_in_both_modes() {
+        assert_eq!(plain(KeyCode::Up), b"\x1b[A");
+        assert_eq!(plain(KeyCode::Home), b"\x1b[H");
+        assert_eq!(encode(KeyCode::Up, KeyModifiers::NONE, true), b"\x1bOA");
+        assert_eq!(encode(KeyCode::End, KeyModifiers::NONE, true), b"\x1bOF");
+    }
+
+    #[test]
+    fn encodes_modified_cursor_keys_in_application_mode() {
+        // Modifiers always use the CSI form, whatever the cursor key mode.
+        for application_cursor_keys in [false, true] {
+            assert_eq!(
+                encode(
+                    KeyCode::Left,
+                    KeyModifiers::CONTROL,
+                    application_cursor_keys
+                ),
+                b"\x1b[1;5D"
+            );
+            assert_eq!(
+                encode(KeyCode::Right, KeyModifiers::SHIFT, application_cursor_keys),
+                b"\x1b[1;2C"
+            );
+            assert_eq!(
+                encode(KeyCode::Up, KeyModifiers::ALT, application_cursor_keys),
+                b"\x1b[1;3A"
+            );
+            assert_eq!(
+                encode(
+                    KeyCode::Down,
+                    KeyModifiers::SHIFT | KeyModifiers::ALT | KeyModifiers::CONTROL,
+                    application_cursor_keys
+                ),
+                b"\x1b[1;8B"
+            );
+        }
+    }
+
+    #[test]
+    fn encodes_editing_and_function_keys() {
+        assert_eq!(plain(KeyCode::Delete), b"\x1b[3~");
+        assert_eq!(plain(KeyCode::PageDown), b"\x1b[6~");
+        assert_eq!(
+            encode(KeyCode::PageUp, KeyModifiers::CONTROL, false),
+            b"\x1b[5;5~"
+        );
+        assert_eq!(plain(KeyCode::F(1)), b"\x1bOP");
+        assert_eq!(
+            encode(KeyCode::F(4), KeyModifiers::SHIFT, false),
+            b"\x1b[1;2S"
+        );
+        assert_eq!(plain(KeyCode::F(5)), b"\x1b[15~");
+        assert_eq!(plain(KeyCode::F(12)), b"\x1b[24~");
+        assert_eq!(
+            encode(KeyCode::F(11), KeyModifiers::CONTROL, false),
+            b"\x1b[23;5~"
+        );
+        assert_eq!(
+            encode_key_event(&KeyEvent::new(KeyCode::F(13), KeyModifiers::NONE), false),
+            None
+        );
+    }
+
+    #[test]
+    fn ignores_key_releases() {
+        let mut key = KeyEvent::new(KeyCode::Char('a'), KeyModifiers::NONE);
+        key.kind = KeyEventKind::Release;
+
+        assert_eq!(encode_key_event(&key, false), None);
+    }
+
+    #[tokio::test]
+    async fn sends_only_the_last_size_of_a_burst() {
+        let mut debounce = ResizeDebounce::new(Duration::from_millis(20));
+        assert!(!debounce.is_pending());
+
+        debounce.resized((80, 24));
+        debounce.resized((100, 30));
+        debounce.resized((120, 40));
+        assert!(debounce.is_pending());
+
+        assert_eq!(debounce.settled().await, Some((120, 40)));
+        assert!(!debounce.is_pending());
+    }
+
+    #[tokio::test]
+    async fn restarts_the_interval_on_every_resize() {
+        let interval = Duration::from_millis(50);
+        let mut debounce = ResizeDebounce::new(interval);
+        let start = Instant::now();
+
+        debounce.resized((80, 24));
+        time::sleep(Duration::from_millis(30)).await;
+        debounce.resized((100, 30));
+
+        assert_eq!(debounce.settled().await, Some((100, 30)));
+        assert!(start.elapsed() >= Duration::from_millis(30) + interval);
+    }
+
+    #[test]
+    fn follows_the_last_cursor_key_mode_request() {
+        let mut session = ShellSession::default();
+
+        session.track_cursor_key_mode(b"\x1b[?1h\x1b=");
+        assert!(session.application_cursor_keys);
+
+        session.track_cursor_key_mode(b"plain output");
+        assert!(session.application_cursor_keys);
+
+        session.track_cursor_key_mode(b"\x1b[?1h\x1b[?1l");
+        assert!(!session.application_cursor_keys);
+
+        session.track_cursor_key_mode(b"\x1b[?1l\x1b[?1h");
+        assert!(session.application_cursor_keys);
+    }
+}
@@ -0,0 +1,133 @@
+use anyhow::{bail, Result};
+use bytes::Bytes;
+
+pub const PROTOCOL_VERSION: u8 = 1;
+pub const HEADER_SIZE: usize = 8;
+
+/// Command of a frame.
+#[derive(Debug, Clone, Copy, PartialEq)]
+#[repr(u8)]
+pub enum Command {
+    /// Opens a stream.
+    Syn = 0,
+    /// Closes a stream.
+    Fin = 1,
+    /// Carries stream data.
+    Psh = 2,
+    /// Keeps the connection alive.
+    Nop = 3,
+    /// Updates the receive window, protocol version 2 only.
+    Upd = 4,
+}
+
+impl TryFrom<u8> for Command {
+    type Error = anyhow::Error;
+
+    fn try_from(value: u8) -> Result<Self> {
+        Ok(match value {
+            0 => Self::Syn,
+            1 => Self::Fin,
+            2 => Self::Psh,
+            3 => Self::Nop,
+            4 => Self::Upd,
+            _ => bail!("Invalid smux command {}", value),
+        })
+    }
+}
+
+/// Frame header layout, all numbers little endian:
+///
+/// | version (1) | command (1) | length (2) | stream id (4) |
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Header {
+    pub version: u8,
+    pub command: Command,
+    pub length: u16,
+    pub stream_id: u32,
+}
+
+impl Header {
+    pub fn decode(bytes: &[u8; HEADER_SIZE]) -> Result<Self> {
+        Ok(Self {
+            version: bytes[0],
+            command: Command::try_from(bytes[1])?,
+            length: u16::from_le_bytes([bytes[2], bytes[3]]),
+            stream_id: u32::from_le_bytes([bytes[4], bytes[5], bytes[6], bytes[7]]),
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub version: u8,
+    pub command: Command,
+    pub stream_id: u32,
+    pub data: Bytes,
+}
+
+impl Frame {
+    pub fn new(command: Command, stream_id: u32) -> Self {
+        Self::with_data(command, stream_id, Bytes::new())
+    }
+
+    pub fn with_data(command: Command, stream_id: u32, data: Bytes) -> Self {
+        Self {
+            version: PROTOCOL_VERSION,
+            command,
+            stream_id,
+            data,
+        }
+    }
+
+    /// Serializes the header and data of the frame. The data must fit the 16 bit length.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_SIZE + self.data.len());
+        bytes.push(self.version);
+        bytes.push(self.command as u8);
+        bytes.extend_from_slice(&(self.data.len() as u16).to_le_bytes());
+        bytes.extend_from_slice(&self.stream_id.to_le_bytes());
+        bytes.extend_from_slice(&self.data);
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_the_header_little_endian() {
+        let frame = Frame::with_data(Command::Psh, 0x0403_0201, Bytes::from_static(b"abc"));
+
+        assert_eq!(
+            frame.encode(),
+            [1, 2, 3, 0, 0x01, 0x02, 0x03, 0x04, b'a', b'b', b'c']
+        );
+        assert_eq!(
+            Frame::new(Command::Nop, 0).encode(),
+            [1, 3, 0, 0, 0, 0, 0, 0]
+        );
+    }
+
+    #[test]
+    fn decodes_encoded_headers() {
+        let data = Bytes::from(vec![0; 300]);
+        let bytes = Frame::with_data(Command::Syn, 7, data).encode();
+
+        assert_eq!(
+            Header::decode(bytes[..HEADER_SIZE].try_into().unwrap()).unwrap(),
+            Header {
+                version: PROTOCOL_VERSION,
+                command: Command::Syn,
+                length: 300,
+                stream_id: 7,
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_commands() {
+        assert!(Header::decode(&[1, 5, 0, 0, 0, 0, 0, 0]).is_err());
+        assert_eq!(Command::try_from(4).unwrap(), Command::Upd);
+    }
+}
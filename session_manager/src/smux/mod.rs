@@ -0,0 +1,4 @@
+/// Smux package implements the smux stream multiplexing protocol (version 1) which the agent uses
+/// to carry several port forwarding connections over one data channel.
+pub mod frame;
+pub mod mux;
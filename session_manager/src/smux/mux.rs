@@ -0,0 +1,548 @@
+use crate::smux::frame::{Command, Frame, Header, HEADER_SIZE, PROTOCOL_VERSION};
+use anyhow::{anyhow, bail, Result};
+use bytes::Bytes;
+use log::{debug, warn};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::mpsc::{self, UnboundedReceiver, UnboundedSender};
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use tokio::time;
+
+/// Number of frames queued for the transport before writers have to wait.
+const FRAME_QUEUE_SIZE: usize = 64;
+
+/// Configuration of a multiplexer, the defaults match the smux defaults used by the agent.
+#[derive(Debug, Clone)]
+pub struct MuxConfig {
+    /// Disables sending keepalive frames and closing the multiplexer when the peer is silent.
+    pub keep_alive_disabled: bool,
+    pub keep_alive_interval: Duration,
+    pub keep_alive_timeout: Duration,
+    /// Maximum data carried by a single frame, at most 65535 bytes.
+    pub max_frame_size: usize,
+}
+
+impl Default for MuxConfig {
+    fn default() -> Self {
+        Self {
+            keep_alive_disabled: false,
+            keep_alive_interval: Duration::from_secs(10),
+            keep_alive_timeout: Duration::from_secs(30),
+            max_frame_size: 32768,
+        }
+    }
+}
+
+/// State shared by the multiplexer, its streams and its background tasks.
+struct Shared {
+    streams: Mutex<HashMap<u32, UnboundedSender<Bytes>>>,
+    frames: mpsc::Sender<Frame>,
+    closed: watch::Sender<bool>,
+    /// Set whenever a frame is received, used to detect a silent peer.
+    data_ready: AtomicBool,
+}
+
+impl Shared {
+    async fn send(&self, frame: Frame) -> Result<()> {
+        if *self.closed.borrow() {
+            bail!("smux session is closed");
+        }
+
+        self.frames
+            .send(frame)
+            .await
+            .map_err(|_| anyhow!("smux session is closed"))
+    }
+
+    async fn wait_closed(&self) {
+        let mut closed = self.closed.subscribe();
+        let _ = closed.wait_for(|closed| *closed).await;
+    }
+
+    /// Closes the multiplexer, ending every stream.
+    fn close(&self) {
+        self.closed.send_replace(true);
+        self.streams.lock().unwrap().clear();
+    }
+}
+
+/// Multiplexes streams over a single transport, such as the data channel of a port session.
+pub struct Mux {
+    shared: Arc<Shared>,
+    config: MuxConfig,
+    next_stream_id: AtomicU32,
+    accepted: tokio::sync::Mutex<UnboundedReceiver<MuxStream>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Mux {
+    /// Creates the client side of a multiplexer, which opens streams with odd ids.
+    pub fn client<T>(transport: T, config: MuxConfig) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::new(transport, config, 1)
+    }
+
+    /// Creates the server side of a multiplexer, which opens streams with even ids.
+    pub fn server<T>(transport: T, config: MuxConfig) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        Self::new(transport, config, 0)
+    }
+
+    fn new<T>(transport: T, config: MuxConfig, first_stream_id: u32) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + 'static,
+    {
+        let (reader, writer) = tokio::io::split(transport);
+        let (frames, frame_receiver) = mpsc::channel(FRAME_QUEUE_SIZE);
+        let (closed, _) = watch::channel(false);
+        let (accept_sender, accepted) = mpsc::unbounded_channel();
+
+        let shared = Arc::new(Shared {
+            streams: Mutex::new(HashMap::new()),
+            frames,
+            closed,
+            data_ready: AtomicBool::new(false),
+        });
+
+        let mut tasks = vec![
+            tokio::spawn(write_loop(writer, frame_receiver, Arc::clone(&shared))),
+            tokio::spawn(read_loop(
+                reader,
+                Arc::clone(&shared),
+                accept_sender,
+                config.max_frame_size,
+            )),
+        ];
+
+        if !config.keep_alive_disabled {
+            tasks.push(tokio::spawn(keep_alive(
+                Arc::clone(&shared),
+                config.keep_alive_interval,
+                config.keep_alive_timeout,
+            )));
+        }
+
+        Self {
+            shared,
+            config,
+            next_stream_id: AtomicU32::new(first_stream_id),
+            accepted: tokio::sync::Mutex::new(accepted),
+            tasks,
+        }
+    }
+
+    /// Opens a new stream to the peer.
+    pub async fn open_stream(&self) -> Result<MuxStream> {
+        let stream_id = self.next_stream_id.fetch_add(2, Ordering::SeqCst) + 2;
+        let (sender, receiver) = mpsc::unbounded_channel();
+        self.shared
+            .streams
+            .lock()
+            .unwrap()
+            .insert(stream_id, sender);
+
+        self.shared
+            .send(Frame::new(Command::Syn, stream_id))
+            .await?;
+
+        Ok(MuxStream::new(
+            stream_id,
+            receiver,
+            Arc::clone(&self.shared),
+            self.config.max_frame_size,
+        ))
+    }
+
+    /// Waits for the peer to open a stream, returns None once the multiplexer is closed.
+    pub async fn accept_stream(&self) -> Option<MuxStream> {
+        self.accepted.lock().await.recv().await
+    }
+
+    pub fn is_closed(&self) -> bool {
+        *self.shared.closed.borrow()
+    }
+
+    /// Waits until the multiplexer is closed, either locally, by a transport error or by the
+    /// keepalive timing out.
+    pub async fn closed(&self) {
+        self.shared.wait_closed().await;
+    }
+
+    pub fn close(&self) {
+        self.shared.close();
+    }
+}
+
+impl Drop for Mux {
+    fn drop(&mut self) {
+        self.shared.close();
+        for task in &self.tasks {
+            task.abort();
+        }
+    }
+}
+
+/// Stream multiplexed with other streams over the transport of a [`Mux`].
+pub struct MuxStream {
+    id: u32,
+    incoming: UnboundedReceiver<Bytes>,
+    shared: Arc<Shared>,
+    max_frame_size: usize,
+    closed: bool,
+}
+
+impl MuxStream {
+    fn new(
+        id: u32,
+        incoming: UnboundedReceiver<Bytes>,
+        shared: Arc<Shared>,
+        max_frame_size: usize,
+    ) -> Self {
+        Self {
+            id,
+            incoming,
+            shared,
+            max_frame_size,
+            closed: false,
+        }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.id
+    }
+
+    /// Reads the next data sent by the peer, returns None once the peer closed the stream.
+    pub async fn read(&mut self) -> Option<Bytes> {
+        self.incoming.recv().await
+    }
+
+    /// Sends data to the peer, split into frames of at most the configured frame size.
+    pub async fn write(&self, data: &[u8]) -> Result<()> {
+        if self.closed {
+            bail!("smux stream {} is closed", self.id);
+        }
+
+        for chunk in data.chunks(self.max_frame_size) {
+            let frame = Frame::with_data(Command::Psh, self.id, Bytes::copy_from_slice(chunk));
+            self.shared.send(frame).await?;
+        }
+
+        Ok(())
+    }
+
+    /// Closes the stream, the peer reads the end of the stream.
+    pub async fn close(&mut self) -> Result<()> {
+        if self.closed {
+            return Ok(());
+        }
+
+        self.closed = true;
+        self.shared.streams.lock().unwrap().remove(&self.id);
+        self.shared.send(Frame::new(Command::Fin, self.id)).await
+    }
+}
+
+impl Drop for MuxStream {
+    fn drop(&mut self) {
+        if !self.closed {
+            self.shared.streams.lock().unwrap().remove(&self.id);
+            let _ = self
+                .shared
+                .frames
+                .try_send(Frame::new(Command::Fin, self.id));
+        }
+    }
+}
+
+async fn write_loop<W: AsyncWrite + Unpin>(
+    mut writer: W,
+    mut frames: mpsc::Receiver<Frame>,
+    shared: Arc<Shared>,
+) {
+    loop {
+        tokio::select! {
+            frame = frames.recv() => {
+                let Some(frame) = frame else {
+                    break;
+                };
+
+                let result = async {
+                    writer.write_all(&frame.encode()).await?;
+                    writer.flush().await
+                }
+                .await;
+
+                if let Err(e) = result {
+                    warn!("Failed to write smux frame: {}", e);
+                    break;
+                }
+            }
+            () = shared.wait_closed() => break,
+        }
+    }
+
+    shared.close();
+}
+
+async fn read_loop<R: AsyncRead + Unpin>(
+    mut reader: R,
+    shared: Arc<Shared>,
+    accept_sender: UnboundedSender<MuxStream>,
+    max_frame_size: usize,
+) {
+    let result: Result<()> = async {
+        loop {
+            let mut header = [0u8; HEADER_SIZE];
+            reader.read_exact(&mut header).await?;
+            let header = Header::decode(&header)?;
+
+            if header.version != PROTOCOL_VERSION {
+                bail!("Unsupported smux version {}", header.version);
+            }
+
+            let mut data = vec![0; header.length as usize];
+            reader.read_exact(&mut data).await?;
+            shared.data_ready.store(true, Ordering::SeqCst);
+
+            match header.command {
+                Command::Syn => {
+                    let mut streams = shared.streams.lock().unwrap();
+                    if streams.contains_key(&header.stream_id) {
+                        continue;
+                    }
+
+                    let (sender, receiver) = mpsc::unbounded_channel();
+                    streams.insert(header.stream_id, sender);
+                    drop(streams);
+
+                    let stream = MuxStream::new(
+                        header.stream_id,
+                        receiver,
+                        Arc::clone(&shared),
+                        max_frame_size,
+                    );
+                    let _ = accept_sender.send(stream);
+                }
+                Command::Fin => {
+                    // Dropping the sender ends the stream once its data was read.
+                    shared.streams.lock().unwrap().remove(&header.stream_id);
+                }
+                Command::Psh => {
+                    if let Some(stream) = shared.streams.lock().unwrap().get(&header.stream_id) {
+                        let _ = stream.send(Bytes::from(data));
+                    }
+                }
+                Command::Nop | Command::Upd => {}
+            }
+        }
+    }
+    .await;
+
+    if let Err(e) = result {
+        debug!("smux session ended: {}", e);
+    }
+
+    shared.close();
+}
+
+async fn keep_alive(shared: Arc<Shared>, interval: Duration, timeout: Duration) {
+    let mut ping = time::interval_at(time::Instant::now() + interval, interval);
+    let mut check = time::interval_at(time::Instant::now() + timeout, timeout);
+
+    loop {
+        tokio::select! {
+            _ = ping.tick() => {
+                if shared.send(Frame::new(Command::Nop, 0)).await.is_err() {
+                    break;
+                }
+            }
+            _ = check.tick() => {
+                if !shared.data_ready.swap(false, Ordering::SeqCst) {
+                    warn!("smux session timed out as the peer sent nothing for {:?}", timeout);
+                    shared.close();
+                    break;
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::io::DuplexStream;
+
+    const TIMEOUT: Duration = Duration::from_secs(5);
+
+    fn without_keep_alive() -> MuxConfig {
+        MuxConfig {
+            keep_alive_disabled: true,
+            ..Default::default()
+        }
+    }
+
+    fn pair(config: MuxConfig) -> (Mux, Mux) {
+        let (client, server) = tokio::io::duplex(1 << 16);
+        (
+            Mux::client(client, config.clone()),
+            Mux::server(server, config),
+        )
+    }
+
+    async fn read_frame(transport: &mut DuplexStream) -> Frame {
+        let mut header = [0u8; HEADER_SIZE];
+        transport.read_exact(&mut header).await.unwrap();
+        let header = Header::decode(&header).unwrap();
+
+        let mut data = vec![0; header.length as usize];
+        transport.read_exact(&mut data).await.unwrap();
+
+        Frame {
+            version: header.version,
+            command: header.command,
+            stream_id: header.stream_id,
+            data: Bytes::from(data),
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_streams_with_ids_of_its_side() {
+        let (client, server) = pair(without_keep_alive());
+
+        for expected in [3, 5, 7] {
+            assert_eq!(client.open_stream().await.unwrap().id(), expected);
+        }
+        for expected in [2, 4] {
+            assert_eq!(server.open_stream().await.unwrap().id(), expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn carries_data_both_ways_until_closed() {
+        let (client, server) = pair(without_keep_alive());
+
+        let mut outgoing = client.open_stream().await.unwrap();
+        outgoing.write(b"ping").await.unwrap();
+
+        let mut incoming = time::timeout(TIMEOUT, server.accept_stream())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(incoming.id(), outgoing.id());
+        assert_eq!(incoming.read().await.unwrap(), "ping");
+
+        incoming.write(b"pong").await.unwrap();
+        assert_eq!(outgoing.read().await.unwrap(), "pong");
+
+        outgoing.close().await.unwrap();
+        assert_eq!(time::timeout(TIMEOUT, incoming.read()).await.unwrap(), None);
+        assert!(outgoing.write(b"late").await.is_err());
+    }
+
+    #[tokio::test]
+    async fn keeps_streams_apart() {
+        let (client, server) = pair(without_keep_alive());
+
+        let first = client.open_stream().await.unwrap();
+        let second = client.open_stream().await.unwrap();
+        second.write(b"second").await.unwrap();
+        first.write(b"first").await.unwrap();
+
+        let mut first_accepted = server.accept_stream().await.unwrap();
+        let mut second_accepted = server.accept_stream().await.unwrap();
+        assert_eq!(first_accepted.id(), 3);
+        assert_eq!(second_accepted.id(), 5);
+        assert_eq!(first_accepted.read().await.unwrap(), "first");
+        assert_eq!(second_accepted.read().await.unwrap(), "second");
+    }
+
+    #[tokio::test]
+    async fn sends_fin_when_a_stream_is_dropped() {
+        let (client, mut transport) = tokio::io::duplex(1 << 16);
+        let mux = Mux::client(client, without_keep_alive());
+
+        drop(mux.open_stream().await.unwrap());
+
+        assert_eq!(
+            read_frame(&mut transport).await,
+            Frame::new(Command::Syn, 3)
+        );
+        assert_eq!(
+            read_frame(&mut transport).await,
+            Frame::new(Command::Fin, 3)
+        );
+    }
+
+    #[tokio::test]
+    async fn splits_writes_at_the_max_frame_size() {
+        let (client, mut transport) = tokio::io::duplex(1 << 16);
+        let config = MuxConfig {
+            max_frame_size: 4,
+            ..without_keep_alive()
+        };
+        let mux = Mux::client(client, config);
+
+        let stream = mux.open_stream().await.unwrap();
+        stream.write(b"0123456789").await.unwrap();
+
+        assert_eq!(
+            read_frame(&mut transport).await,
+            Frame::new(Command::Syn, 3)
+        );
+        for chunk in ["0123", "4567", "89"] {
+            assert_eq!(
+                read_frame(&mut transport).await,
+                Frame::with_data(Command::Psh, 3, Bytes::from(chunk))
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn closes_when_the_peer_is_silent() {
+        let (client, _transport) = tokio::io::duplex(1 << 16);
+        let config = MuxConfig {
+            keep_alive_interval: Duration::from_secs(3600),
+            keep_alive_timeout: Duration::from_millis(50),
+            ..Default::default()
+        };
+        let mux = Mux::client(client, config);
+
+        time::timeout(TIMEOUT, mux.closed()).await.unwrap();
+        assert!(mux.is_closed());
+        assert!(mux.open_stream().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn stays_open_while_the_peer_sends_keepalives() {
+        let (client, server) = pair(MuxConfig {
+            keep_alive_interval: Duration::from_millis(20),
+            keep_alive_timeout: Duration::from_millis(100),
+            ..Default::default()
+        });
+
+        time::sleep(Duration::from_millis(350)).await;
+        assert!(!client.is_closed());
+        assert!(!server.is_closed());
+    }
+
+    #[tokio::test]
+    async fn closes_on_an_unsupported_version() {
+        let (client, mut transport) = tokio::io::duplex(1 << 16);
+        let mux = Mux::client(client, without_keep_alive());
+
+        let mut frame = Frame::new(Command::Syn, 2);
+        frame.version = 2;
+        transport.write_all(&frame.encode()).await.unwrap();
+
+        time::timeout(TIMEOUT, mux.closed()).await.unwrap();
+        assert!(mux.accept_stream().await.is_none());
+    }
+}
@@ -0,0 +1,3 @@
+/// Terminal package restores the terminal on every way the process can end.
+pub mod signal;
+pub mod terminal_guard;
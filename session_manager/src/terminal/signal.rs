@@ -0,0 +1,107 @@
+use crate::terminal::terminal_guard::restore_terminal;
+use log::{debug, warn};
+use std::fmt;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+/// Number of sessions terminating themselves on signals.
+static HANDLERS: AtomicUsize = AtomicUsize::new(0);
+
+/// Signal asking the process to end.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ShutdownSignal {
+    Interrupt,
+    Terminate,
+    Hangup,
+}
+
+impl ShutdownSignal {
+    /// Returns the exit code of a shell for a process killed by the signal.
+    pub fn exit_code(self) -> i32 {
+        match self {
+            ShutdownSignal::Interrupt => 130,
+            ShutdownSignal::Terminate => 143,
+            ShutdownSignal::Hangup => 129,
+        }
+    }
+}
+
+impl fmt::Display for ShutdownSignal {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            ShutdownSignal::Interrupt => "SIGINT",
+            ShutdownSignal::Terminate => "SIGTERM",
+            ShutdownSignal::Hangup => "SIGHUP",
+        })
+    }
+}
+
+/// Marks signals as handled by a session until dropped.
+#[must_use]
+pub struct SignalHandler {
+    _private: (),
+}
+
+impl SignalHandler {
+    pub fn register() -> Self {
+        HANDLERS.fetch_add(1, Ordering::SeqCst);
+
+        Self { _private: () }
+    }
+}
+
+impl Drop for SignalHandler {
+    fn drop(&mut self) {
+        HANDLERS.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Waits for a signal asking the process to end.
+#[cfg(unix)]
+pub async fn shutdown_signal() -> ShutdownSignal {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let (Ok(mut terminate), Ok(mut hangup)) = (
+        signal(SignalKind::terminate()),
+        signal(SignalKind::hangup()),
+    ) else {
+        warn!("Failed to listen for SIGTERM and SIGHUP");
+        return ctrl_c().await;
+    };
+
+    tokio::select! {
+        signal = ctrl_c() => signal,
+        Some(()) = terminate.recv() => ShutdownSignal::Terminate,
+        Some(()) = hangup.recv() => ShutdownSignal::Hangup,
+    }
+}
+
+/// Waits for a signal asking the process to end.
+#[cfg(not(unix))]
+pub async fn shutdown_signal() -> ShutdownSignal {
+    ctrl_c().await
+}
+
+/// Ends the process on signals no session handles, e.g. while a target is picked, restoring the
+/// terminal first. Listening replaces the default handling, so this runs for the whole process.
+pub async fn exit_on_unhandled_signals() {
+    loop {
+        let signal = shutdown_signal().await;
+        if HANDLERS.load(Ordering::SeqCst) > 0 {
+            debug!("{} handled by the session", signal);
+            continue;
+        }
+
+        restore_terminal();
+        std::process::exit(signal.exit_code());
+    }
+}
+
+/// Waits for Ctrl-C, or never completes when it cannot be listened for.
+async fn ctrl_c() -> ShutdownSignal {
+    if let Err(e) = tokio::signal::ctrl_c().await {
+        warn!("Failed to listen for Ctrl-C: {}", e);
+        std::future::pending::<()>().await;
+    }
+
+    ShutdownSignal::Interrupt
+}
@@ -0,0 +1,158 @@
+use anyhow::Result;
+use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
+use crossterm::{cursor, execute};
+use log::warn;
+use std::io::{self, IsTerminal, Write};
+use std::panic;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// What was changed in the terminal, process wide so the hooks can restore it without a guard.
+static RAW_MODE: AtomicBool = AtomicBool::new(false);
+static ALTERNATE_SCREEN: AtomicBool = AtomicBool::new(false);
+static CURSOR_HIDDEN: AtomicBool = AtomicBool::new(false);
+
+/// Puts the terminal into raw mode until dropped, restoring cooked mode, the main screen and
+/// the cursor however the guard goes out of scope, including `?` and panics that unwind.
+#[must_use]
+pub struct TerminalGuard {
+    _private: (),
+}
+
+impl TerminalGuard {
+    /// Enables raw mode, the terminal passes every key press on unprocessed.
+    pub fn raw_mode() -> Result<Self> {
+        terminal::enable_raw_mode()?;
+        RAW_MODE.store(true, Ordering::SeqCst);
+
+        Ok(Self { _private: () })
+    }
+
+    /// Enables raw mode and switches to the alternate screen, for full screen interfaces drawn
+    /// on stderr.
+    pub fn alternate_screen() -> Result<Self> {
+        let guard = Self::raw_mode()?;
+        execute!(io::stderr(), EnterAlternateScreen)?;
+        ALTERNATE_SCREEN.store(true, Ordering::SeqCst);
+
+        Ok(guard)
+    }
+
+    /// Follows the alternate screen and cursor visibility requested by the remote application in
+    /// its output, so they are restored when the session ends while e.g. vim is running.
+    pub fn track_output(&self, output: &[u8]) {
+        track_mode(
+            output,
+            &[b"\x1b[?1049h", b"\x1b[?1047h", b"\x1b[?47h"],
+            &[b"\x1b[?1049l", b"\x1b[?1047l", b"\x1b[?47l"],
+            &ALTERNATE_SCREEN,
+        );
+        track_mode(output, &[b"\x1b[?25l"], &[b"\x1b[?25h"], &CURSOR_HIDDEN);
+    }
+}
+
+impl Drop for TerminalGuard {
+    fn drop(&mut self) {
+        restore_terminal();
+    }
+}
+
+/// Restores cooked mode, leaves the alternate screen and shows the cursor, as far as they were
+/// changed. Only writes to a terminal and can be called any number of times, from hooks too.
+pub fn restore_terminal() {
+    let mut out: Box<dyn Write> = if io::stdout().is_terminal() {
+        Box::new(io::stdout())
+    } else {
+        Box::new(io::stderr())
+    };
+
+    restore(&mut out);
+}
+
+fn restore(out: &mut impl Write) {
+    if ALTERNATE_SCREEN.swap(false, Ordering::SeqCst) {
+        let _ = execute!(out, LeaveAlternateScreen);
+    }
+    if CURSOR_HIDDEN.swap(false, Ordering::SeqCst) {
+        let _ = execute!(out, cursor::Show);
+    }
+    if RAW_MODE.swap(false, Ordering::SeqCst) {
+        if let Err(e) = terminal::disable_raw_mode() {
+            warn!("Failed to restore the terminal: {}", e);
+        }
+    }
+}
+
+/// Restores the terminal before the panic message is printed, which raw mode would mangle and
+/// the alternate screen would hide.
+pub fn install_panic_hook() {
+    let hook = panic::take_hook();
+    panic::set_hook(Box::new(move |info| {
+        restore_terminal();
+        hook(info);
+    }));
+}
+
+/// Sets the mode when the last of the sequences in the output enables it, resets it when the
+/// last one disables it.
+fn track_mode(output: &[u8], set: &[&[u8]], reset: &[&[u8]], mode: &AtomicBool) {
+    let last = |sequences: &[&[u8]]| {
+        sequences
+            .iter()
+            .filter_map(|sequence| output.windows(sequence.len()).rposition(|w| w == *sequence))
+            .max()
+    };
+
+    match (last(set), last(reset)) {
+        (Some(set), Some(reset)) => mode.store(set > reset, Ordering::SeqCst),
+        (Some(_), None) => mode.store(true, Ordering::SeqCst),
+        (None, Some(_)) => mode.store(false, Ordering::SeqCst),
+        (None, None) => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_the_last_sequence_of_the_output() {
+        let mode = AtomicBool::new(false);
+        let track = |output: &[u8]| {
+            track_mode(
+                output,
+                &[b"\x1b[?1049h", b"\x1b[?47h"],
+                &[b"\x1b[?1049l", b"\x1b[?47l"],
+                &mode,
+            );
+            mode.load(Ordering::SeqCst)
+        };
+
+        assert!(track(b"\x1b[?47h"));
+        assert!(track(b"plain output"));
+        assert!(!track(b"\x1b[?1049h\x1b[?47l"));
+        assert!(!track(b""));
+        assert!(track(b"\x1b[?1049l vim \x1b[?1049h"));
+    }
+
+    #[test]
+    fn restores_what_the_remote_application_changed_once() {
+        let guard = TerminalGuard { _private: () };
+        guard.track_output(b"\x1b[?1049h\x1b[?25l");
+        assert!(ALTERNATE_SCREEN.load(Ordering::SeqCst));
+        assert!(CURSOR_HIDDEN.load(Ordering::SeqCst));
+
+        let mut restored = Vec::new();
+        restore(&mut restored);
+
+        let mut expected = Vec::new();
+        execute!(expected, LeaveAlternateScreen, cursor::Show).unwrap();
+        assert_eq!(restored, expected);
+        assert!(!ALTERNATE_SCREEN.load(Ordering::SeqCst));
+        assert!(!CURSOR_HIDDEN.load(Ordering::SeqCst));
+
+        // Hooks restoring again after the guard write nothing more.
+        let mut restored = Vec::new();
+        restore(&mut restored);
+        assert!(restored.is_empty());
+    }
+}
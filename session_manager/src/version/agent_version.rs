@@ -0,0 +1,171 @@
+use crate::config::config::{
+    TCP_MULTIPLEXING_SUPPORTED_AFTER_THIS_AGENT_VERSION,
+    TCP_MULTIPLEXING_WITH_SMUX_KEEP_ALIVE_DISABLED_AFTER_THIS_AGENT_VERSION,
+    TERMINATE_SESSION_FLAG_SUPPORTED_AFTER_THIS_AGENT_VERSION,
+};
+use std::fmt;
+use std::str::FromStr;
+use thiserror::Error;
+
+/// Version of the SSM agent, such as `3.1.1511.0`, ordered by its numeric parts.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct AgentVersion([u32; 4]);
+
+#[derive(Error, Debug, PartialEq)]
+pub enum AgentVersionError {
+    #[error("Invalid agent version {0:?}")]
+    Invalid(String),
+}
+
+impl AgentVersion {
+    pub const fn new(major: u32, minor: u32, build: u32, revision: u32) -> Self {
+        Self([major, minor, build, revision])
+    }
+
+    /// Returns the features the agent supports.
+    pub fn capabilities(&self) -> AgentCapabilities {
+        AgentCapabilities::from(*self)
+    }
+}
+
+impl FromStr for AgentVersion {
+    type Err = AgentVersionError;
+
+    /// Parses up to four dotted numbers, missing trailing parts are zero.
+    fn from_str(version: &str) -> Result<Self, Self::Err> {
+        let invalid = || AgentVersionError::Invalid(version.to_string());
+
+        let mut parts = [0; 4];
+        for (index, part) in version.trim().split('.').enumerate() {
+            let slot = parts.get_mut(index).ok_or_else(invalid)?;
+            *slot = part.parse().map_err(|_| invalid())?;
+        }
+
+        Ok(Self(parts))
+    }
+}
+
+impl fmt::Display for AgentVersion {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let [major, minor, build, revision] = self.0;
+        write!(f, "{}.{}.{}.{}", major, minor, build, revision)
+    }
+}
+
+/// Features of the session protocol that depend on the version of the agent.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+pub struct AgentCapabilities {
+    /// The agent ends the session when the client sends the `TerminateSession` flag.
+    pub terminate_session_flag: bool,
+
+    /// The agent multiplexes port forwarding connections with smux.
+    pub tcp_multiplexing: bool,
+
+    /// The smux session of the agent sends and expects keepalive frames.
+    pub smux_keep_alive: bool,
+}
+
+impl From<AgentVersion> for AgentCapabilities {
+    fn from(version: AgentVersion) -> Self {
+        Self {
+            terminate_session_flag: version
+                > TERMINATE_SESSION_FLAG_SUPPORTED_AFTER_THIS_AGENT_VERSION,
+            tcp_multiplexing: version > TCP_MULTIPLEXING_SUPPORTED_AFTER_THIS_AGENT_VERSION,
+            smux_keep_alive: version
+                <= TCP_MULTIPLEXING_WITH_SMUX_KEEP_ALIVE_DISABLED_AFTER_THIS_AGENT_VERSION,
+        }
+    }
+}
+
+/// Feature of the session protocol older agents lack, sessions fall back to doing without it.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum AgentFeature {
+    /// Ending the session with the `TerminateSession` flag.
+    TerminateSessionFlag,
+
+    /// Multiplexing port forwarding connections with smux.
+    TcpMultiplexing,
+}
+
+impl AgentFeature {
+    /// Describes what the session does without the feature.
+    pub fn fallback(&self) -> &'static str {
+        match self {
+            AgentFeature::TerminateSessionFlag => "the session is terminated through SSM only",
+            AgentFeature::TcpMultiplexing => "connections are forwarded one at a time",
+        }
+    }
+}
+
+impl fmt::Display for AgentFeature {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AgentFeature::TerminateSessionFlag => write!(f, "the TerminateSession flag"),
+            AgentFeature::TcpMultiplexing => write!(f, "multiplexing"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(version: &str) -> AgentVersion {
+        version.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_dotted_versions() {
+        assert_eq!(version("3.1.1511.0"), AgentVersion::new(3, 1, 1511, 0));
+        assert_eq!(version(" 2.3.722.0\n"), AgentVersion::new(2, 3, 722, 0));
+        assert_eq!(version("3.2"), AgentVersion::new(3, 2, 0, 0));
+        assert_eq!(version("3.1.1511.0").to_string(), "3.1.1511.0");
+    }
+
+    #[test]
+    fn rejects_invalid_versions() {
+        for invalid in ["", "3.1.x.0", "3.1.1511.0.1", "3..1", "-3.1", "latest"] {
+            assert_eq!(
+                invalid.parse::<AgentVersion>(),
+                Err(AgentVersionError::Invalid(invalid.to_string())),
+                "{:?}",
+                invalid
+            );
+        }
+    }
+
+    #[test]
+    fn orders_numerically() {
+        assert!(version("3.1.1511.0") > version("3.1.200.0"));
+        assert!(version("3.10.0.0") > version("3.9.9999.0"));
+        assert!(version("3.1.1511.1") > version("3.1.1511.0"));
+        assert!(version("3.1") < version("3.1.0.1"));
+        assert_eq!(version("3.1"), version("3.1.0.0"));
+    }
+
+    #[test]
+    fn derives_capabilities_at_the_boundaries() {
+        let capabilities = |v: &str| AgentCapabilities::from(version(v));
+
+        assert!(!capabilities("2.3.722.0").terminate_session_flag);
+        assert!(capabilities("2.3.722.1").terminate_session_flag);
+
+        assert!(!capabilities("3.0.196.0").tcp_multiplexing);
+        assert!(capabilities("3.0.196.1").tcp_multiplexing);
+
+        assert!(capabilities("3.1.1511.0").smux_keep_alive);
+        assert!(!capabilities("3.1.1511.1").smux_keep_alive);
+    }
+
+    #[test]
+    fn derives_capabilities_of_the_default_version() {
+        assert_eq!(
+            AgentVersion::default().capabilities(),
+            AgentCapabilities {
+                terminate_session_flag: false,
+                tcp_multiplexing: false,
+                smux_keep_alive: true,
+            }
+        );
+    }
+}
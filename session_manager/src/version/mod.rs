@@ -0,0 +1,2 @@
+/// Version package parses agent versions and derives the features the agent supports from them.
+pub mod agent_version;